@@ -0,0 +1,529 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use crossterm::{cursor::MoveTo, queue, terminal};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use log::warn;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use ratatui::widgets::Widget;
+use serde::Deserialize;
+
+use crate::{metadata, waveform};
+
+/// Image file names looked for next to a track when it has no embedded cover
+const COVER_NAMES: &[&str] = &["cover", "folder", "front", "album"];
+const COVER_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+/// Largest edge kept of a decoded cover, in pixels
+const MAX_SOURCE_SIZE: u32 = 600;
+
+/// Number of covers and rendered images kept in memory
+const CACHE_LIMIT: usize = 32;
+
+/// Cell size assumed when the terminal doesn't report its pixel size
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+/// Bytes of base64 data per kitty graphics escape sequence
+const KITTY_CHUNK: usize = 4096;
+
+/// Image id used for the cover in the kitty graphics protocol
+const KITTY_IMAGE_ID: u32 = 1;
+
+/// How album art is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtMode {
+    /// Don't show album art
+    Off,
+    /// Use a graphics protocol when the terminal advertises one, otherwise blocks
+    #[default]
+    Auto,
+    /// Half-block characters, in truecolor or 256 colors
+    Blocks,
+    /// The kitty graphics protocol
+    Kitty,
+    /// Sixel graphics
+    Sixel,
+}
+
+/// Drawing method after resolving the mode against the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Renderer {
+    TrueColor,
+    Indexed,
+    Kitty,
+    Sixel,
+}
+
+impl Renderer {
+    /// Pick a renderer from the environment variables terminals set
+    fn detect(mode: ArtMode) -> Option<Self> {
+        let var = |name: &str| env::var(name).unwrap_or_default().to_lowercase();
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+
+        let kitty = env::var_os("KITTY_WINDOW_ID").is_some()
+            || term.contains("kitty")
+            || term.contains("ghostty")
+            || program == "wezterm"
+            || program == "ghostty";
+        let sixel =
+            term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm");
+        let blocks = if matches!(var("COLORTERM").as_str(), "truecolor" | "24bit") {
+            Self::TrueColor
+        } else {
+            Self::Indexed
+        };
+
+        match mode {
+            ArtMode::Off => None,
+            ArtMode::Auto if kitty => Some(Self::Kitty),
+            ArtMode::Auto if sixel => Some(Self::Sixel),
+            ArtMode::Auto | ArtMode::Blocks => Some(blocks),
+            ArtMode::Kitty => Some(Self::Kitty),
+            ArtMode::Sixel => Some(Self::Sixel),
+        }
+    }
+}
+
+/// A cover rendered for a specific area
+pub enum Art {
+    /// Half-block cells, each holding a top and a bottom pixel
+    Blocks {
+        width: u16,
+        cells: Vec<(Color, Color)>,
+    },
+    /// Escape sequence drawing the image over `cols` x `rows` cells
+    Graphics { cols: u16, rows: u16, data: String },
+}
+
+impl Art {
+    /// Size of the rendered image in cells
+    pub fn size(&self) -> (u16, u16) {
+        match self {
+            Art::Blocks { width, cells } => {
+                let rows = cells.len() / usize::from((*width).max(1));
+                (*width, rows as u16)
+            }
+            Art::Graphics { cols, rows, .. } => (*cols, *rows),
+        }
+    }
+}
+
+/// A render request: the track and the largest area the art may take
+type Request = (PathBuf, u16, u16);
+
+/// State of a render in the store
+#[derive(Clone)]
+enum Entry {
+    Pending,
+    Ready(Arc<Art>),
+    Missing,
+}
+
+/// Finds, decodes and scales album art on a background thread, caching the results
+pub struct ArtStore {
+    entries: Arc<Mutex<HashMap<Request, Entry>>>,
+    requests: Sender<Request>,
+    renderer: Renderer,
+    /// Graphics placed during the current draw, and the ones on screen
+    placed: Option<(Rect, Arc<Art>)>,
+    shown: Option<(Rect, Arc<Art>)>,
+}
+
+impl ArtStore {
+    /// Create a store, or `None` when album art is switched off
+    pub fn new(mode: ArtMode) -> Option<Self> {
+        let renderer = Renderer::detect(mode)?;
+        let entries: Arc<Mutex<HashMap<Request, Entry>>> = Arc::default();
+        let (requests, receiver) = mpsc::channel::<Request>();
+
+        let worker_entries = entries.clone();
+        thread::spawn(move || {
+            let mut sources: HashMap<PathBuf, Option<Arc<DynamicImage>>> = HashMap::new();
+            for request in receiver {
+                let (path, cols, rows) = &request;
+
+                if sources.len() >= CACHE_LIMIT {
+                    sources.clear();
+                }
+                let source = sources
+                    .entry(path.clone())
+                    .or_insert_with(|| match load_cover(path) {
+                        Ok(cover) => cover.map(Arc::new),
+                        Err(e) => {
+                            warn!("Failed to load album art for {}: {}", path.display(), e);
+                            None
+                        }
+                    })
+                    .clone();
+
+                let entry = match source.map(|image| render(&image, *cols, *rows, renderer)) {
+                    Some(Ok(art)) => Entry::Ready(Arc::new(art)),
+                    Some(Err(e)) => {
+                        warn!("Failed to render album art for {}: {}", path.display(), e);
+                        Entry::Missing
+                    }
+                    None => Entry::Missing,
+                };
+                if let Ok(mut entries) = worker_entries.lock() {
+                    if entries.len() >= CACHE_LIMIT {
+                        entries.retain(|_, entry| matches!(entry, Entry::Pending));
+                    }
+                    entries.insert(request, entry);
+                }
+            }
+        });
+
+        Some(Self {
+            entries,
+            requests,
+            renderer,
+            placed: None,
+            shown: None,
+        })
+    }
+
+    /// Get a track's art fitted to an area, queueing it for rendering if needed
+    pub fn get(&self, path: &Path, cols: u16, rows: u16) -> Option<Arc<Art>> {
+        let key = (path.to_path_buf(), cols, rows);
+        let mut entries = self.entries.lock().ok()?;
+        match entries.get(&key) {
+            Some(Entry::Ready(art)) => Some(art.clone()),
+            Some(Entry::Pending | Entry::Missing) => None,
+            None => {
+                entries.insert(key.clone(), Entry::Pending);
+                let _ = self.requests.send(key);
+                None
+            }
+        }
+    }
+
+    /// Draw art into a frame buffer
+    ///
+    /// Graphics protocol images only reserve their cells here; [`ArtStore::flush`] draws them
+    /// once the frame has been written.
+    pub fn render(&mut self, art: &Arc<Art>, area: Rect, buf: &mut Buffer) {
+        match art.as_ref() {
+            Art::Blocks { .. } => ArtBlocks { art }.render(area, buf),
+            Art::Graphics { cols, rows, .. } => {
+                let area = area.intersection(Rect::new(area.x, area.y, *cols, *rows));
+                for y in area.top()..area.bottom() {
+                    for x in area.left()..area.right() {
+                        buf[(x, y)].set_skip(true);
+                    }
+                }
+                self.placed = Some((area, art.clone()));
+            }
+        }
+    }
+
+    /// Write, move or remove graphics protocol images after a frame is drawn
+    pub fn flush(&mut self) -> io::Result<()> {
+        let placed = self.placed.take();
+        let unchanged = match (&placed, &self.shown) {
+            (Some((a, art)), Some((b, shown))) => a == b && Arc::ptr_eq(art, shown),
+            (None, None) => true,
+            _ => false,
+        };
+        if unchanged {
+            return Ok(());
+        }
+
+        let mut out = io::stdout();
+        if self.renderer == Renderer::Kitty && self.shown.is_some() {
+            write!(out, "\x1b_Ga=d,d=i,i={},q=2\x1b\\", KITTY_IMAGE_ID)?;
+        }
+        if let Some((area, art)) = &placed
+            && let Art::Graphics { data, .. } = art.as_ref()
+        {
+            queue!(out, MoveTo(area.x, area.y))?;
+            out.write_all(data.as_bytes())?;
+        }
+        out.flush()?;
+
+        self.shown = placed;
+        Ok(())
+    }
+
+    /// Forget what is on screen, e.g. after the terminal was cleared
+    pub fn invalidate(&mut self) {
+        self.shown = None;
+    }
+}
+
+/// Half-block rendering of a cover
+struct ArtBlocks<'a> {
+    art: &'a Art,
+}
+
+impl Widget for ArtBlocks<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let Art::Blocks { width, cells } = self.art else {
+            return;
+        };
+        for (i, (top, bottom)) in cells.iter().enumerate() {
+            let x = (i % usize::from(*width)) as u16;
+            let y = (i / usize::from(*width)) as u16;
+            if x >= area.width || y >= area.height {
+                continue;
+            }
+            buf[(area.x + x, area.y + y)]
+                .set_char('▀')
+                .set_fg(*top)
+                .set_bg(*bottom);
+        }
+    }
+}
+
+/// Read the embedded cover, falling back to an image file in the track's directory
+fn load_cover(path: &Path) -> Result<Option<DynamicImage>> {
+    let data = match metadata::read_cover(path) {
+        Ok(Some(data)) => Some(data),
+        Ok(None) | Err(_) => match find_cover_file(path) {
+            Some(file) => Some(
+                fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?,
+            ),
+            None => None,
+        },
+    };
+    let Some(data) = data else {
+        return Ok(None);
+    };
+
+    let image = image::load_from_memory(&data).context("Unsupported image")?;
+    Ok(Some(image.thumbnail(MAX_SOURCE_SIZE, MAX_SOURCE_SIZE)))
+}
+
+/// A file holding a track's cover, for clients that take an image path rather than pixels
+///
+/// Embedded covers have no file of their own, so they are copied to the cache directory.
+pub fn cover_file(path: &Path) -> Option<PathBuf> {
+    let Ok(Some(data)) = metadata::read_cover(path) else {
+        return find_cover_file(path);
+    };
+    let extension = image::guess_format(&data).ok()?.extensions_str().first()?;
+    let dir = dirs::cache_dir()?.join("music_player").join("covers");
+    let file = dir.join(format!("{}.{}", waveform::cache_key(path)?, extension));
+    if !file.exists()
+        && let Err(e) = fs::create_dir_all(&dir).and_then(|()| fs::write(&file, &data))
+    {
+        warn!("Failed to write cover {}: {}", file.display(), e);
+        return None;
+    }
+    Some(file)
+}
+
+/// Find a `cover.jpg`, `folder.png` or similar file next to a track, ignoring case
+fn find_cover_file(path: &Path) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    files.sort();
+
+    COVER_NAMES.iter().find_map(|name| {
+        files
+            .iter()
+            .find(|file| {
+                let stem = file.file_stem().and_then(|s| s.to_str());
+                let ext = file.extension().and_then(|e| e.to_str());
+                stem.is_some_and(|s| s.eq_ignore_ascii_case(name))
+                    && ext
+                        .is_some_and(|e| COVER_EXTENSIONS.iter().any(|c| e.eq_ignore_ascii_case(c)))
+            })
+            .cloned()
+    })
+}
+
+/// Scale a cover to fit within `cols` x `rows` cells and render it
+fn render(image: &DynamicImage, cols: u16, rows: u16, renderer: Renderer) -> Result<Art> {
+    let cols = u32::from(cols.max(1));
+    let rows = u32::from(rows.max(1));
+
+    match renderer {
+        Renderer::TrueColor | Renderer::Indexed => {
+            // Each cell shows two square pixels stacked vertically
+            let scaled = image.resize(cols, rows * 2, FilterType::Triangle).to_rgb8();
+            let width = scaled.width();
+            let cells = (0..scaled.height().div_ceil(2))
+                .flat_map(|row| (0..width).map(move |x| (x, row * 2)))
+                .map(|(x, y)| {
+                    let top = scaled.get_pixel(x, y).0;
+                    let bottom = if y + 1 < scaled.height() {
+                        scaled.get_pixel(x, y + 1).0
+                    } else {
+                        [0, 0, 0]
+                    };
+                    (to_color(top, renderer), to_color(bottom, renderer))
+                })
+                .collect();
+            Ok(Art::Blocks {
+                width: width as u16,
+                cells,
+            })
+        }
+        Renderer::Kitty | Renderer::Sixel => {
+            let (cell_width, cell_height) = cell_size();
+            let scaled = image.resize(cols * cell_width, rows * cell_height, FilterType::Triangle);
+            let cols = scaled.width().div_ceil(cell_width).min(cols) as u16;
+            let rows = scaled.height().div_ceil(cell_height).min(rows) as u16;
+
+            let data = if renderer == Renderer::Kitty {
+                kitty_sequence(&scaled, cols, rows)?
+            } else {
+                sixel_sequence(&scaled.to_rgb8())
+            };
+            Ok(Art::Graphics { cols, rows, data })
+        }
+    }
+}
+
+/// Convert a pixel to a truecolor or nearest 256-color palette color
+fn to_color([r, g, b]: [u8; 3], renderer: Renderer) -> Color {
+    if renderer == Renderer::TrueColor {
+        return Color::Rgb(r, g, b);
+    }
+
+    // Compare the nearest 6x6x6 cube color with the nearest gray ramp step
+    let level = |v: u8| (u16::from(v) * 5 + 127) / 255;
+    let cube_value = |l: u16| if l == 0 { 0 } else { 55 + l * 40 };
+    let (lr, lg, lb) = (level(r), level(g), level(b));
+    let cube = [cube_value(lr), cube_value(lg), cube_value(lb)];
+
+    let average = (u16::from(r) + u16::from(g) + u16::from(b)) / 3;
+    let gray_step = (average.saturating_sub(3) / 10).min(23);
+    let gray = 8 + gray_step * 10;
+
+    let distance = |c: [u16; 3]| {
+        [r, g, b]
+            .iter()
+            .zip(c)
+            .map(|(v, c)| (i32::from(*v) - i32::from(c)).pow(2))
+            .sum::<i32>()
+    };
+    if distance([gray; 3]) < distance(cube) {
+        Color::Indexed(232 + gray_step as u8)
+    } else {
+        Color::Indexed(16 + (lr * 36 + lg * 6 + lb) as u8)
+    }
+}
+
+/// Pixel size of one terminal cell
+fn cell_size() -> (u32, u32) {
+    match terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => (
+            u32::from(size.width / size.columns).max(1),
+            u32::from(size.height / size.rows).max(1),
+        ),
+        _ => DEFAULT_CELL_SIZE,
+    }
+}
+
+/// Kitty graphics protocol sequence placing a PNG over `cols` x `rows` cells
+fn kitty_sequence(image: &DynamicImage, cols: u16, rows: u16) -> Result<String> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let encoded = BASE64.encode(png);
+
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut data = String::with_capacity(encoded.len() + chunks.len() * 32);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(i + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk)?;
+        if i == 0 {
+            // Quiet, and don't move the cursor so the rest of the frame isn't disturbed
+            data.push_str(&format!(
+                "\x1b_Ga=T,f=100,i={},c={},r={},C=1,q=2,m={};{}\x1b\\",
+                KITTY_IMAGE_ID, cols, rows, more, chunk
+            ));
+        } else {
+            data.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk));
+        }
+    }
+    Ok(data)
+}
+
+/// Sixel sequence for an image, quantized to the 6x6x6 color cube
+fn sixel_sequence(image: &RgbImage) -> String {
+    let (width, height) = image.dimensions();
+    let level = |v: u8| (u32::from(v) * 5 + 127) / 255;
+    let indexes: Vec<u8> = image
+        .pixels()
+        .map(|p| (level(p[0]) * 36 + level(p[1]) * 6 + level(p[2])) as u8)
+        .collect();
+
+    let mut data = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for i in 0..216u32 {
+        let percent = |l: u32| l * 100 / 5;
+        data.push_str(&format!(
+            "#{};2;{};{};{}",
+            i,
+            percent(i / 36),
+            percent(i / 6 % 6),
+            percent(i % 6)
+        ));
+    }
+
+    // Each band is six pixel rows, drawn once per color it contains
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+        let mut colors: Vec<u8> = (band..band + rows)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| indexes[(y * width + x) as usize])
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+
+        for (n, color) in colors.iter().enumerate() {
+            if n > 0 {
+                data.push('$');
+            }
+            data.push_str(&format!("#{}", color));
+
+            let mut run: Option<(char, usize)> = None;
+            for x in 0..width {
+                let bits = (0..rows)
+                    .filter(|row| indexes[((band + row) * width + x) as usize] == *color)
+                    .fold(0u8, |bits, row| bits | 1 << row);
+                let symbol = char::from(63 + bits);
+                run = match run {
+                    Some((c, count)) if c == symbol => Some((c, count + 1)),
+                    Some((c, count)) => {
+                        push_sixel_run(&mut data, c, count);
+                        Some((symbol, 1))
+                    }
+                    None => Some((symbol, 1)),
+                };
+            }
+            if let Some((c, count)) = run {
+                push_sixel_run(&mut data, c, count);
+            }
+        }
+        data.push('-');
+    }
+
+    data.push_str("\x1b\\");
+    data
+}
+
+fn push_sixel_run(data: &mut String, symbol: char, count: usize) {
+    if count > 3 {
+        data.push_str(&format!("!{}{}", count, symbol));
+    } else {
+        data.extend(std::iter::repeat_n(symbol, count));
+    }
+}
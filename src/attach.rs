@@ -0,0 +1,298 @@
+//! Terminal view of a player running elsewhere, usually a headless one
+//!
+//! The view polls the player's status over its control socket. Keys bound to playback actions
+//! in the keymap send the matching request, ←/→ seek, +/- change the volume, and `:` opens a
+//! prompt whose line runs on the player. Quitting detaches and leaves the player running.
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Gauge, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use crate::ipc::{Connection, Request, Status};
+use crate::keymap::{Action, KeyPress, Keymap, Lookup};
+use crate::theme::Theme;
+use crate::{SHUTDOWN, format_time};
+
+/// Time between status requests
+const REFRESH: Duration = Duration::from_millis(250);
+
+/// Seconds moved by ←/→
+const SEEK_STEP: f64 = 5.0;
+
+/// Percent changed by +/-
+const VOLUME_STEP: i16 = 5;
+
+/// Attach to the player listening on `socket` until the user detaches
+pub fn run(socket: &Path, keymap: &Keymap, theme: &Theme) -> Result<()> {
+    let mut connection = Connection::open(socket)?;
+    let status = fetch_status(&mut connection)?;
+    let mut view = View {
+        status,
+        pending_keys: Vec::new(),
+        prompt: None,
+        message: Ok(format!("Attached to {}", socket.display())),
+    };
+
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+    let result = view.run(&mut terminal, &mut connection, keymap, theme);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+fn fetch_status(connection: &mut Connection) -> Result<Status> {
+    connection
+        .request(Request::Status)?
+        .status
+        .context("The player sent no status")
+}
+
+struct View {
+    status: Status,
+    /// Keys typed so far of a multi-key binding
+    pending_keys: Vec<KeyPress>,
+    /// Text typed at the `:` prompt while it is open
+    prompt: Option<String>,
+    /// Reply to the last request
+    message: Result<String, String>,
+}
+
+impl View {
+    fn run<B: Backend>(
+        &mut self,
+        terminal: &mut Terminal<B>,
+        connection: &mut Connection,
+        keymap: &Keymap,
+        theme: &Theme,
+    ) -> Result<()> {
+        loop {
+            terminal.draw(|f| self.draw(f, keymap, theme))?;
+
+            if event::poll(REFRESH)?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                match self.handle_key(key, keymap) {
+                    Some(Some(request)) => {
+                        self.message = match connection.request(request) {
+                            Ok(response) => Ok(response.message.unwrap_or_default()),
+                            Err(e) => Err(format!("{:#}", e)),
+                        };
+                    }
+                    Some(None) => return Ok(()),
+                    None => {}
+                }
+            }
+
+            if SHUTDOWN.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            self.status = fetch_status(connection).context("Lost the player")?;
+        }
+    }
+
+    /// Handle a key, returning the request to send, `Some(None)` to detach, or `None` for nothing
+    fn handle_key(&mut self, key: KeyEvent, keymap: &Keymap) -> Option<Option<Request>> {
+        if let Some(line) = &mut self.prompt {
+            match key.code {
+                KeyCode::Enter => {
+                    let line = self.prompt.take().unwrap_or_default();
+                    if !line.trim().is_empty() {
+                        return Some(Some(Request::Exec { line }));
+                    }
+                }
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Backspace if line.is_empty() => self.prompt = None,
+                KeyCode::Backspace => {
+                    line.pop();
+                }
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => line.push(c),
+                _ => {}
+            }
+            return None;
+        }
+
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(None);
+        }
+
+        // Seeking and volume have no bindings in the main view, which uses the mouse for them
+        if self.pending_keys.is_empty() {
+            let request = match key.code {
+                KeyCode::Left => Some(Request::Seek {
+                    position: None,
+                    offset: Some(-SEEK_STEP),
+                }),
+                KeyCode::Right => Some(Request::Seek {
+                    position: None,
+                    offset: Some(SEEK_STEP),
+                }),
+                KeyCode::Char('+' | '=') => Some(Request::Volume {
+                    volume: None,
+                    offset: Some(VOLUME_STEP),
+                }),
+                KeyCode::Char('-') => Some(Request::Volume {
+                    volume: None,
+                    offset: Some(-VOLUME_STEP),
+                }),
+                _ => None,
+            };
+            if request.is_some() {
+                return Some(request);
+            }
+        }
+
+        self.pending_keys.push(KeyPress::from_event(key));
+        let action = match keymap.lookup(&self.pending_keys) {
+            Lookup::Action(action) => action,
+            Lookup::Pending => return None,
+            Lookup::Unbound => {
+                self.pending_keys.clear();
+                return None;
+            }
+        };
+        self.pending_keys.clear();
+
+        let request = match action {
+            Action::Quit => return Some(None),
+            Action::PlayPause => Request::Toggle,
+            Action::NextTrack => Request::Next,
+            Action::PreviousTrack => Request::Previous,
+            Action::Stop => Request::Stop,
+            Action::ToggleShuffle => Request::Exec {
+                line: "shuffle".to_string(),
+            },
+            Action::CycleRepeat => {
+                let next = match self.status.repeat.as_str() {
+                    "off" => "one",
+                    "one" => "all",
+                    _ => "off",
+                };
+                Request::Exec {
+                    line: format!("repeat {}", next),
+                }
+            }
+            Action::CommandLine => {
+                self.prompt = Some(String::new());
+                return None;
+            }
+            action => {
+                self.message = Err(format!(
+                    "{} is not available when attached",
+                    action.description()
+                ));
+                return None;
+            }
+        };
+        Some(Some(request))
+    }
+
+    fn draw(&self, f: &mut Frame, keymap: &Keymap, theme: &Theme) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(6),
+                Constraint::Length(3),
+                Constraint::Length(1),
+                Constraint::Min(0),
+            ])
+            .split(f.area());
+
+        let status = &self.status;
+        let mut lines = match &status.track {
+            Some(track) => vec![
+                Line::styled(track.title.clone(), theme.playing),
+                Line::from(
+                    [track.artist.as_deref(), track.album.as_deref()]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" · "),
+                ),
+            ],
+            None => vec![Line::from("No tracks loaded"), Line::default()],
+        };
+        let field = |label: &str, value: String| {
+            vec![
+                Span::styled(format!("{}: ", label), theme.key),
+                Span::styled(value, theme.value),
+                Span::raw("  "),
+            ]
+        };
+        lines.push(Line::from(
+            [
+                field("Track", format!("{}/{}", status.index + 1, status.tracks)),
+                field("Queued", status.queue.to_string()),
+                field("Volume", format!("{}%", status.volume)),
+            ]
+            .concat(),
+        ));
+        lines.push(Line::from(
+            [
+                field(
+                    "Shuffle",
+                    if status.shuffle { "on" } else { "off" }.to_string(),
+                ),
+                field("Repeat", status.repeat.clone()),
+            ]
+            .concat(),
+        ));
+        let title = format!("{} (attached)", status.state);
+        f.render_widget(Paragraph::new(lines).block(theme.block(title)), chunks[0]);
+
+        let duration = status.track.as_ref().and_then(|track| track.duration);
+        let ratio = duration
+            .filter(|d| *d > 0.0)
+            .map_or(0.0, |d| (status.position / d).clamp(0.0, 1.0));
+        let label = format!(
+            "{} / {}",
+            format_time(Duration::from_secs_f64(status.position.max(0.0))),
+            duration.map_or("--:--".to_string(), |d| {
+                format_time(Duration::from_secs_f64(d))
+            })
+        );
+        let gauge = Gauge::default()
+            .block(theme.block("Progress"))
+            .gauge_style(theme.progress)
+            .ratio(ratio)
+            .label(label);
+        f.render_widget(gauge, chunks[1]);
+
+        let line = match (&self.prompt, &self.message) {
+            (Some(text), _) => Line::from(format!(":{}", text)),
+            (None, Ok(message)) => Line::styled(message.clone(), theme.info),
+            (None, Err(error)) => Line::styled(error.clone(), theme.error),
+        };
+        f.render_widget(Paragraph::new(line).style(theme.base), chunks[2]);
+
+        let keys = |action: Action| keymap.keys_for(action).join("/");
+        let help = format!(
+            "{} play/pause  {} next  {} previous  {} stop  ←/→ seek  +/- volume  {} command  {} detach",
+            keys(Action::PlayPause),
+            keys(Action::NextTrack),
+            keys(Action::PreviousTrack),
+            keys(Action::Stop),
+            keys(Action::CommandLine),
+            keys(Action::Quit),
+        );
+        f.render_widget(Paragraph::new(help).style(theme.disabled), chunks[3]);
+    }
+}
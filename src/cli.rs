@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+/// 🎵 Terminal Music Player
+#[derive(Debug, Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+
+    /// Load configuration from <FILE> instead of the default location
+    #[arg(long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Run a command once playback starts, e.g. -c 'seek 1:30' (repeatable)
+    #[arg(short = 'c', long = "command", value_name = "COMMAND")]
+    pub commands: Vec<String>,
+
+    /// Play without the TUI, controlled through the control socket and signals
+    #[arg(long, visible_alias = "headless", conflicts_with = "test")]
+    pub daemon: bool,
+
+    /// Exit immediately after testing playback (for testing)
+    #[arg(long)]
+    pub test: bool,
+
+    /// Music directories or files, instead of the configured library roots
+    #[arg(value_name = "PATH")]
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Control a running player through its control socket
+    Ctl(CtlArgs),
+    /// Show and control a running player, such as a headless one, in the terminal
+    Attach(AttachArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct AttachArgs {
+    /// Socket to connect to, instead of the configured one
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct CtlArgs {
+    /// Socket to connect to, instead of the configured one
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: CtlCommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum CtlCommand {
+    /// Resume or start playback
+    Play,
+    /// Pause playback
+    Pause,
+    /// Pause when playing, otherwise play
+    Toggle,
+    /// Stop playback
+    Stop,
+    /// Play the next track
+    Next,
+    /// Play the previous track
+    #[command(alias = "prev")]
+    Previous,
+    /// Seek to a position ([h:]m:ss or seconds), or by +/- an offset
+    #[command(allow_negative_numbers = true)]
+    Seek { time: String },
+    /// Set the volume (0-100), or change it by +/- an amount
+    #[command(allow_negative_numbers = true)]
+    Volume { volume: String },
+    /// Queue files to play next, adding them to the track list if needed
+    Enqueue {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Show what is playing
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print events as JSON lines until the player exits
+    Watch,
+    /// Run a command as if typed at the `:` prompt, e.g. `ctl exec repeat all`
+    Exec {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        line: Vec<String>,
+    },
+}
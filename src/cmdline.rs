@@ -0,0 +1,351 @@
+use crate::command;
+
+/// Number of entered commands kept for recall with Up/Down
+const HISTORY_LIMIT: usize = 100;
+
+/// Candidates offered by Tab and the one currently inserted
+struct Completion {
+    candidates: Vec<String>,
+    index: usize,
+    /// Text after the cursor, kept in place while cycling
+    suffix: String,
+}
+
+/// The `:` prompt: input line, cursor, history and completion state
+pub struct CommandLine {
+    active: bool,
+    input: String,
+    /// Cursor position as a character index into `input`
+    cursor: usize,
+    history: Vec<String>,
+    /// History entry being shown, and the text typed before browsing started
+    browsing: Option<(usize, String)>,
+    completion: Option<Completion>,
+    /// Why the last command failed, shown after the input until the next edit
+    pub error: Option<String>,
+}
+
+impl CommandLine {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            input: String::new(),
+            cursor: 0,
+            history: Vec::new(),
+            browsing: None,
+            completion: None,
+            error: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn open(&mut self) {
+        self.open_with("");
+    }
+
+    /// Open the prompt with a command already typed, for the user to finish or confirm
+    pub fn open_with(&mut self, input: &str) {
+        self.active = true;
+        self.set_input(input.to_string());
+    }
+
+    pub fn close(&mut self) {
+        self.active = false;
+        self.set_input(String::new());
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Cursor position in characters from the start of the input
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Completion candidates with the index of the inserted one, while cycling through them
+    pub fn candidates(&self) -> Option<(&[String], usize)> {
+        self.completion
+            .as_ref()
+            .filter(|c| c.candidates.len() > 1)
+            .map(|c| (c.candidates.as_slice(), c.index))
+    }
+
+    /// Replace the input, leaving history browsing and completion
+    fn set_input(&mut self, input: String) {
+        self.cursor = input.chars().count();
+        self.input = input;
+        self.browsing = None;
+        self.completion = None;
+        self.error = None;
+    }
+
+    /// Byte offset of a character index into the input
+    fn byte_offset(&self, cursor: usize) -> usize {
+        self.input
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.input.len(), |(i, _)| i)
+    }
+
+    /// Note that the input was edited by hand
+    fn edited(&mut self) {
+        self.browsing = None;
+        self.completion = None;
+        self.error = None;
+    }
+
+    pub fn insert(&mut self, c: char) {
+        let at = self.byte_offset(self.cursor);
+        self.input.insert(at, c);
+        self.cursor += 1;
+        self.edited();
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            let at = self.byte_offset(self.cursor);
+            self.input.remove(at);
+        }
+        self.edited();
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.input.chars().count() {
+            let at = self.byte_offset(self.cursor);
+            self.input.remove(at);
+        }
+        self.edited();
+    }
+
+    /// Delete the word before the cursor, like Ctrl-w in a shell
+    pub fn delete_word(&mut self) {
+        let end = self.byte_offset(self.cursor);
+        // Skip separators first, or a directory's trailing `/` would stop every deletion
+        let before = self.input[..end].trim_end_matches(|c: char| c.is_whitespace() || c == '/');
+        let start = before
+            .rfind(|c: char| c.is_whitespace() || c == '/')
+            .map_or(0, |i| i + 1);
+        self.cursor = self.input[..start].chars().count();
+        self.input.replace_range(start..end, "");
+        self.edited();
+    }
+
+    /// Delete everything before the cursor
+    pub fn delete_to_start(&mut self) {
+        let end = self.byte_offset(self.cursor);
+        self.input.replace_range(..end, "");
+        self.cursor = 0;
+        self.edited();
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.input.chars().count());
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.input.chars().count();
+    }
+
+    /// Recall an older command starting with the text typed so far
+    pub fn history_previous(&mut self) {
+        let (from, typed) = match self.browsing.take() {
+            Some((index, typed)) => (index, typed),
+            None => (self.history.len(), self.input.clone()),
+        };
+        let found = self.history[..from]
+            .iter()
+            .rposition(|entry| entry.starts_with(&typed));
+        match found {
+            Some(index) => {
+                self.set_input(self.history[index].clone());
+                self.browsing = Some((index, typed));
+            }
+            None => self.browsing = Some((from, typed)),
+        }
+    }
+
+    /// Recall a newer command, returning to the typed text after the newest one
+    pub fn history_next(&mut self) {
+        let Some((from, typed)) = self.browsing.take() else {
+            return;
+        };
+        let found = self
+            .history
+            .iter()
+            .enumerate()
+            .skip(from + 1)
+            .find(|(_, entry)| entry.starts_with(&typed))
+            .map(|(index, _)| index);
+        match found {
+            Some(index) => {
+                self.set_input(self.history[index].clone());
+                self.browsing = Some((index, typed));
+            }
+            None => self.set_input(typed),
+        }
+    }
+
+    /// Complete the input, or move to the next (or previous) candidate when already completing
+    pub fn complete(&mut self, forward: bool) {
+        if let Some(completion) = &mut self.completion {
+            let len = completion.candidates.len();
+            completion.index = if forward {
+                (completion.index + 1) % len
+            } else {
+                (completion.index + len - 1) % len
+            };
+            let candidate = &completion.candidates[completion.index];
+            self.cursor = candidate.chars().count();
+            self.input = format!("{}{}", candidate, completion.suffix);
+            return;
+        }
+
+        // Completion works on the text before the cursor
+        let at = self.byte_offset(self.cursor);
+        let candidates = command::complete(&self.input[..at]);
+        if candidates.is_empty() {
+            return;
+        }
+
+        let suffix = self.input[at..].to_string();
+        let index = if forward { 0 } else { candidates.len() - 1 };
+        self.cursor = candidates[index].chars().count();
+        self.input = format!("{}{}", candidates[index], suffix);
+        self.error = None;
+        self.browsing = None;
+        // A single candidate is final, so the next Tab completes from it
+        if candidates.len() > 1 {
+            self.completion = Some(Completion {
+                candidates,
+                index,
+                suffix,
+            });
+        }
+    }
+
+    /// Take the entered command, remembering it in the history
+    ///
+    /// The prompt stays open so a failing command can be fixed; close it once it succeeds.
+    pub fn submit(&mut self) -> String {
+        let line = self.input.trim().to_string();
+        if !line.is_empty() && self.history.last() != Some(&line) {
+            if self.history.len() == HISTORY_LIMIT {
+                self.history.remove(0);
+            }
+            self.history.push(line.clone());
+        }
+        self.browsing = None;
+        self.completion = None;
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entered(lines: &[&str]) -> CommandLine {
+        let mut command_line = CommandLine::new();
+        for line in lines {
+            command_line.open_with(line);
+            command_line.submit();
+        }
+        command_line.open();
+        command_line
+    }
+
+    fn type_text(command_line: &mut CommandLine, text: &str) {
+        for c in text.chars() {
+            command_line.insert(c);
+        }
+    }
+
+    #[test]
+    fn history_recalls_commands_starting_with_the_typed_text() {
+        let mut command_line = entered(&["seek 10", "volume 50", "seek 20", "seek 20"]);
+        type_text(&mut command_line, "se");
+
+        command_line.history_previous();
+        assert_eq!(command_line.input(), "seek 20");
+        command_line.history_previous();
+        assert_eq!(command_line.input(), "seek 10");
+        // Nothing older matches, so the oldest match stays
+        command_line.history_previous();
+        assert_eq!(command_line.input(), "seek 10");
+
+        command_line.history_next();
+        assert_eq!(command_line.input(), "seek 20");
+        command_line.history_next();
+        assert_eq!(command_line.input(), "se");
+        assert_eq!(command_line.cursor(), 2);
+    }
+
+    #[test]
+    fn completion_cycles_before_the_text_after_the_cursor() {
+        let mut command_line = CommandLine::new();
+        command_line.open_with("p x");
+        command_line.move_home();
+        command_line.move_right();
+
+        command_line.complete(true);
+        assert_eq!(command_line.input(), "pause x");
+        assert_eq!(command_line.cursor(), 5);
+        assert_eq!(
+            command_line.candidates(),
+            Some((
+                &[
+                    "pause".to_string(),
+                    "play".to_string(),
+                    "previous".to_string()
+                ][..],
+                0
+            ))
+        );
+        command_line.complete(true);
+        assert_eq!(command_line.input(), "play x");
+        assert_eq!(command_line.cursor(), 4);
+        command_line.complete(false);
+        command_line.complete(false);
+        assert_eq!(command_line.input(), "previous x");
+
+        // Typing ends the cycle
+        command_line.insert('!');
+        assert_eq!(command_line.candidates(), None);
+        assert_eq!(command_line.input(), "previous! x");
+    }
+
+    #[test]
+    fn delete_word_removes_path_components_behind_the_cursor() {
+        let mut command_line = CommandLine::new();
+        command_line.open_with("add ~/Music/");
+
+        command_line.delete_word();
+        assert_eq!(command_line.input(), "add ~/");
+        assert_eq!(command_line.cursor(), 6);
+        command_line.delete_word();
+        assert_eq!(command_line.input(), "add ");
+        command_line.delete_word();
+        assert_eq!(command_line.input(), "");
+
+        command_line.open_with("volume 50 up");
+        command_line.move_left();
+        command_line.move_left();
+        command_line.move_left();
+        command_line.delete_word();
+        assert_eq!(command_line.input(), "volume  up");
+        assert_eq!(command_line.cursor(), 7);
+    }
+}
@@ -0,0 +1,558 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use crate::RepeatMode;
+use crate::config::expand_home;
+use crate::metadata::TagField;
+use crate::sort::{SortKey, SortOrder};
+
+/// A command typed at the `:` prompt or passed with `-c` on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Add(PathBuf),
+    /// Add the target tracks to the end of a playlist file
+    Append(PathBuf),
+    /// Queue the target tracks to play next, in order
+    Enqueue,
+    Next,
+    Pause,
+    Play,
+    Previous,
+    Quit,
+    /// Take the target tracks out of the track list
+    Remove,
+    Repeat(RepeatMode),
+    Save(PathBuf),
+    Seek(Amount<Duration>),
+    /// Turn shuffle on or off, or toggle it when no state is given
+    Shuffle(Option<bool>),
+    Sort(SortOrder),
+    Stop,
+    /// Set a tag on the target tracks, removing it when the value is empty
+    Tag(TagField, String),
+    /// Move the target tracks' files to the trash
+    Trash,
+    Volume(Amount<u8>),
+}
+
+impl Command {
+    /// Whether the command works on a set of tracks rather than on playback
+    pub fn takes_targets(&self) -> bool {
+        matches!(
+            self,
+            Command::Append(_)
+                | Command::Enqueue
+                | Command::Remove
+                | Command::Tag(..)
+                | Command::Trash
+        )
+    }
+}
+
+/// A value to set, or a step up or down from the current one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Amount<T> {
+    To(T),
+    Up(T),
+    Down(T),
+}
+
+impl<T> Amount<T> {
+    /// Convert the value, keeping the direction
+    fn try_map<U>(self, f: impl Fn(T) -> Result<U>) -> Result<Amount<U>> {
+        Ok(match self {
+            Amount::To(value) => Amount::To(f(value)?),
+            Amount::Up(value) => Amount::Up(f(value)?),
+            Amount::Down(value) => Amount::Down(f(value)?),
+        })
+    }
+}
+
+/// How the argument of a command is completed
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    None,
+    /// A file or directory, taking the rest of the line
+    Path,
+    /// Words from fixed lists, one list per position
+    Words(&'static [&'static [&'static str]]),
+    /// A value such as a time or a number, with nothing to complete
+    Value,
+}
+
+/// Name, argument and help text of a command
+struct CommandInfo {
+    name: &'static str,
+    arg: Arg,
+    usage: &'static str,
+}
+
+const SORT_KEYS: &[&str] = &[
+    "path",
+    "filename",
+    "title",
+    "artist",
+    "duration",
+    "date_added",
+    "play_count",
+];
+
+/// All commands, in the order they are completed
+const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "add",
+        arg: Arg::Path,
+        usage: "add <file|directory>",
+    },
+    CommandInfo {
+        name: "append",
+        arg: Arg::Path,
+        usage: "append <playlist.m3u>",
+    },
+    CommandInfo {
+        name: "enqueue",
+        arg: Arg::None,
+        usage: "enqueue",
+    },
+    CommandInfo {
+        name: "next",
+        arg: Arg::None,
+        usage: "next",
+    },
+    CommandInfo {
+        name: "pause",
+        arg: Arg::None,
+        usage: "pause",
+    },
+    CommandInfo {
+        name: "play",
+        arg: Arg::None,
+        usage: "play",
+    },
+    CommandInfo {
+        name: "previous",
+        arg: Arg::None,
+        usage: "previous",
+    },
+    CommandInfo {
+        name: "quit",
+        arg: Arg::None,
+        usage: "quit",
+    },
+    CommandInfo {
+        name: "remove",
+        arg: Arg::None,
+        usage: "remove",
+    },
+    CommandInfo {
+        name: "repeat",
+        arg: Arg::Words(&[&["off", "one", "all"]]),
+        usage: "repeat off|one|all",
+    },
+    CommandInfo {
+        name: "save",
+        arg: Arg::Path,
+        usage: "save <playlist.m3u>",
+    },
+    CommandInfo {
+        name: "seek",
+        arg: Arg::Value,
+        usage: "seek [+|-]<[h:]m:ss|seconds>",
+    },
+    CommandInfo {
+        name: "shuffle",
+        arg: Arg::Words(&[&["on", "off", "toggle"]]),
+        usage: "shuffle [on|off|toggle]",
+    },
+    CommandInfo {
+        name: "sort",
+        arg: Arg::Words(&[SORT_KEYS, &["asc", "desc"]]),
+        usage: "sort <key> [asc|desc]",
+    },
+    CommandInfo {
+        name: "stop",
+        arg: Arg::None,
+        usage: "stop",
+    },
+    CommandInfo {
+        name: "tag",
+        arg: Arg::Words(&[TagField::NAMES]),
+        usage: "tag title|artist|album|track [value]",
+    },
+    CommandInfo {
+        name: "trash",
+        arg: Arg::None,
+        usage: "trash",
+    },
+    CommandInfo {
+        name: "volume",
+        arg: Arg::Value,
+        usage: "volume [+|-]<0-100>",
+    },
+];
+
+/// Find a command by name or unambiguous prefix, like `vol` for `volume`
+fn find(name: &str) -> Result<&'static CommandInfo> {
+    if let Some(info) = COMMANDS.iter().find(|info| info.name == name) {
+        return Ok(info);
+    }
+    let matches: Vec<&CommandInfo> = COMMANDS
+        .iter()
+        .filter(|info| info.name.starts_with(name))
+        .collect();
+    match matches.as_slice() {
+        [info] => Ok(info),
+        [] => bail!("Unknown command '{}'", name),
+        _ => bail!(
+            "Ambiguous command '{}': {}",
+            name,
+            matches
+                .iter()
+                .map(|info| info.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Parse a command line such as `seek 1:30` or `:sort artist desc`
+pub fn parse(line: &str) -> Result<Command> {
+    let line = line.trim().trim_start_matches(':').trim_start();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if name.is_empty() {
+        bail!("Empty command");
+    }
+    let info = find(name)?;
+    let rest = rest.trim();
+    parse_args(info, rest).map_err(|e| anyhow!("{} (usage: {})", e, info.usage))
+}
+
+fn parse_args(info: &CommandInfo, rest: &str) -> Result<Command> {
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    if matches!(info.arg, Arg::None) && !words.is_empty() {
+        bail!("'{}' takes no argument", info.name);
+    }
+
+    let command = match info.name {
+        "add" => Command::Add(parse_path(rest)?),
+        "append" => Command::Append(parse_path(rest)?),
+        "enqueue" => Command::Enqueue,
+        "next" => Command::Next,
+        "pause" => Command::Pause,
+        "play" => Command::Play,
+        "previous" => Command::Previous,
+        "quit" => Command::Quit,
+        "remove" => Command::Remove,
+        "repeat" => Command::Repeat(match words.as_slice() {
+            ["off" | "none"] => RepeatMode::None,
+            ["one"] => RepeatMode::One,
+            ["all"] => RepeatMode::All,
+            _ => bail!("Expected off, one or all"),
+        }),
+        "save" => Command::Save(parse_path(rest)?),
+        "seek" => Command::Seek(parse_amount(rest)?.try_map(|value| {
+            parse_time(value).with_context(|| format!("Invalid time '{}'", value))
+        })?),
+        "shuffle" => Command::Shuffle(match words.as_slice() {
+            [] | ["toggle"] => None,
+            ["on"] => Some(true),
+            ["off"] => Some(false),
+            _ => bail!("Expected on, off or toggle"),
+        }),
+        "sort" => {
+            let (key, descending) = match words.as_slice() {
+                [key] => (key, false),
+                [key, "asc"] => (key, false),
+                [key, "desc"] => (key, true),
+                _ => bail!("Expected a sort key and an optional direction"),
+            };
+            let key = SortKey::ALL
+                .into_iter()
+                .zip(SORT_KEYS)
+                .find(|(_, name)| *name == key)
+                .map(|(key, _)| key)
+                .with_context(|| format!("Unknown sort key '{}'", key))?;
+            Command::Sort(SortOrder { key, descending })
+        }
+        "stop" => Command::Stop,
+        "tag" => {
+            // The value is the rest of the line, so it may contain spaces
+            let (name, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let field =
+                TagField::from_name(name).with_context(|| format!("Unknown tag '{}'", name))?;
+            Command::Tag(field, value.trim().to_string())
+        }
+        "trash" => Command::Trash,
+        "volume" => Command::Volume(parse_amount(rest)?.try_map(|value| {
+            value
+                .parse::<u8>()
+                .ok()
+                .filter(|v| *v <= 100)
+                .with_context(|| format!("Invalid volume '{}'", value))
+        })?),
+        name => unreachable!("command '{}' has no parser", name),
+    };
+    Ok(command)
+}
+
+/// Split a leading `+` or `-` off a value
+fn parse_amount(value: &str) -> Result<Amount<&str>> {
+    if value.is_empty() {
+        bail!("Missing value");
+    }
+    Ok(if let Some(step) = value.strip_prefix('+') {
+        Amount::Up(step.trim())
+    } else if let Some(step) = value.strip_prefix('-') {
+        Amount::Down(step.trim())
+    } else {
+        Amount::To(value)
+    })
+}
+
+/// Parse `[h:]m:ss` or a number of seconds
+fn parse_time(value: &str) -> Option<Duration> {
+    let parts: Vec<&str> = value.split(':').collect();
+    let (seconds, units) = parts.split_last()?;
+    let seconds: f64 = seconds.parse().ok()?;
+    if !seconds.is_finite() || seconds < 0.0 || (!units.is_empty() && seconds >= 60.0) {
+        return None;
+    }
+
+    if units.len() > 2 {
+        return None;
+    }
+
+    let mut total = 0u64;
+    for (i, unit) in units.iter().enumerate() {
+        let unit: u64 = unit.parse().ok()?;
+        // Minutes after hours are limited to 0-59 as well
+        if i > 0 && unit >= 60 {
+            return None;
+        }
+        total = total * 60 + unit;
+    }
+    Some(Duration::from_secs(total * 60) + Duration::from_secs_f64(seconds))
+}
+
+/// Read a path argument, dropping surrounding quotes and expanding `~`
+fn parse_path(value: &str) -> Result<PathBuf> {
+    let value = ['"', '\'']
+        .iter()
+        .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q))
+        .unwrap_or(value);
+    if value.is_empty() {
+        bail!("Missing path");
+    }
+    Ok(expand_home(Path::new(value)))
+}
+
+/// Complete the word before the end of a command line
+///
+/// Returns whole command lines to cycle through, so the caller can simply replace its input.
+pub fn complete(line: &str) -> Vec<String> {
+    let Some((name, rest)) = line.split_once(char::is_whitespace) else {
+        let matches: Vec<&CommandInfo> = COMMANDS
+            .iter()
+            .filter(|info| info.name.starts_with(line))
+            .collect();
+        return match matches.as_slice() {
+            // Go straight on to the argument when there is only one match
+            [info] if !matches!(info.arg, Arg::None) => vec![format!("{} ", info.name)],
+            _ => matches.iter().map(|info| info.name.to_string()).collect(),
+        };
+    };
+    let Ok(info) = find(name) else {
+        return Vec::new();
+    };
+    let rest = rest.trim_start();
+
+    let completions = match info.arg {
+        Arg::Path => complete_path(rest),
+        Arg::Words(lists) => {
+            // Complete the last word, keeping the ones before it
+            let (done, word) = match rest.rfind(char::is_whitespace) {
+                Some(i) => (&rest[..=i], &rest[i + 1..]),
+                None => ("", rest),
+            };
+            let position = done.split_whitespace().count();
+            lists
+                .get(position)
+                .into_iter()
+                .flat_map(|list| list.iter())
+                .filter(|choice| choice.starts_with(word))
+                .map(|choice| format!("{}{}", done, choice))
+                .collect()
+        }
+        Arg::None | Arg::Value => Vec::new(),
+    };
+
+    completions
+        .into_iter()
+        .map(|completion| format!("{} {}", info.name, completion))
+        .collect()
+}
+
+/// Complete a partly typed path from the file system, marking directories with a `/`
+fn complete_path(typed: &str) -> Vec<String> {
+    let (dir, prefix) = match typed.rfind('/') {
+        Some(i) => (&typed[..=i], &typed[i + 1..]),
+        None => ("", typed),
+    };
+    let search = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_home(Path::new(dir))
+    };
+    let Ok(entries) = fs::read_dir(&search) else {
+        return Vec::new();
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            // Hidden files only show up once a dot has been typed
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let is_dir = entry.path().is_dir();
+            Some(if is_dir { format!("{}/", name) } else { name })
+        })
+        .collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| format!("{}{}", dir, name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    fn error(line: &str) -> String {
+        format!("{:#}", parse(line).unwrap_err())
+    }
+
+    #[test]
+    fn parse_takes_unambiguous_prefixes() {
+        assert_eq!(parse("vol 50").unwrap(), Command::Volume(Amount::To(50)));
+        assert_eq!(parse(":  next").unwrap(), Command::Next);
+        assert_eq!(parse("stop").unwrap(), Command::Stop);
+        assert_eq!(parse("sh on").unwrap(), Command::Shuffle(Some(true)));
+        assert_eq!(
+            error("s 1"),
+            "Ambiguous command 's': save, seek, shuffle, sort, stop"
+        );
+        assert_eq!(error("p"), "Ambiguous command 'p': pause, play, previous");
+        assert_eq!(error("rewind"), "Unknown command 'rewind'");
+        assert_eq!(error(":"), "Empty command");
+        assert_eq!(error("next 2"), "'next' takes no argument (usage: next)");
+    }
+
+    #[test]
+    fn parse_reads_seek_times_and_directions() {
+        assert_eq!(
+            parse("seek +10").unwrap(),
+            Command::Seek(Amount::Up(secs(10)))
+        );
+        assert_eq!(
+            parse("seek -1:30").unwrap(),
+            Command::Seek(Amount::Down(secs(90)))
+        );
+        assert_eq!(
+            parse("seek 1:02:03").unwrap(),
+            Command::Seek(Amount::To(secs(3723)))
+        );
+        assert_eq!(
+            parse("seek 2.5").unwrap(),
+            Command::Seek(Amount::To(Duration::from_millis(2500)))
+        );
+        assert_eq!(
+            error("seek 1:60"),
+            "Invalid time '1:60' (usage: seek [+|-]<[h:]m:ss|seconds>)"
+        );
+        assert!(parse("seek 1:60:00").is_err());
+        assert!(parse("seek 1:2:3:4").is_err());
+        assert!(parse("seek nan").is_err());
+        assert!(parse("seek inf").is_err());
+        assert!(parse("seek").is_err());
+    }
+
+    #[test]
+    fn parse_limits_the_volume() {
+        assert_eq!(
+            parse("volume 100").unwrap(),
+            Command::Volume(Amount::To(100))
+        );
+        assert_eq!(
+            parse("volume -5").unwrap(),
+            Command::Volume(Amount::Down(5))
+        );
+        assert_eq!(
+            error("volume 101"),
+            "Invalid volume '101' (usage: volume [+|-]<0-100>)"
+        );
+        assert!(parse("volume loud").is_err());
+    }
+
+    #[test]
+    fn parse_keeps_spaces_in_tag_values_and_paths() {
+        assert_eq!(
+            parse("tag title Hold On  Tight ").unwrap(),
+            Command::Tag(TagField::Title, "Hold On  Tight".to_string())
+        );
+        assert_eq!(
+            parse("tag album").unwrap(),
+            Command::Tag(TagField::Album, String::new())
+        );
+        assert!(parse("tag genre Rock").is_err());
+
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            parse(r#"add "~/Music/Low Songs""#).unwrap(),
+            Command::Add(home.join("Music/Low Songs"))
+        );
+        assert_eq!(
+            parse("add '/tmp/a b.flac'").unwrap(),
+            Command::Add(PathBuf::from("/tmp/a b.flac"))
+        );
+        assert_eq!(
+            parse("sort artist desc").unwrap(),
+            Command::Sort(SortOrder {
+                key: SortKey::Artist,
+                descending: true
+            })
+        );
+        assert!(parse("add ''").is_err());
+    }
+
+    #[test]
+    fn complete_moves_on_to_the_argument_of_a_single_match() {
+        assert_eq!(complete("vo"), ["volume "]);
+        // Commands without an argument are finished as they are
+        assert_eq!(complete("sto"), ["stop"]);
+        assert_eq!(complete("p"), ["pause", "play", "previous"]);
+        assert!(complete("x").is_empty());
+    }
+
+    #[test]
+    fn complete_offers_words_for_each_position() {
+        assert_eq!(complete("sort ar"), ["sort artist"]);
+        assert_eq!(
+            complete("sort artist "),
+            ["sort artist asc", "sort artist desc"]
+        );
+        assert_eq!(complete("sort artist d"), ["sort artist desc"]);
+        assert!(complete("sort artist desc ").is_empty());
+        assert_eq!(complete("rep o"), ["repeat off", "repeat one"]);
+        assert!(complete("seek 1").is_empty());
+    }
+}
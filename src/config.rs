@@ -0,0 +1,444 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::artwork::ArtMode;
+use crate::nowplaying::{self, OutputFormat};
+use crate::sort::{SortKey, SortOrder};
+use crate::theme::StyleSpec;
+use crate::{RepeatMode, SUPPORTED_EXTENSIONS};
+
+/// File name of the configuration file inside the config directory
+const CONFIG_FILE: &str = "config.toml";
+
+/// User configuration loaded from `config.toml`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub library: LibraryConfig,
+    pub playback: PlaybackConfig,
+    pub ui: UiConfig,
+    pub ipc: IpcConfig,
+    pub mpris: MprisConfig,
+    pub mpd: MpdConfig,
+    pub http: HttpConfig,
+    pub hooks: HooksConfig,
+    /// Files describing the playing track, for stream overlays
+    pub now_playing: Vec<NowPlayingConfig>,
+    /// Theme element to style, overriding the selected theme
+    pub theme: BTreeMap<String, StyleSpec>,
+    /// Action name to list of keys, overriding the default bindings
+    pub keys: BTreeMap<String, Vec<String>>,
+}
+
+/// Where music is loaded from and how it is listed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LibraryConfig {
+    /// Directories or files loaded when no path is given on the command line
+    pub roots: Vec<PathBuf>,
+    /// Audio file extensions to load, without the leading dot
+    pub extensions: Vec<String>,
+    /// Follow symbolic links while scanning directories
+    pub follow_links: bool,
+    /// Initial sort key for the track list
+    pub sort: SortKey,
+    /// Sort the track list in descending order
+    pub sort_descending: bool,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            extensions: SUPPORTED_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            follow_links: false,
+            sort: SortKey::Path,
+            sort_descending: false,
+        }
+    }
+}
+
+impl LibraryConfig {
+    /// Get the configured sort order
+    pub fn sort_order(&self) -> SortOrder {
+        SortOrder {
+            key: self.sort,
+            descending: self.sort_descending,
+        }
+    }
+}
+
+/// Playback state applied at startup
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaybackConfig {
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+    /// Volume in percent (0-100)
+    pub volume: u8,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        Self {
+            shuffle: false,
+            repeat: RepeatMode::None,
+            volume: 100,
+        }
+    }
+}
+
+/// Terminal UI behaviour
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiConfig {
+    /// How long to wait for input before redrawing, in milliseconds
+    pub poll_interval_ms: u64,
+    /// Built-in theme name, user theme name or path to a theme file
+    pub theme: Option<String>,
+    /// Draw the progress bar as a waveform of the playing track
+    pub waveform: bool,
+    /// How album art is drawn in the Now Playing pane
+    pub album_art: ArtMode,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 100,
+            theme: None,
+            waveform: true,
+            album_art: ArtMode::Auto,
+        }
+    }
+}
+
+/// Control socket for scripts and `music_player ctl`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IpcConfig {
+    /// Listen for commands while the player is running
+    pub enabled: bool,
+    /// Socket path, instead of `music_player.sock` in `$XDG_RUNTIME_DIR`
+    pub socket: Option<PathBuf>,
+}
+
+impl Default for IpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            socket: None,
+        }
+    }
+}
+
+impl IpcConfig {
+    /// Configured socket path, or the default location
+    pub fn socket_path(&self) -> PathBuf {
+        self.socket
+            .clone()
+            .unwrap_or_else(crate::ipc::default_socket_path)
+    }
+}
+
+/// MPRIS interface for desktop media keys, `playerctl` and status bars
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MprisConfig {
+    /// Register on the D-Bus session bus while the player is running
+    pub enabled: bool,
+}
+
+impl Default for MprisConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// MPD protocol server for existing MPD clients such as `mpc` and `ncmpcpp`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MpdConfig {
+    /// Listen for MPD clients while the player is running
+    pub enabled: bool,
+    /// Address and port to listen on
+    pub address: String,
+    /// Directory browsed and searched by clients, instead of the first library root
+    pub music_directory: Option<PathBuf>,
+}
+
+impl Default for MpdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:6600".to_string(),
+            music_directory: None,
+        }
+    }
+}
+
+/// HTTP/JSON API and web remote
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Serve the API and the remote page while the player is running
+    pub enabled: bool,
+    /// Address and port to listen on; use `0.0.0.0:<port>` to reach it from other devices
+    pub address: String,
+    /// Secret API requests must carry, as a bearer token or a `token` query parameter
+    pub token: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:8080".to_string(),
+            token: None,
+        }
+    }
+}
+
+/// Shell commands run on playback events
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    pub track_started: Option<String>,
+    /// Run when a track plays to its end, but not when it is skipped or stopped
+    pub track_finished: Option<String>,
+    pub paused: Option<String>,
+    pub resumed: Option<String>,
+    pub stopped: Option<String>,
+    /// Run when a track fails to play
+    pub error: Option<String>,
+    /// Seconds a hook may run before it is killed
+    pub timeout_secs: u64,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            track_started: None,
+            track_finished: None,
+            paused: None,
+            resumed: None,
+            stopped: None,
+            error: None,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// A now-playing file for stream overlays
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NowPlayingConfig {
+    /// File to write
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// Text for the `text` format, with `{title}`, `{artist}` and other fields filled in
+    #[serde(default = "default_template")]
+    pub template: String,
+}
+
+fn default_template() -> String {
+    "{title}".to_string()
+}
+
+impl Config {
+    /// Directory MPD clients browse: the configured one, or the first library root
+    pub fn music_directory(&self) -> Option<PathBuf> {
+        self.mpd.music_directory.clone().or_else(|| {
+            self.library
+                .roots
+                .iter()
+                .find(|root| root.is_dir())
+                .cloned()
+        })
+    }
+
+    /// Default location of the configuration file in the XDG config directory
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("music_player").join(CONFIG_FILE))
+    }
+
+    /// Load the configuration from an explicit path, or the default location if it exists
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match Self::default_path().filter(|p| p.exists()) {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let mut config: Config = toml::from_str(&data)
+            .with_context(|| format!("Invalid config file: {}", path.display()))?;
+
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file: {}", path.display()))?;
+        info!("Loaded configuration from {}", path.display());
+
+        Ok(config)
+    }
+
+    /// Check value ranges and normalize paths, reporting every problem at once
+    fn validate(&mut self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.playback.volume > 100 {
+            errors.push(format!(
+                "playback.volume must be between 0 and 100, got {}",
+                self.playback.volume
+            ));
+        }
+
+        if !(10..=1000).contains(&self.ui.poll_interval_ms) {
+            errors.push(format!(
+                "ui.poll_interval_ms must be between 10 and 1000, got {}",
+                self.ui.poll_interval_ms
+            ));
+        }
+
+        if self.hooks.timeout_secs == 0 {
+            errors.push("hooks.timeout_secs must be at least 1".to_string());
+        }
+
+        for (i, output) in self.now_playing.iter_mut().enumerate() {
+            output.path = expand_home(&output.path);
+            if let Err(e) = nowplaying::check_template(&output.template) {
+                errors.push(format!("now_playing[{}].template: {}", i, e));
+            }
+        }
+
+        if self.library.extensions.is_empty() {
+            errors.push("library.extensions must not be empty".to_string());
+        }
+        for ext in &mut self.library.extensions {
+            *ext = ext.trim_start_matches('.').to_lowercase();
+            if ext.is_empty() {
+                errors.push("library.extensions must not contain empty entries".to_string());
+            }
+        }
+
+        if let Some(socket) = &mut self.ipc.socket {
+            *socket = expand_home(socket);
+        }
+        if let Some(dir) = &mut self.mpd.music_directory {
+            *dir = expand_home(dir);
+        }
+
+        for root in &mut self.library.roots {
+            *root = expand_home(root);
+            if !root.exists() {
+                warn!("Music root does not exist: {}", root.display());
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Expand a leading `~` to the user's home directory
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn parse(text: &str) -> Result<Config> {
+        let mut config: Config = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn every_bad_value_is_reported_together() {
+        let error = parse(
+            "[playback]\nvolume = 150\n\
+             [ui]\npoll_interval_ms = 5\n\
+             [hooks]\ntimeout_secs = 0\n\
+             [[now_playing]]\npath = \"np.txt\"\ntemplate = \"{nope}\"\n",
+        )
+        .unwrap_err()
+        .to_string();
+        let lines: Vec<&str> = error.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", error);
+        assert_eq!(
+            lines[0],
+            "playback.volume must be between 0 and 100, got 150"
+        );
+        assert_eq!(
+            lines[1],
+            "ui.poll_interval_ms must be between 10 and 1000, got 5"
+        );
+        assert_eq!(lines[2], "hooks.timeout_secs must be at least 1");
+        assert!(
+            lines[3].starts_with("now_playing[0].template: "),
+            "{}",
+            lines[3]
+        );
+
+        assert!(parse("").is_ok());
+    }
+
+    #[test]
+    fn extensions_are_normalized_and_required() {
+        let config = parse("[library]\nextensions = [\".MP3\", \"Flac\"]\n").unwrap();
+        assert_eq!(config.library.extensions, ["mp3", "flac"]);
+
+        let error = parse("[library]\nextensions = []\n").unwrap_err();
+        assert_eq!(error.to_string(), "library.extensions must not be empty");
+        let error = parse("[library]\nextensions = [\".\"]\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "library.extensions must not contain empty entries"
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"[playback]\nvolume = 50\nvolum = 60\n")
+            .unwrap();
+
+        let error = Config::load(Some(file.path())).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("unknown field `volum`"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn home_is_expanded_in_paths() {
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(expand_home(Path::new("~/x")), home.join("x"));
+        assert_eq!(expand_home(Path::new("~")), home);
+        assert_eq!(expand_home(Path::new("/srv/~/x")), Path::new("/srv/~/x"));
+        assert_eq!(expand_home(Path::new("~user/x")), Path::new("~user/x"));
+
+        let config = parse("[ipc]\nsocket = \"~/player.sock\"\n").unwrap();
+        assert_eq!(config.ipc.socket, Some(home.join("player.sock")));
+    }
+}
@@ -0,0 +1,137 @@
+use std::path::{self, Path};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::cli::CtlCommand;
+use crate::command::{self, Amount, Command};
+use crate::format_time;
+use crate::ipc::{Connection, Request, Status};
+
+/// Send one `ctl` command to the player listening on `socket` and print the result
+pub fn run(socket: &Path, command: CtlCommand) -> Result<()> {
+    let mut connection = Connection::open(socket)?;
+
+    let request = match command {
+        CtlCommand::Play => Request::Play,
+        CtlCommand::Pause => Request::Pause,
+        CtlCommand::Toggle => Request::Toggle,
+        CtlCommand::Stop => Request::Stop,
+        CtlCommand::Next => Request::Next,
+        CtlCommand::Previous => Request::Previous,
+        CtlCommand::Seek { time } => seek_request(&time)?,
+        CtlCommand::Volume { volume } => volume_request(&volume)?,
+        CtlCommand::Enqueue { files } => {
+            for file in files {
+                // The player may be running in another directory
+                let path = path::absolute(&file)
+                    .with_context(|| format!("Invalid path: {}", file.display()))?;
+                let response = connection.request(Request::Enqueue { path })?;
+                print_message(response.message);
+            }
+            return Ok(());
+        }
+        CtlCommand::Status { json } => {
+            let status = connection
+                .request(Request::Status)?
+                .status
+                .context("The player sent no status")?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                print_status(&status);
+            }
+            return Ok(());
+        }
+        CtlCommand::Watch => {
+            connection.request(Request::Subscribe)?;
+            while let Some(event) = connection.next_event()? {
+                println!("{}", serde_json::to_string(&event)?);
+            }
+            return Ok(());
+        }
+        CtlCommand::Exec { line } => Request::Exec {
+            line: line.join(" "),
+        },
+    };
+
+    let response = connection.request(request)?;
+    print_message(response.message);
+    Ok(())
+}
+
+/// Build a seek request from the same arguments as the `:seek` command
+fn seek_request(time: &str) -> Result<Request> {
+    let Command::Seek(amount) = command::parse(&format!("seek {}", time))? else {
+        unreachable!("seek always parses to a seek command");
+    };
+    let seconds = |d: Duration| Some(d.as_secs_f64());
+    Ok(match amount {
+        Amount::To(time) => Request::Seek {
+            position: seconds(time),
+            offset: None,
+        },
+        Amount::Up(step) => Request::Seek {
+            position: None,
+            offset: seconds(step),
+        },
+        Amount::Down(step) => Request::Seek {
+            position: None,
+            offset: seconds(step).map(|s| -s),
+        },
+    })
+}
+
+/// Build a volume request from the same arguments as the `:volume` command
+fn volume_request(volume: &str) -> Result<Request> {
+    let Command::Volume(amount) = command::parse(&format!("volume {}", volume))? else {
+        unreachable!("volume always parses to a volume command");
+    };
+    Ok(match amount {
+        Amount::To(volume) => Request::Volume {
+            volume: Some(volume),
+            offset: None,
+        },
+        Amount::Up(step) => Request::Volume {
+            volume: None,
+            offset: Some(i16::from(step)),
+        },
+        Amount::Down(step) => Request::Volume {
+            volume: None,
+            offset: Some(-i16::from(step)),
+        },
+    })
+}
+
+fn print_message(message: Option<String>) {
+    if let Some(message) = message {
+        println!("{}", message);
+    }
+}
+
+fn print_status(status: &Status) {
+    let Some(track) = &status.track else {
+        println!("No tracks loaded");
+        return;
+    };
+    let title = match &track.artist {
+        Some(artist) => format!("{} - {}", artist, track.title),
+        None => track.title.clone(),
+    };
+    let position = format_time(Duration::from_secs_f64(status.position));
+    let duration = track.duration.map_or("--:--".to_string(), |d| {
+        format_time(Duration::from_secs_f64(d))
+    });
+
+    println!("{}: {}", status.state, title);
+    println!("  {} / {}", position, duration);
+    println!(
+        "  track {}/{}, {} queued, volume {}%, shuffle {}, repeat {}",
+        status.index + 1,
+        status.tracks,
+        status.queue,
+        status.volume,
+        if status.shuffle { "on" } else { "off" },
+        status.repeat
+    );
+}
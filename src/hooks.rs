@@ -0,0 +1,350 @@
+//! User commands run on playback events
+//!
+//! Each configured hook is a shell command run with `sh -c` on its own thread, so a slow hook
+//! never holds up the player. It gets the event and the track as `MUSIC_PLAYER_*` environment
+//! variables and as one JSON object on stdin. It runs in a process group of its own, which
+//! is killed as a whole once the hook outlives the timeout, so commands it started go too.
+//! Hooks for events that happen close together run concurrently, so they may finish in any order.
+
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::Track;
+use crate::config::HooksConfig;
+use crate::ipc::TrackInfo;
+
+/// How often a running hook is checked for having exited
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Playback events hooks can be attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    TrackStarted,
+    TrackFinished,
+    Paused,
+    Resumed,
+    Stopped,
+    Error,
+}
+
+impl HookEvent {
+    /// Name used in the config file, `MUSIC_PLAYER_EVENT` and the JSON
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::TrackStarted => "track_started",
+            HookEvent::TrackFinished => "track_finished",
+            HookEvent::Paused => "paused",
+            HookEvent::Resumed => "resumed",
+            HookEvent::Stopped => "stopped",
+            HookEvent::Error => "error",
+        }
+    }
+}
+
+/// What a hook receives on stdin
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'static str,
+    track: Option<TrackInfo>,
+    /// Position in the track, in seconds
+    position: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// The configured hooks
+pub struct Hooks {
+    config: HooksConfig,
+    timeout: Duration,
+}
+
+impl Hooks {
+    /// Load the hooks from the config, or `None` when there are none
+    pub fn new(config: &HooksConfig) -> Option<Self> {
+        let any = [
+            &config.track_started,
+            &config.track_finished,
+            &config.paused,
+            &config.resumed,
+            &config.stopped,
+            &config.error,
+        ]
+        .iter()
+        .any(|command| command.is_some());
+
+        any.then(|| Self {
+            config: config.clone(),
+            timeout: Duration::from_secs(config.timeout_secs),
+        })
+    }
+
+    fn command(&self, event: HookEvent) -> Option<&str> {
+        match event {
+            HookEvent::TrackStarted => self.config.track_started.as_deref(),
+            HookEvent::TrackFinished => self.config.track_finished.as_deref(),
+            HookEvent::Paused => self.config.paused.as_deref(),
+            HookEvent::Resumed => self.config.resumed.as_deref(),
+            HookEvent::Stopped => self.config.stopped.as_deref(),
+            HookEvent::Error => self.config.error.as_deref(),
+        }
+    }
+
+    /// Start the hook for `event`, if one is configured, without waiting for it
+    pub fn run(
+        &self,
+        event: HookEvent,
+        track: Option<&Track>,
+        position: Duration,
+        error: Option<&str>,
+    ) {
+        let Some(command) = self.command(event) else {
+            return;
+        };
+
+        let (env, input) = match inputs(event, track, position, error) {
+            Ok(inputs) => inputs,
+            Err(e) => return warn!("Failed to encode {} hook input: {}", event.name(), e),
+        };
+
+        let command = command.to_string();
+        let timeout = self.timeout;
+        let spawned = thread::Builder::new()
+            .name("hook".to_string())
+            .spawn(move || {
+                if let Err(e) = execute(&command, &env, &input, timeout) {
+                    warn!("{} hook failed: {:#}", event.name(), e);
+                }
+            });
+        if let Err(e) = spawned {
+            warn!("Failed to start {} hook: {}", event.name(), e);
+        }
+    }
+}
+
+/// Environment variables a hook is run with, by name
+type Environment = Vec<(&'static str, String)>;
+
+/// Build the environment and the stdin JSON for a hook
+fn inputs(
+    event: HookEvent,
+    track: Option<&Track>,
+    position: Duration,
+    error: Option<&str>,
+) -> serde_json::Result<(Environment, String)> {
+    let track = track.map(TrackInfo::from);
+    let mut env = vec![
+        ("MUSIC_PLAYER_EVENT", event.name().to_string()),
+        (
+            "MUSIC_PLAYER_POSITION",
+            format!("{:.3}", position.as_secs_f64()),
+        ),
+    ];
+    if let Some(track) = &track {
+        env.push(("MUSIC_PLAYER_PATH", track.path.display().to_string()));
+        env.push(("MUSIC_PLAYER_TITLE", track.title.clone()));
+        if let Some(artist) = &track.artist {
+            env.push(("MUSIC_PLAYER_ARTIST", artist.clone()));
+        }
+        if let Some(album) = &track.album {
+            env.push(("MUSIC_PLAYER_ALBUM", album.clone()));
+        }
+        if let Some(number) = track.track_number {
+            env.push(("MUSIC_PLAYER_TRACK_NUMBER", number.to_string()));
+        }
+        if let Some(duration) = track.duration {
+            env.push(("MUSIC_PLAYER_DURATION", format!("{:.3}", duration)));
+        }
+    }
+    if let Some(error) = error {
+        env.push(("MUSIC_PLAYER_ERROR", error.to_string()));
+    }
+
+    let payload = Payload {
+        event: event.name(),
+        track,
+        position: position.as_secs_f64(),
+        error,
+    };
+    Ok((env, serde_json::to_string(&payload)?))
+}
+
+/// Run one hook to completion, killing it after `timeout`
+fn execute(command: &str, env: &[(&str, String)], input: &str, timeout: Duration) -> Result<()> {
+    debug!("Running hook: {}", command);
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", command))?;
+
+    // Hooks that don't read stdin close it early, which is fine
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{}", input);
+    }
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if !status.success() {
+                bail!("'{}' exited with {}", command, status);
+            }
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            // The group shares the shell's id, as the shell leads it
+            if let Ok(group) = libc::pid_t::try_from(child.id()) {
+                // SAFETY: killpg only sends a signal; the group is the hook's own
+                unsafe { libc::killpg(group, libc::SIGKILL) };
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!(
+                "'{}' was killed after running for {}s",
+                command,
+                timeout.as_secs()
+            );
+        }
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    fn track() -> Track {
+        Track {
+            path: PathBuf::from("/music/Low/Sunflower.flac"),
+            title: "Sunflower".to_string(),
+            artist: Some("Low".to_string()),
+            album: None,
+            track_number: Some(1),
+            duration: Some(Duration::from_millis(245_500)),
+            date_added: None,
+            play_count: 0,
+            unplayable: None,
+        }
+    }
+
+    /// Run a hook with its inputs and a `DIR` variable naming a scratch directory
+    fn execute_in(dir: &Path, command: &str, timeout: Duration, error: Option<&str>) -> Result<()> {
+        let track = track();
+        let (mut env, input) = inputs(
+            HookEvent::TrackStarted,
+            Some(&track),
+            Duration::from_millis(1500),
+            error,
+        )
+        .unwrap();
+        env.push(("DIR", dir.display().to_string()));
+        execute(command, &env, &input, timeout)
+    }
+
+    #[test]
+    fn hooks_get_the_event_as_variables_and_json() {
+        let dir = tempfile::tempdir().unwrap();
+        execute_in(
+            dir.path(),
+            r#"cat > "$DIR/out"; env > "$DIR/env""#,
+            Duration::from_secs(10),
+            Some("Unsupported codec"),
+        )
+        .unwrap();
+
+        let input: Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("out")).unwrap()).unwrap();
+        assert_eq!(
+            input,
+            json!({
+                "event": "track_started",
+                "track": {
+                    "path": "/music/Low/Sunflower.flac",
+                    "title": "Sunflower",
+                    "artist": "Low",
+                    "album": null,
+                    "track_number": 1,
+                    "duration": 245.5,
+                },
+                "position": 1.5,
+                "error": "Unsupported codec",
+            })
+        );
+
+        let env = fs::read_to_string(dir.path().join("env")).unwrap();
+        let env: Vec<&str> = env.lines().collect();
+        for variable in [
+            "MUSIC_PLAYER_EVENT=track_started",
+            "MUSIC_PLAYER_POSITION=1.500",
+            "MUSIC_PLAYER_PATH=/music/Low/Sunflower.flac",
+            "MUSIC_PLAYER_TITLE=Sunflower",
+            "MUSIC_PLAYER_ARTIST=Low",
+            "MUSIC_PLAYER_TRACK_NUMBER=1",
+            "MUSIC_PLAYER_DURATION=245.500",
+            "MUSIC_PLAYER_ERROR=Unsupported codec",
+        ] {
+            assert!(env.contains(&variable), "missing {}", variable);
+        }
+        assert!(
+            !env.iter()
+                .any(|line| line.starts_with("MUSIC_PLAYER_ALBUM="))
+        );
+    }
+
+    #[test]
+    fn failing_hooks_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = execute_in(dir.path(), "exit 3", Duration::from_secs(10), None).unwrap_err();
+        assert_eq!(error.to_string(), "'exit 3' exited with exit status: 3");
+    }
+
+    #[test]
+    fn slow_hooks_are_killed_with_their_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let started = Instant::now();
+        let error = execute_in(
+            dir.path(),
+            r#"sleep 30 & echo $! > "$DIR/pid"; wait"#,
+            Duration::from_secs(1),
+            None,
+        )
+        .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(
+            error
+                .to_string()
+                .contains("was killed after running for 1s")
+        );
+
+        // The orphaned `sleep` is gone, or at most waiting to be reaped
+        let pid = fs::read_to_string(dir.path().join("pid")).unwrap();
+        let stat = Path::new("/proc").join(pid.trim()).join("stat");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let state = fs::read_to_string(&stat)
+                .ok()
+                .and_then(|stat| stat.rsplit_once(") ")?.1.chars().next());
+            if matches!(state, None | Some('Z' | 'X')) {
+                break;
+            }
+            assert!(Instant::now() < deadline, "sleep is still running");
+            thread::sleep(WAIT_INTERVAL);
+        }
+    }
+}
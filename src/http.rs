@@ -0,0 +1,1003 @@
+//! HTTP/JSON API and a small web remote
+//!
+//! An opt-in HTTP/1.1 server so a phone or browser can control the player. The page at `/`
+//! is a remote built on the JSON API under `/api`, which takes the same requests and sends
+//! the same events as the control socket:
+//!
+//! - `GET /api/status`: the status object
+//! - `POST /api/play`, `pause`, `toggle`, `stop`, `next`, `previous`
+//! - `POST /api/seek` and `POST /api/volume` with a body such as `{"offset": -10}`
+//! - `GET /api/queue`: the tracks queued to play next
+//! - `POST /api/queue` with `{"path": ...}`: queue a track from the track list
+//! - `GET /api/tracks?q=...&limit=...`: tracks whose tags or path contain every word
+//! - `GET /api/events`: server-sent events, starting with `status`, then the socket events
+//!
+//! API requests must name this server in `Host`, and an `Origin` when they send one, so web
+//! pages on other sites can't drive the player through the browser; `POST` bodies must be
+//! sent as `application/json`. When a token is configured, API requests must also carry it
+//! as `Authorization: Bearer ...` or as a `token` query parameter. The server runs on its
+//! own thread with a tokio runtime; commands go to the main loop, which sends back the
+//! state and events on every pass.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, oneshot};
+
+use crate::MusicPlayer;
+use crate::config::HttpConfig;
+use crate::ipc::{Event, Request, Response, Status, TrackInfo};
+use crate::mpris::percent_decode;
+
+/// The web remote, served at `/`
+const REMOTE_PAGE: &str = include_str!("remote.html");
+
+/// Commands accepted as `POST /api/{command}`
+const COMMANDS: &[&str] = &[
+    "play", "pause", "toggle", "stop", "next", "previous", "seek", "volume",
+];
+
+/// Largest request line and headers accepted, in bytes
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// Largest request body accepted, in bytes
+const MAX_BODY: usize = 64 * 1024;
+
+/// How long a client may take to send its whole request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a request waits for the main loop to answer
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often an idle event stream sends a comment, to notice clients that have gone
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Search results returned when the request doesn't give a limit
+const DEFAULT_LIMIT: usize = 50;
+
+/// A request from an HTTP client, for the main loop to answer
+pub struct Call {
+    pub request: Request,
+    reply: oneshot::Sender<Response>,
+}
+
+impl Call {
+    /// Send the main loop's answer back to the waiting client
+    pub fn finish(self, response: Response) {
+        let _ = self.reply.send(response);
+    }
+}
+
+/// The HTTP server as seen from the main loop
+pub struct Server {
+    requests: Receiver<Call>,
+    shared: Arc<Mutex<Shared>>,
+    events: broadcast::Sender<Event>,
+    /// Track list version last copied for searches
+    version: Option<u32>,
+    /// Queue last copied for `GET /api/queue`
+    queue: Vec<PathBuf>,
+}
+
+impl Server {
+    /// Bind the configured address and start serving on a thread of its own
+    pub fn start(config: &HttpConfig, player: &MusicPlayer) -> Result<Self> {
+        let listener = std::net::TcpListener::bind(&config.address)
+            .with_context(|| format!("Failed to listen on {}", config.address))?;
+        listener.set_nonblocking(true)?;
+
+        let (request_sender, requests) = mpsc::channel();
+        let (events, _) = broadcast::channel(64);
+        let shared = Arc::new(Mutex::new(Shared {
+            status: player.status(),
+            tracks: Vec::new(),
+            queue: Vec::new(),
+        }));
+        let context = Arc::new(Context {
+            shared: Arc::clone(&shared),
+            requests: request_sender,
+            events: events.clone(),
+            token: config.token.clone(),
+            host: host_name(&config.address).to_string(),
+            read_timeout: READ_TIMEOUT,
+        });
+        spawn(listener, context)?;
+        info!("Serving the web remote on http://{}", config.address);
+
+        let mut server = Self {
+            requests,
+            shared,
+            events,
+            version: None,
+            queue: Vec::new(),
+        };
+        server.update(player);
+        Ok(server)
+    }
+
+    /// Take the next request from an HTTP client, if any
+    pub fn try_recv(&self) -> Option<Call> {
+        self.requests.try_recv().ok()
+    }
+
+    /// Copy the player's state for requests answered without the main loop
+    pub fn update(&mut self, player: &MusicPlayer) {
+        let Ok(mut shared) = self.shared.lock() else {
+            return;
+        };
+        shared.status = player.status();
+
+        let list_changed = self.version != Some(player.list_version());
+        if list_changed {
+            shared.tracks = player.tracks.iter().map(TrackInfo::from).collect();
+            self.version = Some(player.list_version());
+        }
+        if list_changed || player.queue.iter().ne(self.queue.iter()) {
+            self.queue = player.queue.iter().cloned().collect();
+            shared.queue = self
+                .queue
+                .iter()
+                .filter_map(|path| player.index_of(path))
+                .map(|index| TrackInfo::from(&player.tracks[index]))
+                .collect();
+        }
+    }
+
+    /// Send an event to every open event stream
+    pub fn publish(&self, event: &Event) {
+        // Fails only when no stream is listening
+        let _ = self.events.send(event.clone());
+    }
+}
+
+/// Player state kept up to date by the main loop
+struct Shared {
+    status: Status,
+    tracks: Vec<TrackInfo>,
+    queue: Vec<TrackInfo>,
+}
+
+/// What every connection needs
+struct Context {
+    shared: Arc<Mutex<Shared>>,
+    requests: Sender<Call>,
+    events: broadcast::Sender<Event>,
+    token: Option<String>,
+    /// Host of the configured address, which `Host` headers may name besides `localhost`
+    host: String,
+    /// How long a client may take to send its request before the connection is closed
+    read_timeout: Duration,
+}
+
+impl Context {
+    fn status(&self) -> Option<Status> {
+        self.shared.lock().ok().map(|shared| shared.status.clone())
+    }
+
+    /// Refuse requests that a web page on another site made through the browser
+    ///
+    /// A page can send simple cross-origin requests here, and read the answers once it has
+    /// rebound its own name to this address. The first carry the page's `Origin`, the second
+    /// its name as `Host`. Addresses can't be rebound, so any address is taken to name this
+    /// server, which is how other devices reach it.
+    fn check_origin(&self, request: &HttpRequest) -> Result<(), &'static str> {
+        let host = request.headers.get("host").map_or("", String::as_str);
+        let name = host_name(host);
+        let known = name.eq_ignore_ascii_case("localhost")
+            || name.eq_ignore_ascii_case(&self.host)
+            || name.parse::<IpAddr>().is_ok();
+        if !known {
+            return Err("Unknown Host");
+        }
+        if let Some(origin) = request.headers.get("origin")
+            && !origin.eq_ignore_ascii_case(&format!("http://{}", host))
+        {
+            return Err("Cross-origin requests are not allowed");
+        }
+        Ok(())
+    }
+
+    /// Check the token, when one is configured
+    fn authorized(&self, request: &HttpRequest) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let bearer = request
+            .headers
+            .get("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        bearer.or(request.query.get("token").map(String::as_str)) == Some(token.as_str())
+    }
+
+    /// Hand a request to the main loop and wait for its answer
+    async fn call(&self, request: Request) -> Reply {
+        let (reply, response) = oneshot::channel();
+        if self.requests.send(Call { request, reply }).is_err() {
+            return Reply::error(503, "The player has stopped");
+        }
+        match tokio::time::timeout(CALL_TIMEOUT, response).await {
+            Ok(Ok(response)) => {
+                let status = if response.ok { 200 } else { 400 };
+                Reply::json(status, &response)
+            }
+            _ => Reply::error(503, "The player did not respond"),
+        }
+    }
+}
+
+/// A parsed HTTP request
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    /// Header values by lowercase name
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// What to send back for a request
+enum Reply {
+    Json(u16, Value),
+    Page,
+    Events,
+}
+
+impl HttpRequest {
+    /// Whether the body is declared as JSON, which browsers won't send cross-origin unasked
+    fn is_json(&self) -> bool {
+        self.headers.get("content-type").is_some_and(|value| {
+            let media_type = value.split(';').next().unwrap_or("");
+            media_type.trim().eq_ignore_ascii_case("application/json")
+        })
+    }
+}
+
+impl Reply {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        Reply::Json(status, serde_json::to_value(value).unwrap_or(Value::Null))
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Reply::json(status, &Response::error(&anyhow!("{}", message)))
+    }
+}
+
+/// A search result, with the track's position in the list
+#[derive(Serialize)]
+struct Found<'a> {
+    index: usize,
+    #[serde(flatten)]
+    track: &'a TrackInfo,
+}
+
+/// Body of `POST /api/queue`
+#[derive(Deserialize)]
+struct QueueBody {
+    path: PathBuf,
+}
+
+/// Serve on a thread of its own with a single-threaded runtime
+fn spawn(listener: std::net::TcpListener, context: Arc<Context>) -> Result<()> {
+    thread::Builder::new()
+        .name("http".to_string())
+        .spawn(move || {
+            match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(runtime) => runtime.block_on(serve(listener, context)),
+                Err(e) => warn!("Failed to start the HTTP runtime: {}", e),
+            }
+        })
+        .context("Failed to start the HTTP thread")?;
+    Ok(())
+}
+
+/// Accept connections until the process exits
+async fn serve(listener: std::net::TcpListener, context: Arc<Context>) {
+    let listener = match TcpListener::from_std(listener) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed to serve HTTP: {}", e);
+            return;
+        }
+    };
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let context = Arc::clone(&context);
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, &context).await {
+                        debug!("HTTP connection closed: {:#}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept HTTP connection: {}", e),
+        }
+    }
+}
+
+/// Answer one request; every response closes the connection
+async fn handle(stream: TcpStream, context: &Context) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    // Bounded, so clients that never finish a request don't hold on to their connection
+    let mut reader = BufReader::new(reader);
+    let read = tokio::time::timeout(context.read_timeout, read_request(&mut reader)).await;
+    let request = match read {
+        Ok(Ok(Some(request))) => request,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Err(e)) => {
+            let reply = Reply::error(400, &format!("{:#}", e));
+            return write_reply(&mut writer, reply).await;
+        }
+        Err(_) => {
+            let reply = Reply::error(408, "Timed out waiting for the request");
+            return write_reply(&mut writer, reply).await;
+        }
+    };
+    debug!("HTTP {} {}", request.method, request.path);
+
+    let reply = route(&request, context).await;
+    if let Reply::Events = reply {
+        return stream_events(&mut writer, context).await;
+    }
+    write_reply(&mut writer, reply).await
+}
+
+async fn route(request: &HttpRequest, context: &Context) -> Reply {
+    let Some(endpoint) = request.path.strip_prefix("/api/") else {
+        return match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/") => Reply::Page,
+            (_, "/") => Reply::error(405, "Method not allowed"),
+            _ => Reply::error(404, "Not found"),
+        };
+    };
+    if let Err(reason) = context.check_origin(request) {
+        return Reply::error(403, reason);
+    }
+    if !context.authorized(request) {
+        return Reply::error(401, "Missing or wrong token");
+    }
+    if request.method == "POST" && !request.is_json() {
+        return Reply::error(415, "The request body must be sent as application/json");
+    }
+
+    match (request.method.as_str(), endpoint) {
+        ("GET", "status") => match context.status() {
+            Some(status) => Reply::json(200, &status),
+            None => Reply::error(503, "The player has stopped"),
+        },
+        ("GET", "events") => Reply::Events,
+        ("GET", "queue") => match context.shared.lock() {
+            Ok(shared) => Reply::json(200, &shared.queue),
+            Err(_) => Reply::error(503, "The player has stopped"),
+        },
+        ("GET", "tracks") => {
+            let query = request.query.get("q").map_or("", String::as_str);
+            let limit = match request.query.get("limit").map(|l| l.parse()) {
+                Some(Ok(limit)) => limit,
+                Some(Err(_)) => return Reply::error(400, "limit must be a number"),
+                None => DEFAULT_LIMIT,
+            };
+            match context.shared.lock() {
+                Ok(shared) => Reply::json(200, &search(&shared.tracks, query, limit)),
+                Err(_) => Reply::error(503, "The player has stopped"),
+            }
+        }
+        ("POST", "queue") => {
+            let path = match serde_json::from_slice::<QueueBody>(&request.body) {
+                Ok(body) => body.path,
+                Err(e) => return Reply::error(400, &format!("Invalid request body: {}", e)),
+            };
+            // Only tracks already in the list, so clients can't load arbitrary files
+            let known = context
+                .shared
+                .lock()
+                .is_ok_and(|shared| shared.tracks.iter().any(|t| t.path == path));
+            if !known {
+                return Reply::error(404, "Not in the track list");
+            }
+            context.call(Request::Enqueue { path }).await
+        }
+        ("POST", command) if COMMANDS.contains(&command) => {
+            match command_request(command, &request.body) {
+                Ok(request) => context.call(request).await,
+                Err(e) => Reply::error(400, &format!("{:#}", e)),
+            }
+        }
+        (_, "status" | "events" | "queue" | "tracks") => Reply::error(405, "Method not allowed"),
+        (_, command) if COMMANDS.contains(&command) => Reply::error(405, "Method not allowed"),
+        _ => Reply::error(404, "Not found"),
+    }
+}
+
+/// Build a control request from a command name and an optional JSON object body
+fn command_request(command: &str, body: &[u8]) -> Result<Request> {
+    let mut fields = if body.iter().all(u8::is_ascii_whitespace) {
+        serde_json::Map::new()
+    } else {
+        match serde_json::from_slice(body).context("Invalid request body")? {
+            Value::Object(fields) => fields,
+            _ => bail!("The request body must be a JSON object"),
+        }
+    };
+    fields.insert("command".to_string(), json!(command));
+    serde_json::from_value(Value::Object(fields)).context("Invalid request body")
+}
+
+/// Tracks whose title, artist, album or path contain every word of the query, ignoring case
+fn search<'a>(tracks: &'a [TrackInfo], query: &str, limit: usize) -> Vec<Found<'a>> {
+    let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, track)| {
+            let text = format!(
+                "{} {} {} {}",
+                track.title,
+                track.artist.as_deref().unwrap_or(""),
+                track.album.as_deref().unwrap_or(""),
+                track.path.display()
+            )
+            .to_lowercase();
+            words.iter().all(|word| text.contains(word))
+        })
+        .take(limit)
+        .map(|(index, track)| Found { index, track })
+        .collect()
+}
+
+/// Read the request line, headers and body, or `None` when the client sent nothing
+async fn read_request(reader: &mut BufReader<OwnedReadHalf>) -> Result<Option<HttpRequest>> {
+    let mut head = (&mut *reader).take(MAX_HEAD);
+    let mut line = String::new();
+    if head.read_line(&mut line).await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("Malformed request line");
+    };
+    let method = method.to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = path.to_string();
+    let query = parse_query(query);
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if head.read_line(&mut line).await? == 0 {
+            bail!("Request headers are too large or incomplete");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = match headers.get("content-length") {
+        Some(length) => length.parse().context("Invalid Content-Length")?,
+        None => 0,
+    };
+    if length > MAX_BODY {
+        bail!("The request body is too large");
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Some(HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    }))
+}
+
+/// The host in a `Host` header or listening address, without the port or IPv6 brackets
+fn host_name(authority: &str) -> &str {
+    let host = match authority.rsplit_once(':') {
+        // A bare IPv6 address has colons of its own, so only a bracketed one has a port
+        Some((host, port))
+            if port.bytes().all(|b| b.is_ascii_digit())
+                && (!host.contains(':') || host.ends_with(']')) =>
+        {
+            host
+        }
+        _ => authority,
+    };
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Decode `a=1&b=two+words` query parameters
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |text: &str| percent_decode(&text.replace('+', " "));
+            Some((decode(name)?, decode(value)?))
+        })
+        .collect()
+}
+
+async fn write_reply(writer: &mut OwnedWriteHalf, reply: Reply) -> Result<()> {
+    let (status, content_type, body) = match reply {
+        Reply::Json(status, value) => (status, "application/json", value.to_string()),
+        Reply::Page => (200, "text/html; charset=utf-8", REMOTE_PAGE.to_string()),
+        Reply::Events => unreachable!("event streams are written by stream_events"),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// Send `status`, then every event, until the client goes away
+async fn stream_events(writer: &mut OwnedWriteHalf, context: &Context) -> Result<()> {
+    // Subscribe first so nothing is missed between the status and the first event
+    let mut events = context.events.subscribe();
+    writer
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+    if let Some(status) = context.status() {
+        write_event(writer, "status", &serde_json::to_value(status)?).await?;
+    }
+
+    loop {
+        match tokio::time::timeout(KEEP_ALIVE, events.recv()).await {
+            Ok(Ok(event)) => {
+                let value = serde_json::to_value(&event)?;
+                let name = value["event"].as_str().unwrap_or("message").to_string();
+                write_event(writer, &name, &value).await?;
+            }
+            // A slow client misses some positions rather than holding everyone up
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => {}
+            Ok(Err(broadcast::error::RecvError::Closed)) => return Ok(()),
+            Err(_) => writer.write_all(b": keep-alive\n\n").await?,
+        }
+    }
+}
+
+async fn write_event(writer: &mut OwnedWriteHalf, name: &str, data: &Value) -> Result<()> {
+    let message = format!("event: {}\ndata: {}\n\n", name, data);
+    writer.write_all(message.as_bytes()).await?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        415 => "Unsupported Media Type",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+
+    use super::*;
+    use crate::PlaybackState;
+
+    fn track(path: &str, title: &str, artist: &str, album: &str) -> TrackInfo {
+        TrackInfo {
+            path: PathBuf::from(path),
+            title: title.to_string(),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            track_number: None,
+            duration: Some(200.0),
+        }
+    }
+
+    fn tracks() -> Vec<TrackInfo> {
+        vec![
+            track(
+                "/music/Low/Sunflower.flac",
+                "Sunflower",
+                "Low",
+                "Things We Lost",
+            ),
+            track("/music/Low/Whore.flac", "Whore", "Low", "Things We Lost"),
+            track(
+                "/music/Slint/Good Morning, Captain.flac",
+                "Good Morning, Captain",
+                "Slint",
+                "Spiderland",
+            ),
+        ]
+    }
+
+    /// A server on a free local port with a main loop that echoes each request back
+    struct TestServer {
+        address: SocketAddr,
+        events: broadcast::Sender<Event>,
+    }
+
+    impl TestServer {
+        fn start(token: Option<&str>) -> Self {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let address = listener.local_addr().unwrap();
+
+            let tracks = tracks();
+            let status = Status {
+                state: PlaybackState::Playing,
+                track: Some(tracks[0].clone()),
+                position: 12.0,
+                volume: 70,
+                shuffle: false,
+                repeat: "off".to_string(),
+                index: 0,
+                tracks: tracks.len(),
+                queue: 1,
+            };
+            let (requests, calls) = mpsc::channel::<Call>();
+            let (events, _) = broadcast::channel(16);
+            let context = Arc::new(Context {
+                shared: Arc::new(Mutex::new(Shared {
+                    status,
+                    queue: vec![tracks[1].clone()],
+                    tracks,
+                })),
+                requests,
+                events: events.clone(),
+                token: token.map(str::to_string),
+                host: "player.local".to_string(),
+                read_timeout: Duration::from_millis(500),
+            });
+            spawn(listener, context).unwrap();
+            thread::spawn(move || {
+                for call in calls {
+                    let message = serde_json::to_string(&call.request).unwrap();
+                    call.finish(Response::ok(message));
+                }
+            });
+            Self { address, events }
+        }
+
+        fn connect(&self) -> TcpStream {
+            let stream = TcpStream::connect(self.address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+        }
+
+        /// Send a raw request and return the status code and the body
+        fn send(&self, request: &str) -> (u16, String) {
+            let mut stream = self.connect();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+            (status, body.to_string())
+        }
+
+        fn get(&self, target: &str) -> (u16, String) {
+            self.send(&format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                target
+            ))
+        }
+
+        fn post(&self, target: &str, body: &str) -> (u16, String) {
+            self.send(&format!(
+                "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                target,
+                body.len(),
+                body
+            ))
+        }
+    }
+
+    fn json(body: &str) -> Value {
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[test]
+    fn parse_query_decodes_parameters() {
+        let query = parse_query("q=two+words&limit=5&token=a%2Fb&flag&&=empty");
+        assert_eq!(query["q"], "two words");
+        assert_eq!(query["limit"], "5");
+        assert_eq!(query["token"], "a/b");
+        assert_eq!(query["flag"], "");
+        assert_eq!(query[""], "empty");
+        assert!(parse_query("").is_empty());
+    }
+
+    #[test]
+    fn host_name_drops_ports_and_brackets() {
+        assert_eq!(host_name("localhost:8080"), "localhost");
+        assert_eq!(host_name("127.0.0.1"), "127.0.0.1");
+        assert_eq!(host_name("[::1]:8080"), "::1");
+        assert_eq!(host_name("[::1]"), "::1");
+        assert_eq!(host_name("0.0.0.0:8080"), "0.0.0.0");
+    }
+
+    #[test]
+    fn command_request_merges_the_body_into_the_command() {
+        assert!(matches!(
+            command_request("next", b"").unwrap(),
+            Request::Next
+        ));
+        assert!(matches!(
+            command_request("stop", b" \n").unwrap(),
+            Request::Stop
+        ));
+        assert!(matches!(
+            command_request("seek", br#"{"offset": -10}"#).unwrap(),
+            Request::Seek {
+                position: None,
+                offset: Some(-10.0)
+            }
+        ));
+        assert!(matches!(
+            command_request("volume", br#"{"volume": 50}"#).unwrap(),
+            Request::Volume {
+                volume: Some(50),
+                offset: None
+            }
+        ));
+        assert!(command_request("seek", b"[1]").is_err());
+        assert!(command_request("seek", b"{").is_err());
+        assert!(command_request("volume", br#"{"volume": "loud"}"#).is_err());
+    }
+
+    #[test]
+    fn search_matches_every_word_in_any_field() {
+        let tracks = tracks();
+        let indexes = |query: &str, limit: usize| -> Vec<usize> {
+            search(&tracks, query, limit)
+                .iter()
+                .map(|f| f.index)
+                .collect()
+        };
+        assert_eq!(indexes("low", 10), [0, 1]);
+        assert_eq!(indexes("LOW whore", 10), [1]);
+        assert_eq!(indexes("spiderland captain", 10), [2]);
+        assert_eq!(indexes("slint/good", 10), [2]);
+        assert_eq!(indexes("", 2), [0, 1]);
+        assert!(indexes("low slint", 10).is_empty());
+    }
+
+    #[test]
+    fn routes_answer_gets_and_posts() {
+        let server = TestServer::start(None);
+
+        let (status, body) = server.get("/");
+        assert_eq!(status, 200);
+        assert_eq!(body, REMOTE_PAGE);
+
+        let (status, body) = server.get("/api/status");
+        assert_eq!(status, 200);
+        assert_eq!(json(&body)["track"]["title"], "Sunflower");
+
+        let (status, body) = server.get("/api/queue");
+        assert_eq!(status, 200);
+        assert_eq!(json(&body)[0]["title"], "Whore");
+
+        let (status, body) = server.get("/api/tracks?q=low+whore");
+        assert_eq!(status, 200);
+        assert_eq!(
+            json(&body),
+            json(
+                r#"[{"index": 1, "path": "/music/Low/Whore.flac", "title": "Whore", "artist": "Low", "album": "Things We Lost", "duration": 200.0}]"#
+            )
+        );
+        assert_eq!(
+            json(&server.get("/api/tracks?limit=1").1)
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(server.get("/api/tracks?limit=many").0, 400);
+
+        let (status, body) = server.post("/api/seek", r#"{"offset": 30}"#);
+        assert_eq!(status, 200);
+        assert_eq!(
+            json(&body)["message"],
+            r#"{"command":"seek","offset":30.0}"#
+        );
+        assert_eq!(
+            json(&server.post("/api/next", "").1)["message"],
+            r#"{"command":"next"}"#
+        );
+        assert_eq!(server.post("/api/volume", r#"{"volume": "loud"}"#).0, 400);
+
+        let (status, body) = server.post("/api/queue", r#"{"path": "/music/Low/Whore.flac"}"#);
+        assert_eq!(status, 200);
+        assert_eq!(
+            json(&body)["message"],
+            r#"{"command":"enqueue","path":"/music/Low/Whore.flac"}"#
+        );
+        assert_eq!(
+            server.post("/api/queue", r#"{"path": "/etc/passwd"}"#).0,
+            404
+        );
+        assert_eq!(server.post("/api/queue", "{}").0, 400);
+    }
+
+    #[test]
+    fn unknown_paths_and_methods_are_refused() {
+        let server = TestServer::start(None);
+        assert_eq!(server.get("/index.html").0, 404);
+        assert_eq!(server.get("/api/shuffle").0, 404);
+        assert_eq!(server.post("/", "").0, 405);
+        assert_eq!(server.get("/api/next").0, 405);
+        assert_eq!(server.post("/api/status", "").0, 405);
+        assert_eq!(server.post("/api/tracks", "").0, 405);
+    }
+
+    #[test]
+    fn malformed_requests_are_refused() {
+        let server = TestServer::start(None);
+        assert_eq!(server.send("GET\r\n").0, 400);
+        assert_eq!(
+            server
+                .send("POST /api/next HTTP/1.1\r\nHost: localhost\r\nContent-Length: lots\r\n\r\n")
+                .0,
+            400
+        );
+        let too_large = format!(
+            "POST /api/next HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(server.send(&too_large).0, 400);
+    }
+
+    #[test]
+    fn tokens_are_taken_from_the_header_or_the_query() {
+        let server = TestServer::start(Some("secret"));
+        // The page itself is public; it reads the token from its own address
+        assert_eq!(server.get("/").0, 200);
+        assert_eq!(server.get("/api/status").0, 401);
+        assert_eq!(server.get("/api/status?token=wrong").0, 401);
+        assert_eq!(server.get("/api/status?token=secret").0, 200);
+        let bearer = |token: &str| {
+            server.send(&format!(
+                "GET /api/status HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\n\r\n",
+                token
+            ))
+        };
+        assert_eq!(bearer("secret").0, 200);
+        assert_eq!(bearer("Secret").0, 401);
+    }
+
+    #[test]
+    fn requests_from_other_sites_are_refused() {
+        let server = TestServer::start(None);
+        let port = server.address.port();
+        let get = |host: &str, origin: Option<&str>| {
+            let origin = origin.map_or(String::new(), |o| format!("Origin: {}\r\n", o));
+            server
+                .send(&format!(
+                    "GET /api/status HTTP/1.1\r\nHost: {}\r\n{}\r\n",
+                    host, origin
+                ))
+                .0
+        };
+
+        assert_eq!(get(&format!("localhost:{}", port), None), 200);
+        assert_eq!(get(&format!("127.0.0.1:{}", port), None), 200);
+        assert_eq!(get("192.168.1.20:8080", None), 200);
+        assert_eq!(get("[::1]:8080", None), 200);
+        assert_eq!(get("player.local:8080", None), 200);
+        // A rebound name, or none at all
+        assert_eq!(get("attacker.example:8080", None), 403);
+        assert_eq!(get("", None), 403);
+
+        let host = format!("127.0.0.1:{}", port);
+        let page = format!("http://{}", host);
+        assert_eq!(get(&host, Some(&page)), 200);
+        assert_eq!(get(&host, Some("http://attacker.example")), 403);
+        assert_eq!(get(&host, Some("null")), 403);
+
+        // Forms and `no-cors` fetches can't send JSON across origins without asking first
+        let post = |content_type: &str| {
+            server
+                .send(&format!(
+                    "POST /api/next HTTP/1.1\r\nHost: localhost\r\nContent-Type: {}\r\nContent-Length: 0\r\n\r\n",
+                    content_type
+                ))
+                .0
+        };
+        assert_eq!(post("text/plain"), 415);
+        assert_eq!(post("application/x-www-form-urlencoded"), 415);
+        assert_eq!(post("Application/JSON; charset=utf-8"), 200);
+        assert_eq!(
+            server
+                .send("POST /api/next HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .0,
+            415
+        );
+    }
+
+    #[test]
+    fn event_streams_start_with_the_status() {
+        let server = TestServer::start(None);
+        let mut stream = server.connect();
+        stream
+            .write_all(b"GET /api/events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut read_message = || {
+            let mut message = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                assert!(!line.is_empty(), "stream closed");
+                if line == "\n" || line == "\r\n" {
+                    return message;
+                }
+                message.push_str(&line);
+            }
+        };
+
+        let head = read_message();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: text/event-stream\r\n"));
+
+        let status = read_message();
+        let data = status.strip_prefix("event: status\ndata: ").unwrap();
+        assert_eq!(json(data)["state"], "playing");
+
+        // The stream subscribes before sending the status, so this can't be missed
+        server.events.send(Event::Paused).unwrap();
+        assert_eq!(
+            read_message(),
+            "event: paused\ndata: {\"event\":\"paused\"}\n"
+        );
+    }
+
+    #[test]
+    fn unfinished_requests_time_out() {
+        let server = TestServer::start(None);
+        let (status, body) = server.send("POST /api/next HTTP/1.1\r\nHost: localhost\r\n");
+        assert_eq!(status, 408);
+        assert_eq!(json(&body)["error"], "Timed out waiting for the request");
+
+        let (status, _) = server.send(
+            "POST /api/next HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: 10\r\n\r\n{}",
+        );
+        assert_eq!(status, 408);
+    }
+}
@@ -0,0 +1,858 @@
+//! Newline-delimited JSON control protocol over a Unix domain socket
+//!
+//! Each line a client writes is one request object, tagged by `command`, with an optional
+//! `id` that is echoed in the response:
+//!
+//! ```text
+//! {"id": 1, "command": "seek", "position": 90}
+//! {"id": 1, "ok": true, "message": "Seeked to 1:30"}
+//! ```
+//!
+//! The first line the server writes on every connection is a `hello` event carrying the
+//! protocol version. After a `subscribe` request the connection also receives events as the
+//! player changes: `track_changed`, `playing`, `paused`, `stopped` and, once a second while
+//! playing, `position`. Requests and responses keep their meaning within a protocol version;
+//! new fields and commands may be added without changing it.
+
+use std::env;
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::command::{self, Amount, Command};
+use crate::{PlaybackState, Track};
+
+/// Version of the request, response and event formats
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Socket file name inside the runtime directory
+const SOCKET_FILE: &str = "music_player.sock";
+
+/// Default socket path: `$XDG_RUNTIME_DIR/music_player.sock`, or the same file in a private
+/// per-user directory under the temporary directory when there is no runtime directory
+pub fn default_socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(|| env::temp_dir().join(format!("music_player-{}", current_uid())))
+        .join(SOCKET_FILE)
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and can't fail
+    unsafe { libc::geteuid() }
+}
+
+/// Create the socket's directory readable only by the user, or check that an existing one
+/// can't be used by someone else to plant or replace the socket
+fn prepare_dir(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    let metadata = fs::metadata(dir)
+        .with_context(|| format!("Failed to read directory: {}", dir.display()))?;
+    if metadata.uid() != current_uid() && metadata.uid() != 0 {
+        bail!("{} belongs to another user", dir.display());
+    }
+    // Others may write to a shared directory like /tmp only when the sticky bit stops them
+    // removing files they don't own
+    let mode = metadata.mode();
+    if mode & 0o022 != 0 && mode & 0o1000 == 0 {
+        bail!("{} is writable by other users", dir.display());
+    }
+    Ok(())
+}
+
+/// A request line: the command with an optional id to match the response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+/// Commands accepted on the socket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Play,
+    Pause,
+    /// Pause when playing, otherwise play
+    Toggle,
+    Stop,
+    Next,
+    Previous,
+    /// Seek to `position`, or by `offset`, in seconds
+    Seek {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        position: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<f64>,
+    },
+    /// Set the volume to `volume`, or change it by `offset`, in percent
+    Volume {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        volume: Option<u8>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<i16>,
+    },
+    /// Queue a file to play next, adding it to the track list if needed
+    Enqueue {
+        path: PathBuf,
+    },
+    Status,
+    /// Start receiving events on this connection
+    Subscribe,
+    /// Run a line as if typed at the `:` prompt
+    Exec {
+        line: String,
+    },
+}
+
+impl Request {
+    /// The `:` command with the same effect, for requests that have one
+    ///
+    /// Requests that need the player's state or the connection (`toggle`, `enqueue`,
+    /// `status`, `subscribe`) are answered by the main loop directly and return `None`.
+    pub fn command(&self) -> Result<Option<Command>> {
+        let command = match self {
+            Request::Play => Command::Play,
+            Request::Pause => Command::Pause,
+            Request::Stop => Command::Stop,
+            Request::Next => Command::Next,
+            Request::Previous => Command::Previous,
+            Request::Seek { position, offset } => {
+                let seconds = |value: f64| {
+                    Duration::try_from_secs_f64(value.abs()).context("Invalid seek time")
+                };
+                Command::Seek(match (position, offset) {
+                    (Some(position), None) if *position >= 0.0 => Amount::To(seconds(*position)?),
+                    (None, Some(offset)) if *offset >= 0.0 => Amount::Up(seconds(*offset)?),
+                    (None, Some(offset)) => Amount::Down(seconds(*offset)?),
+                    _ => bail!("seek needs either a non-negative position or an offset"),
+                })
+            }
+            Request::Volume { volume, offset } => Command::Volume(match (volume, offset) {
+                (Some(volume), None) => Amount::To(*volume),
+                (None, Some(offset)) => {
+                    let step = offset.unsigned_abs().min(100) as u8;
+                    if *offset >= 0 {
+                        Amount::Up(step)
+                    } else {
+                        Amount::Down(step)
+                    }
+                }
+                _ => bail!("volume needs either a volume or an offset"),
+            }),
+            Request::Exec { line } => command::parse(line)?,
+            Request::Toggle | Request::Enqueue { .. } | Request::Status | Request::Subscribe => {
+                return Ok(None);
+            }
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Reply to one request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Response {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+impl Response {
+    pub fn ok(message: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            message: Some(message.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn error(error: &anyhow::Error) -> Self {
+        Self {
+            ok: false,
+            error: Some(format!("{:#}", error)),
+            ..Self::default()
+        }
+    }
+
+    pub fn status(status: Status) -> Self {
+        Self {
+            ok: true,
+            status: Some(status),
+            ..Self::default()
+        }
+    }
+}
+
+/// Snapshot of the player returned by `status`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub state: PlaybackState,
+    pub track: Option<TrackInfo>,
+    /// Position in the playing track, in seconds
+    pub position: f64,
+    /// Volume in percent
+    pub volume: u8,
+    pub shuffle: bool,
+    pub repeat: String,
+    /// Index of the current track in the list, counting from 0
+    pub index: usize,
+    pub tracks: usize,
+    /// Number of queued tracks
+    pub queue: usize,
+}
+
+/// A track as described to clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_number: Option<u32>,
+    /// Length in seconds, when known
+    pub duration: Option<f64>,
+}
+
+impl From<&Track> for TrackInfo {
+    fn from(track: &Track) -> Self {
+        Self {
+            path: track.path.clone(),
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            album: track.album.clone(),
+            track_number: track.track_number,
+            duration: track.duration.map(|d| d.as_secs_f64()),
+        }
+    }
+}
+
+/// Notifications sent to subscribed connections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// First line on every connection
+    Hello {
+        protocol: u32,
+        version: String,
+    },
+    TrackChanged {
+        track: TrackInfo,
+    },
+    Playing,
+    Paused,
+    Stopped,
+    Position {
+        position: f64,
+        duration: Option<f64>,
+    },
+}
+
+/// Sends lines to one connected client
+#[derive(Debug, Clone)]
+pub struct Client {
+    lines: Sender<String>,
+}
+
+impl Client {
+    /// Send a JSON value as one line, returning false once the client has gone
+    pub fn send(&self, value: &impl Serialize) -> bool {
+        match serde_json::to_string(value) {
+            Ok(line) => self.lines.send(line).is_ok(),
+            Err(e) => {
+                warn!("Failed to encode IPC message: {}", e);
+                true
+            }
+        }
+    }
+}
+
+/// A request received from a client, for the main loop to answer
+pub struct Incoming {
+    pub envelope: Envelope,
+    pub client: Client,
+}
+
+/// Listens on the control socket and hands requests to the main loop
+pub struct Server {
+    path: PathBuf,
+    requests: Receiver<Incoming>,
+    subscribers: Vec<Client>,
+}
+
+impl Server {
+    /// Bind the socket, replacing a stale one left by a player that didn't exit cleanly
+    ///
+    /// The socket is only usable by the current user.
+    pub fn start(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            prepare_dir(dir)?;
+        }
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("Another player is already listening on {}", path.display());
+            }
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket: {}", path.display()))?;
+        }
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to listen on {}", path.display()))?;
+        fs::set_permissions(path, Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict access to {}", path.display()))?;
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || accept(listener, sender));
+        info!("Listening for commands on {}", path.display());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            requests,
+            subscribers: Vec::new(),
+        })
+    }
+
+    /// Take the next waiting request, if any
+    pub fn try_recv(&self) -> Option<Incoming> {
+        self.requests.try_recv().ok()
+    }
+
+    /// Send future events to a client
+    pub fn subscribe(&mut self, client: Client) {
+        self.subscribers.push(client);
+    }
+
+    /// Send an event to every subscriber, forgetting the ones that have disconnected
+    pub fn publish(&mut self, event: &Event) {
+        self.subscribers.retain(|client| client.send(event));
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Client side of the socket, used by `music_player ctl`
+pub struct Connection {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl Connection {
+    /// Connect to a running player and check that it speaks this protocol version
+    pub fn open(path: &Path) -> Result<Self> {
+        let writer = UnixStream::connect(path)
+            .with_context(|| format!("No player is listening on {}", path.display()))?;
+        let mut connection = Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            next_id: 1,
+        };
+
+        match connection.read_line()? {
+            Some(Line::Event(Event::Hello { protocol, .. })) if protocol == PROTOCOL_VERSION => {}
+            Some(Line::Event(Event::Hello { protocol, version })) => bail!(
+                "The player (version {}) speaks protocol {}, but this client speaks {}",
+                version,
+                protocol,
+                PROTOCOL_VERSION
+            ),
+            _ => bail!("The player didn't greet the connection"),
+        }
+        Ok(connection)
+    }
+
+    /// Send a request and wait for its response, failing when the player reports an error
+    pub fn request(&mut self, request: Request) -> Result<Response> {
+        let id = Value::from(self.next_id);
+        self.next_id += 1;
+        let envelope = Envelope {
+            id: Some(id.clone()),
+            request,
+        };
+        writeln!(self.writer, "{}", serde_json::to_string(&envelope)?)
+            .context("Failed to send request")?;
+
+        loop {
+            match self.read_line()? {
+                Some(Line::Response(response)) if response.id.as_ref() == Some(&id) => {
+                    if !response.ok {
+                        bail!(
+                            response
+                                .error
+                                .unwrap_or_else(|| "Request failed".to_string())
+                        );
+                    }
+                    return Ok(response);
+                }
+                Some(_) => {}
+                None => bail!("The player closed the connection"),
+            }
+        }
+    }
+
+    /// Wait for the next event, or `None` once the player has gone
+    pub fn next_event(&mut self) -> Result<Option<Event>> {
+        loop {
+            match self.read_line()? {
+                Some(Line::Event(event)) => return Ok(Some(event)),
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn read_line(&mut self) -> Result<Option<Line>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = serde_json::from_str(&line).context("Invalid message from the player")?;
+        Ok(Some(line))
+    }
+}
+
+/// A line written by the server
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Event(Event),
+    Response(Response),
+    /// Events from a newer player, skipped
+    Unknown(serde::de::IgnoredAny),
+}
+
+/// Accept connections until the main loop goes away
+fn accept(listener: UnixListener, requests: Sender<Incoming>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let requests = requests.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, requests) {
+                        debug!("IPC connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept IPC connection: {}", e),
+        }
+    }
+}
+
+/// Read requests from one connection and write its responses and events
+fn serve(stream: UnixStream, requests: Sender<Incoming>) -> Result<()> {
+    let (lines, outgoing) = mpsc::channel::<String>();
+    let mut writer = stream.try_clone()?;
+    thread::spawn(move || {
+        for line in outgoing {
+            if writeln!(writer, "{}", line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let client = Client { lines };
+    client.send(&Event::Hello {
+        protocol: PROTOCOL_VERSION,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    });
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Envelope>(&line) {
+            Ok(envelope) => {
+                let incoming = Incoming {
+                    envelope,
+                    client: client.clone(),
+                };
+                if requests.send(incoming).is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                let error = anyhow::Error::new(e).context("Invalid request");
+                client.send(&Response::error(&error));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::command::Amount;
+
+    /// Each request and the line a client sends for it
+    fn requests() -> Vec<(Request, &'static str)> {
+        vec![
+            (Request::Play, r#"{"command":"play"}"#),
+            (Request::Pause, r#"{"command":"pause"}"#),
+            (Request::Toggle, r#"{"command":"toggle"}"#),
+            (Request::Stop, r#"{"command":"stop"}"#),
+            (Request::Next, r#"{"command":"next"}"#),
+            (Request::Previous, r#"{"command":"previous"}"#),
+            (
+                Request::Seek {
+                    position: Some(90.0),
+                    offset: None,
+                },
+                r#"{"command":"seek","position":90.0}"#,
+            ),
+            (
+                Request::Seek {
+                    position: None,
+                    offset: Some(-5.0),
+                },
+                r#"{"command":"seek","offset":-5.0}"#,
+            ),
+            (
+                Request::Volume {
+                    volume: Some(40),
+                    offset: None,
+                },
+                r#"{"command":"volume","volume":40}"#,
+            ),
+            (
+                Request::Volume {
+                    volume: None,
+                    offset: Some(-10),
+                },
+                r#"{"command":"volume","offset":-10}"#,
+            ),
+            (
+                Request::Enqueue {
+                    path: PathBuf::from("/music/a.flac"),
+                },
+                r#"{"command":"enqueue","path":"/music/a.flac"}"#,
+            ),
+            (Request::Status, r#"{"command":"status"}"#),
+            (Request::Subscribe, r#"{"command":"subscribe"}"#),
+            (
+                Request::Exec {
+                    line: "repeat all".to_string(),
+                },
+                r#"{"command":"exec","line":"repeat all"}"#,
+            ),
+        ]
+    }
+
+    fn track() -> TrackInfo {
+        TrackInfo {
+            path: PathBuf::from("/music/a.flac"),
+            title: "A".to_string(),
+            artist: Some("Low".to_string()),
+            album: None,
+            track_number: None,
+            duration: Some(200.0),
+        }
+    }
+
+    fn status() -> Status {
+        Status {
+            state: PlaybackState::Paused,
+            track: Some(track()),
+            position: 1.5,
+            volume: 80,
+            shuffle: true,
+            repeat: "all".to_string(),
+            index: 2,
+            tracks: 7,
+            queue: 1,
+        }
+    }
+
+    /// Answer requests on a server the way the main loop does, until `count` are answered
+    fn answer(server: &mut Server, count: usize) {
+        let started = Instant::now();
+        let mut answered = 0;
+        while answered < count {
+            assert!(started.elapsed() < Duration::from_secs(5), "no request");
+            let Some(incoming) = server.try_recv() else {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            };
+            let mut response = match incoming.envelope.request {
+                Request::Status => Response::status(status()),
+                Request::Subscribe => {
+                    server.subscribe(incoming.client.clone());
+                    Response::ok("Subscribed")
+                }
+                Request::Exec { line } if line == "fail" => {
+                    Response::error(&anyhow::anyhow!("Unknown command 'fail'"))
+                }
+                request => Response::ok(format!("{:?}", request)),
+            };
+            response.id = incoming.envelope.id;
+            incoming.client.send(&response);
+            answered += 1;
+        }
+    }
+
+    #[test]
+    fn requests_round_trip_as_single_lines() {
+        for (request, line) in requests() {
+            let envelope = Envelope {
+                id: None,
+                request: request.clone(),
+            };
+            assert_eq!(serde_json::to_string(&envelope).unwrap(), line);
+            let parsed: Envelope = serde_json::from_str(line).unwrap();
+            assert!(parsed.id.is_none());
+            assert_eq!(format!("{:?}", parsed.request), format!("{:?}", request));
+        }
+
+        let envelope: Envelope =
+            serde_json::from_str(r#"{"id": "a1", "command": "seek", "position": 90}"#).unwrap();
+        assert_eq!(envelope.id, Some(Value::from("a1")));
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"id":"a1","command":"seek","position":90.0}"#
+        );
+        assert!(serde_json::from_str::<Envelope>(r#"{"command":"dance"}"#).is_err());
+        assert!(serde_json::from_str::<Envelope>(r#"{"id":1}"#).is_err());
+    }
+
+    #[test]
+    fn requests_map_onto_commands() {
+        let seek = Request::Seek {
+            position: None,
+            offset: Some(-5.0),
+        };
+        assert_eq!(
+            seek.command().unwrap(),
+            Some(Command::Seek(Amount::Down(Duration::from_secs(5))))
+        );
+        let volume = Request::Volume {
+            volume: None,
+            offset: Some(300),
+        };
+        assert_eq!(
+            volume.command().unwrap(),
+            Some(Command::Volume(Amount::Up(100)))
+        );
+        let both = Request::Seek {
+            position: Some(1.0),
+            offset: Some(1.0),
+        };
+        assert!(both.command().is_err());
+        assert_eq!(Request::Toggle.command().unwrap(), None);
+    }
+
+    #[test]
+    fn responses_and_events_round_trip() {
+        let mut response = Response::ok("Seeked to 1:30");
+        response.id = Some(Value::from(1));
+        let line = serde_json::to_string(&response).unwrap();
+        assert_eq!(line, r#"{"id":1,"ok":true,"message":"Seeked to 1:30"}"#);
+        let parsed: Response = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.id, Some(Value::from(1)));
+        assert_eq!(parsed.message.as_deref(), Some("Seeked to 1:30"));
+
+        let error = Response::error(&anyhow::anyhow!("No tracks loaded"));
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"ok":false,"error":"No tracks loaded"}"#
+        );
+
+        let line = serde_json::to_string(&Response::status(status())).unwrap();
+        assert_eq!(
+            line,
+            r#"{"ok":true,"status":{"state":"paused","track":{"path":"/music/a.flac","title":"A","artist":"Low","album":null,"duration":200.0},"position":1.5,"volume":80,"shuffle":true,"repeat":"all","index":2,"tracks":7,"queue":1}}"#
+        );
+        let parsed: Response = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.status.unwrap().track, Some(track()));
+
+        let events = [
+            (
+                Event::Hello {
+                    protocol: PROTOCOL_VERSION,
+                    version: "0.1.0".to_string(),
+                },
+                r#"{"event":"hello","protocol":1,"version":"0.1.0"}"#,
+            ),
+            (
+                Event::TrackChanged { track: track() },
+                r#"{"event":"track_changed","track":{"path":"/music/a.flac","title":"A","artist":"Low","album":null,"duration":200.0}}"#,
+            ),
+            (Event::Playing, r#"{"event":"playing"}"#),
+            (Event::Paused, r#"{"event":"paused"}"#),
+            (Event::Stopped, r#"{"event":"stopped"}"#),
+            (
+                Event::Position {
+                    position: 12.0,
+                    duration: None,
+                },
+                r#"{"event":"position","position":12.0,"duration":null}"#,
+            ),
+        ];
+        for (event, line) in events {
+            assert_eq!(serde_json::to_string(&event).unwrap(), line);
+            let parsed: Event = serde_json::from_str(line).unwrap();
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", event));
+        }
+    }
+
+    #[test]
+    fn server_greets_answers_and_publishes_over_the_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run").join(SOCKET_FILE);
+        let mut server = Server::start(&path).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        // A second player can't take the socket over
+        assert!(Server::start(&path).is_err());
+
+        // The first line is the hello event with the protocol version
+        let stream = UnixStream::connect(&path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut read_line = || {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            serde_json::from_str::<Value>(&line).unwrap()
+        };
+        let hello = read_line();
+        assert_eq!(hello["event"], "hello");
+        assert_eq!(hello["protocol"], PROTOCOL_VERSION);
+
+        // Whatever id a request has comes back with its response
+        writeln!(writer, r#"{{"id": "abc", "command": "next"}}"#).unwrap();
+        writeln!(writer, r#"{{"id": [1, 2], "command": "stop"}}"#).unwrap();
+        writeln!(writer, r#"{{"command": "play"}}"#).unwrap();
+        answer(&mut server, 3);
+        let next = read_line();
+        assert_eq!(next["id"], "abc");
+        assert_eq!(next["ok"], true);
+        assert_eq!(next["message"], "Next");
+        assert_eq!(read_line()["id"], serde_json::json!([1, 2]));
+        assert!(read_line().get("id").is_none());
+
+        // Lines that aren't requests are answered with an error straight away
+        writeln!(writer, r#"{{"id": 4, "command": "dance"}}"#).unwrap();
+        let invalid = read_line();
+        assert_eq!(invalid["ok"], false);
+        assert!(
+            invalid["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("Invalid request")
+        );
+
+        // Subscribed connections receive events
+        writeln!(writer, r#"{{"id": 5, "command": "subscribe"}}"#).unwrap();
+        answer(&mut server, 1);
+        assert_eq!(read_line()["message"], "Subscribed");
+        server.publish(&Event::Paused);
+        assert_eq!(read_line()["event"], "paused");
+
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn connection_matches_responses_by_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SOCKET_FILE);
+        let mut server = Server::start(&path).unwrap();
+
+        let client = thread::spawn({
+            let path = path.clone();
+            move || {
+                let mut connection = Connection::open(&path).unwrap();
+                let status = connection.request(Request::Status).unwrap().status;
+                let error = connection
+                    .request(Request::Exec {
+                        line: "fail".to_string(),
+                    })
+                    .err();
+                connection.request(Request::Subscribe).unwrap();
+                (status, error, connection)
+            }
+        });
+        answer(&mut server, 3);
+        server.publish(&Event::Stopped);
+        let (status, error, mut connection) = client.join().unwrap();
+        assert_eq!(status.unwrap().index, 2);
+        assert_eq!(error.unwrap().to_string(), "Unknown command 'fail'");
+        assert!(matches!(
+            connection.next_event().unwrap(),
+            Some(Event::Stopped)
+        ));
+    }
+
+    #[test]
+    fn connection_refuses_another_protocol_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SOCKET_FILE);
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || {
+            for greeting in [
+                r#"{"event":"hello","protocol":2,"version":"9.0.0"}"#,
+                r#"{"event":"playing"}"#,
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                writeln!(stream, "{}", greeting).unwrap();
+            }
+        });
+
+        let error = Connection::open(&path).err().unwrap().to_string();
+        assert_eq!(
+            error,
+            "The player (version 9.0.0) speaks protocol 2, but this client speaks 1"
+        );
+        let error = Connection::open(&path).err().unwrap().to_string();
+        assert_eq!(error, "The player didn't greet the connection");
+    }
+
+    #[test]
+    fn socket_directories_others_can_write_to_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        fs::set_permissions(&shared, Permissions::from_mode(0o777)).unwrap();
+        let error = Server::start(&shared.join(SOCKET_FILE)).err().unwrap();
+        assert!(error.to_string().ends_with("is writable by other users"));
+
+        // Shared directories with the sticky bit, like /tmp, are fine
+        fs::set_permissions(&shared, Permissions::from_mode(0o1777)).unwrap();
+        assert!(Server::start(&shared.join(SOCKET_FILE)).is_ok());
+    }
+}
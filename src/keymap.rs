@@ -0,0 +1,939 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{Result, bail};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A user-triggerable action in the TUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Down,
+    Up,
+    GoTop,
+    GoBottom,
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+    JumpToPlaying,
+    CenterPlaying,
+    SetMark,
+    JumpToMark,
+    VisualMode,
+    ToggleSelect,
+    Enqueue,
+    RemoveTracks,
+    AddToPlaylist,
+    EditTags,
+    TrashTracks,
+    PlayPause,
+    PlaySelected,
+    NextTrack,
+    PreviousTrack,
+    ToggleShuffle,
+    CycleRepeat,
+    Stop,
+    CycleSort,
+    ReverseSort,
+    ToggleTimeDisplay,
+    ToggleVisualizer,
+    ToggleLyrics,
+    ToggleDetails,
+    ToggleMessages,
+    ToggleLog,
+    CycleLogLevel,
+    CommandLine,
+    ToggleHelp,
+}
+
+/// Groups actions under a heading in the help overlay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Navigation,
+    Selection,
+    Playback,
+    Modes,
+    Other,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [
+        Category::Navigation,
+        Category::Selection,
+        Category::Playback,
+        Category::Modes,
+        Category::Other,
+    ];
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Category::Navigation => write!(f, "Navigation"),
+            Category::Selection => write!(f, "Selection"),
+            Category::Playback => write!(f, "Playback"),
+            Category::Modes => write!(f, "Modes"),
+            Category::Other => write!(f, "Other"),
+        }
+    }
+}
+
+/// Config name, help text, category and default keys of an action
+struct ActionInfo {
+    action: Action,
+    name: &'static str,
+    description: &'static str,
+    category: Category,
+    keys: &'static [&'static str],
+}
+
+impl Action {
+    /// All actions in the order they are listed in the help overlay
+    const INFO: &[ActionInfo] = &[
+        ActionInfo {
+            action: Action::Down,
+            name: "down",
+            description: "Move down in track list",
+            category: Category::Navigation,
+            keys: &["j", "down"],
+        },
+        ActionInfo {
+            action: Action::Up,
+            name: "up",
+            description: "Move up in track list",
+            category: Category::Navigation,
+            keys: &["k", "up"],
+        },
+        ActionInfo {
+            action: Action::GoTop,
+            name: "go_top",
+            description: "Go to first track (or track N with a count)",
+            category: Category::Navigation,
+            keys: &["g g", "home"],
+        },
+        ActionInfo {
+            action: Action::GoBottom,
+            name: "go_bottom",
+            description: "Go to last track (or track N with a count)",
+            category: Category::Navigation,
+            keys: &["G", "end"],
+        },
+        ActionInfo {
+            action: Action::HalfPageDown,
+            name: "half_page_down",
+            description: "Scroll down half a page",
+            category: Category::Navigation,
+            keys: &["ctrl-d"],
+        },
+        ActionInfo {
+            action: Action::HalfPageUp,
+            name: "half_page_up",
+            description: "Scroll up half a page",
+            category: Category::Navigation,
+            keys: &["ctrl-u"],
+        },
+        ActionInfo {
+            action: Action::PageDown,
+            name: "page_down",
+            description: "Scroll down a page",
+            category: Category::Navigation,
+            keys: &["ctrl-f", "pagedown"],
+        },
+        ActionInfo {
+            action: Action::PageUp,
+            name: "page_up",
+            description: "Scroll up a page",
+            category: Category::Navigation,
+            keys: &["ctrl-b", "pageup"],
+        },
+        ActionInfo {
+            action: Action::JumpToPlaying,
+            name: "jump_to_playing",
+            description: "Select the playing track",
+            category: Category::Navigation,
+            keys: &["g p"],
+        },
+        ActionInfo {
+            action: Action::CenterPlaying,
+            name: "center_playing",
+            description: "Select the playing track and center it",
+            category: Category::Navigation,
+            keys: &["z z"],
+        },
+        ActionInfo {
+            action: Action::SetMark,
+            name: "set_mark",
+            description: "Mark selected track (followed by a-z)",
+            category: Category::Navigation,
+            keys: &["m"],
+        },
+        ActionInfo {
+            action: Action::JumpToMark,
+            name: "jump_to_mark",
+            description: "Jump to marked track (followed by a-z)",
+            category: Category::Navigation,
+            keys: &["'"],
+        },
+        ActionInfo {
+            action: Action::VisualMode,
+            name: "visual_mode",
+            description: "Start or end selecting a range",
+            category: Category::Selection,
+            keys: &["V"],
+        },
+        ActionInfo {
+            action: Action::ToggleSelect,
+            name: "toggle_select",
+            description: "Select or deselect track and move down",
+            category: Category::Selection,
+            keys: &["x"],
+        },
+        ActionInfo {
+            action: Action::Enqueue,
+            name: "enqueue",
+            description: "Queue selected tracks to play next",
+            category: Category::Selection,
+            keys: &["a"],
+        },
+        ActionInfo {
+            action: Action::RemoveTracks,
+            name: "remove_tracks",
+            description: "Remove selected tracks from the list",
+            category: Category::Selection,
+            keys: &["d"],
+        },
+        ActionInfo {
+            action: Action::AddToPlaylist,
+            name: "add_to_playlist",
+            description: "Add selected tracks to a playlist file",
+            category: Category::Selection,
+            keys: &["P"],
+        },
+        ActionInfo {
+            action: Action::EditTags,
+            name: "edit_tags",
+            description: "Edit a tag of the selected tracks",
+            category: Category::Selection,
+            keys: &["T"],
+        },
+        ActionInfo {
+            action: Action::TrashTracks,
+            name: "trash_tracks",
+            description: "Move selected files to the trash",
+            category: Category::Selection,
+            keys: &["delete"],
+        },
+        ActionInfo {
+            action: Action::PlayPause,
+            name: "play_pause",
+            description: "Play selected track or pause/unpause",
+            category: Category::Playback,
+            keys: &["space"],
+        },
+        ActionInfo {
+            action: Action::PlaySelected,
+            name: "play_selected",
+            description: "Play selected track",
+            category: Category::Playback,
+            keys: &["enter"],
+        },
+        ActionInfo {
+            action: Action::NextTrack,
+            name: "next_track",
+            description: "Next track",
+            category: Category::Playback,
+            keys: &["n"],
+        },
+        ActionInfo {
+            action: Action::PreviousTrack,
+            name: "previous_track",
+            description: "Previous track",
+            category: Category::Playback,
+            keys: &["p"],
+        },
+        ActionInfo {
+            action: Action::Stop,
+            name: "stop",
+            description: "Stop playback",
+            category: Category::Playback,
+            keys: &["S"],
+        },
+        ActionInfo {
+            action: Action::ToggleShuffle,
+            name: "toggle_shuffle",
+            description: "Toggle shuffle",
+            category: Category::Modes,
+            keys: &["s"],
+        },
+        ActionInfo {
+            action: Action::CycleRepeat,
+            name: "cycle_repeat",
+            description: "Cycle repeat mode (Off/One/All)",
+            category: Category::Modes,
+            keys: &["r"],
+        },
+        ActionInfo {
+            action: Action::CycleSort,
+            name: "cycle_sort",
+            description: "Cycle sort order",
+            category: Category::Modes,
+            keys: &["o"],
+        },
+        ActionInfo {
+            action: Action::ReverseSort,
+            name: "reverse_sort",
+            description: "Reverse sort direction",
+            category: Category::Modes,
+            keys: &["O"],
+        },
+        ActionInfo {
+            action: Action::ToggleTimeDisplay,
+            name: "toggle_time_display",
+            description: "Show elapsed or remaining time",
+            category: Category::Modes,
+            keys: &["t"],
+        },
+        ActionInfo {
+            action: Action::ToggleVisualizer,
+            name: "toggle_visualizer",
+            description: "Toggle spectrum visualizer",
+            category: Category::Modes,
+            keys: &["v"],
+        },
+        ActionInfo {
+            action: Action::ToggleLyrics,
+            name: "toggle_lyrics",
+            description: "Toggle lyrics pane",
+            category: Category::Modes,
+            keys: &["L"],
+        },
+        ActionInfo {
+            action: Action::ToggleDetails,
+            name: "toggle_details",
+            description: "Toggle track details",
+            category: Category::Modes,
+            keys: &["i"],
+        },
+        ActionInfo {
+            action: Action::Quit,
+            name: "quit",
+            description: "Quit",
+            category: Category::Other,
+            keys: &["q", "esc"],
+        },
+        ActionInfo {
+            action: Action::ToggleMessages,
+            name: "toggle_messages",
+            description: "Show message history",
+            category: Category::Other,
+            keys: &["M"],
+        },
+        ActionInfo {
+            action: Action::ToggleLog,
+            name: "toggle_log",
+            description: "Toggle log pane",
+            category: Category::Other,
+            keys: &["D"],
+        },
+        ActionInfo {
+            action: Action::CycleLogLevel,
+            name: "cycle_log_level",
+            description: "Cycle log pane level filter",
+            category: Category::Other,
+            keys: &["F"],
+        },
+        ActionInfo {
+            action: Action::CommandLine,
+            name: "command_line",
+            description: "Open the command line",
+            category: Category::Other,
+            keys: &[":"],
+        },
+        ActionInfo {
+            action: Action::ToggleHelp,
+            name: "toggle_help",
+            description: "Toggle this help",
+            category: Category::Other,
+            keys: &["?", "h"],
+        },
+    ];
+
+    fn info(self) -> &'static ActionInfo {
+        Self::INFO
+            .iter()
+            .find(|info| info.action == self)
+            .expect("every action has info")
+    }
+
+    fn from_name(name: &str) -> Option<Action> {
+        Self::INFO
+            .iter()
+            .find(|info| info.name == name)
+            .map(|info| info.action)
+    }
+
+    /// Short description shown in the help overlay
+    pub fn description(self) -> &'static str {
+        self.info().description
+    }
+}
+
+/// A single key press with its modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyPress {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyPress {
+    /// Normalize a terminal key event so it can be compared against bindings
+    pub fn from_event(event: KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        // Shift is already reflected in the character itself ('S' vs 's')
+        let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        Self { code, modifiers }
+    }
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::Enter => write!(f, "Enter"),
+            KeyCode::Esc => write!(f, "Esc"),
+            KeyCode::Tab => write!(f, "Tab"),
+            KeyCode::Backspace => write!(f, "Backspace"),
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::Left => write!(f, "←"),
+            KeyCode::Right => write!(f, "→"),
+            KeyCode::Home => write!(f, "Home"),
+            KeyCode::End => write!(f, "End"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            KeyCode::Delete => write!(f, "Del"),
+            KeyCode::Insert => write!(f, "Ins"),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            _ => write!(f, "?"),
+        }
+    }
+}
+
+/// Result of looking up the keys typed so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    /// The keys form a complete binding
+    Action(Action),
+    /// The keys are the start of at least one longer binding
+    Pending,
+    /// No binding starts with these keys
+    Unbound,
+}
+
+/// Largest count accepted before a command, like the 5 in `5j`
+const MAX_COUNT: usize = 9999;
+
+/// What a key typed in the track list amounts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// Part of a count or key sequence, or nothing at all
+    Pending,
+    /// A complete binding, with the count typed before it
+    Action(Action, Option<usize>),
+    /// The key after a mark command, naming the mark when it is a-z
+    Mark(Action, Option<char>),
+}
+
+/// Count, keys and mark command typed so far in the track list
+#[derive(Debug, Default)]
+pub struct PendingInput {
+    /// Count typed before a command, like the 5 in `5j`
+    count: Option<usize>,
+    keys: Vec<KeyPress>,
+    /// Mark command waiting for the mark name that follows it
+    mark: Option<Action>,
+}
+
+impl PendingInput {
+    pub fn is_empty(&self) -> bool {
+        self.count.is_none() && self.keys.is_empty() && self.mark.is_none()
+    }
+
+    /// Count and keys typed so far, for the controls title
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(count) = self.count {
+            parts.push(count.to_string());
+        }
+        if !self.keys.is_empty() {
+            parts.push(format_sequence(&self.keys));
+        }
+        parts.join(" ")
+    }
+
+    /// Take one key, returning the action or mark it completes
+    pub fn feed(&mut self, key: KeyEvent, keymap: &Keymap) -> Input {
+        // Esc abandons a half-typed command instead of quitting
+        if key.code == KeyCode::Esc && !self.is_empty() {
+            *self = Self::default();
+            return Input::Pending;
+        }
+
+        // The key after `m` or `'` names the mark
+        if let Some(action) = self.mark.take() {
+            *self = Self::default();
+            let name = match key.code {
+                KeyCode::Char(name @ 'a'..='z')
+                    if !key
+                        .modifiers
+                        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+                {
+                    Some(name)
+                }
+                _ => None,
+            };
+            return Input::Mark(action, name);
+        }
+
+        // Digits typed before a command form its count, unless they are bound themselves
+        let press = KeyPress::from_event(key);
+        if self.keys.is_empty()
+            && press.modifiers.is_empty()
+            && let KeyCode::Char(c) = press.code
+            && let Some(digit) = c.to_digit(10)
+            && (digit != 0 || self.count.is_some())
+            && keymap.lookup(&[press]) == Lookup::Unbound
+        {
+            let count = self.count.unwrap_or(0) * 10 + digit as usize;
+            self.count = Some(count.min(MAX_COUNT));
+            return Input::Pending;
+        }
+
+        self.keys.push(press);
+        let action = match keymap.lookup(&self.keys) {
+            Lookup::Action(action) => action,
+            Lookup::Pending => return Input::Pending,
+            Lookup::Unbound if self.keys.len() > 1 => {
+                // An abandoned sequence shouldn't swallow the key that broke it
+                self.keys = vec![press];
+                match keymap.lookup(&self.keys) {
+                    Lookup::Action(action) => action,
+                    Lookup::Pending => return Input::Pending,
+                    Lookup::Unbound => {
+                        self.keys.clear();
+                        return Input::Pending;
+                    }
+                }
+            }
+            Lookup::Unbound => {
+                self.keys.clear();
+                return Input::Pending;
+            }
+        };
+
+        if matches!(action, Action::SetMark | Action::JumpToMark) {
+            // Keep the keys shown in the controls title until the mark name is typed
+            self.mark = Some(action);
+            return Input::Pending;
+        }
+        self.keys.clear();
+        Input::Action(action, self.count.take())
+    }
+}
+
+/// A help overlay heading with its actions and their bound keys
+pub type HelpSection = (Category, Vec<(Action, Vec<String>)>);
+
+/// Mapping from key sequences to actions
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<(Vec<KeyPress>, Action)>,
+}
+
+impl Keymap {
+    /// Build the keymap from the defaults, replacing the keys of any action listed in `overrides`
+    ///
+    /// Unknown actions, unparseable keys and conflicting bindings are all reported together.
+    pub fn new(overrides: &BTreeMap<String, Vec<String>>) -> Result<Self> {
+        let mut errors = Vec::new();
+
+        for name in overrides.keys() {
+            if Action::from_name(name).is_none() {
+                errors.push(format!("keys.{}: unknown action", name));
+            }
+        }
+
+        let mut bindings: Vec<(Vec<KeyPress>, Action)> = Vec::new();
+        for info in Action::INFO {
+            let keys: Vec<&str> = match overrides.get(info.name) {
+                Some(keys) => keys.iter().map(String::as_str).collect(),
+                None => info.keys.to_vec(),
+            };
+
+            for key in keys {
+                let Some(sequence) = parse_sequence(key) else {
+                    errors.push(format!("keys.{}: invalid key '{}'", info.name, key));
+                    continue;
+                };
+
+                for (existing, action) in &bindings {
+                    if *existing == sequence {
+                        if *action != info.action {
+                            errors.push(format!(
+                                "keys.{}: '{}' is already bound to {}",
+                                info.name,
+                                key,
+                                action.info().name
+                            ));
+                        }
+                    } else if existing.starts_with(&sequence) || sequence.starts_with(existing) {
+                        errors.push(format!(
+                            "keys.{}: '{}' overlaps with '{}' bound to {}",
+                            info.name,
+                            key,
+                            format_sequence(existing),
+                            action.info().name
+                        ));
+                    }
+                }
+
+                bindings.push((sequence, info.action));
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"));
+        }
+
+        Ok(Self { bindings })
+    }
+
+    /// Look up the keys typed so far
+    pub fn lookup(&self, keys: &[KeyPress]) -> Lookup {
+        let mut pending = false;
+        for (sequence, action) in &self.bindings {
+            if sequence == keys {
+                return Lookup::Action(*action);
+            }
+            if sequence.starts_with(keys) {
+                pending = true;
+            }
+        }
+
+        if pending {
+            Lookup::Pending
+        } else {
+            Lookup::Unbound
+        }
+    }
+
+    /// Get the key sequences bound to an action, formatted for display
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        self.bindings
+            .iter()
+            .filter(|(_, a)| *a == action)
+            .map(|(sequence, _)| format_sequence(sequence))
+            .collect()
+    }
+
+    /// Get every action with its bound keys, grouped by category in help order
+    pub fn help(&self) -> Vec<HelpSection> {
+        Category::ALL
+            .iter()
+            .map(|category| {
+                let actions = Action::INFO
+                    .iter()
+                    .filter(|info| info.category == *category)
+                    .map(|info| (info.action, self.keys_for(info.action)))
+                    .filter(|(_, keys)| !keys.is_empty())
+                    .collect();
+                (*category, actions)
+            })
+            .collect()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(&BTreeMap::new()).expect("default keymap is valid")
+    }
+}
+
+/// Format a key sequence for display, e.g. `g g` or `Ctrl-d`
+pub fn format_sequence(sequence: &[KeyPress]) -> String {
+    sequence
+        .iter()
+        .map(|key| key.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Parse a key sequence such as `j`, `ctrl-d`, `g g` or `gg`
+///
+/// Keys are separated by spaces. A word that is not a key name is only read as one key per
+/// character when it repeats a single character, like `gg`, so a misspelt name such as
+/// `pagdown` is reported rather than bound to seven keys.
+fn parse_sequence(keys: &str) -> Option<Vec<KeyPress>> {
+    // A lone space is the space bar rather than an empty sequence
+    if keys == " " {
+        return Some(vec![KeyPress::new(
+            KeyCode::Char(' '),
+            KeyModifiers::empty(),
+        )]);
+    }
+
+    let mut sequence = Vec::new();
+    for word in keys.split_whitespace() {
+        match parse_key(word) {
+            Some(key) => sequence.push(key),
+            None => {
+                let first = word.chars().next()?;
+                if first.is_control() || word.chars().any(|c| c != first) {
+                    return None;
+                }
+                for c in word.chars() {
+                    sequence.push(KeyPress::new(KeyCode::Char(c), KeyModifiers::empty()));
+                }
+            }
+        }
+    }
+
+    if sequence.is_empty() {
+        None
+    } else {
+        Some(sequence)
+    }
+}
+
+/// Parse a single key such as `j`, `space`, `enter`, `f5` or `ctrl-d`
+fn parse_key(key: &str) -> Option<KeyPress> {
+    let mut modifiers = KeyModifiers::empty();
+    let mut rest = key;
+
+    // Peel off modifier prefixes, leaving a lone "-" as the minus key
+    while let Some((prefix, tail)) = rest.split_once('-').filter(|(_, tail)| !tail.is_empty()) {
+        match prefix.to_lowercase().as_str() {
+            "ctrl" | "c" => modifiers |= KeyModifiers::CONTROL,
+            "alt" | "a" | "m" => modifiers |= KeyModifiers::ALT,
+            _ => return None,
+        }
+        rest = tail;
+    }
+
+    let mut chars = rest.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(KeyPress::new(KeyCode::Char(c), modifiers));
+    }
+
+    let code = match rest.to_lowercase().as_str() {
+        "space" => KeyCode::Char(' '),
+        "enter" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        name => {
+            let n = name.strip_prefix('f')?.parse().ok()?;
+            if !(1..=12).contains(&n) {
+                return None;
+            }
+            KeyCode::F(n)
+        }
+    };
+
+    Some(KeyPress::new(code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(c: char) -> KeyPress {
+        KeyPress::new(KeyCode::Char(c), KeyModifiers::empty())
+    }
+
+    /// Feed typed characters to fresh pending input, returning what each one amounted to
+    fn type_keys(keymap: &Keymap, keys: &str) -> (PendingInput, Vec<Input>) {
+        let mut pending = PendingInput::default();
+        let inputs = keys
+            .chars()
+            .map(|c| pending.feed(KeyEvent::from(KeyCode::Char(c)), keymap))
+            .collect();
+        (pending, inputs)
+    }
+
+    /// The last input from typing some characters
+    fn last(keymap: &Keymap, keys: &str) -> Input {
+        *type_keys(keymap, keys).1.last().unwrap()
+    }
+
+    #[test]
+    fn sequences_are_split_on_spaces() {
+        assert_eq!(parse_sequence("g p"), Some(vec![key('g'), key('p')]));
+        assert_eq!(
+            parse_sequence("ctrl-w  pagedown"),
+            Some(vec![
+                KeyPress::new(KeyCode::Char('w'), KeyModifiers::CONTROL),
+                KeyPress::new(KeyCode::PageDown, KeyModifiers::empty()),
+            ])
+        );
+        assert_eq!(parse_sequence(" "), Some(vec![key(' ')]));
+        assert_eq!(parse_sequence("-"), Some(vec![key('-')]));
+        assert_eq!(parse_sequence(""), None);
+    }
+
+    #[test]
+    fn repeated_characters_need_no_spaces() {
+        assert_eq!(parse_sequence("gg"), Some(vec![key('g'), key('g')]));
+        assert_eq!(
+            parse_sequence("zzz"),
+            Some(vec![key('z'), key('z'), key('z')])
+        );
+        assert_eq!(parse_sequence("ëë"), Some(vec![key('ë'), key('ë')]));
+        assert_eq!(parse_sequence("--"), Some(vec![key('-'), key('-')]));
+    }
+
+    #[test]
+    fn unknown_key_names_are_rejected() {
+        for keys in [
+            "pagdown",
+            "enterr",
+            "gp",
+            "g pq",
+            "ctrl-pagdown",
+            "shift-x",
+            "f99",
+        ] {
+            assert_eq!(parse_sequence(keys), None, "{}", keys);
+        }
+
+        let overrides = BTreeMap::from([("page_down".to_string(), vec!["pagdown".to_string()])]);
+        let error = Keymap::new(&overrides).err().unwrap().to_string();
+        assert!(
+            error.contains("keys.page_down: invalid key 'pagdown'"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn counts_are_passed_with_the_action() {
+        let keymap = Keymap::default();
+        assert_eq!(last(&keymap, "j"), Input::Action(Action::Down, None));
+        assert_eq!(last(&keymap, "5j"), Input::Action(Action::Down, Some(5)));
+        assert_eq!(last(&keymap, "12k"), Input::Action(Action::Up, Some(12)));
+        assert_eq!(
+            last(&keymap, "123456j"),
+            Input::Action(Action::Down, Some(MAX_COUNT))
+        );
+        // A leading zero isn't a count
+        assert_eq!(last(&keymap, "0j"), Input::Action(Action::Down, None));
+
+        let (pending, _) = type_keys(&keymap, "5j");
+        assert!(pending.is_empty());
+        let (pending, _) = type_keys(&keymap, "42g");
+        assert_eq!(pending.describe(), "42 g");
+    }
+
+    #[test]
+    fn sequences_complete_with_their_count() {
+        let keymap = Keymap::default();
+        let (_, inputs) = type_keys(&keymap, "gg");
+        assert_eq!(inputs, [Input::Pending, Input::Action(Action::GoTop, None)]);
+        assert_eq!(
+            last(&keymap, "3G"),
+            Input::Action(Action::GoBottom, Some(3))
+        );
+        assert_eq!(last(&keymap, "3gg"), Input::Action(Action::GoTop, Some(3)));
+    }
+
+    #[test]
+    fn abandoned_sequences_dispatch_the_breaking_key() {
+        let keymap = Keymap::default();
+        let (pending, inputs) = type_keys(&keymap, "2gj");
+        assert_eq!(
+            inputs,
+            [
+                Input::Pending,
+                Input::Pending,
+                Input::Action(Action::Down, Some(2))
+            ]
+        );
+        assert!(pending.is_empty());
+
+        // The breaking key may start a sequence of its own
+        let (pending, inputs) = type_keys(&keymap, "zg");
+        assert_eq!(inputs, [Input::Pending, Input::Pending]);
+        assert_eq!(pending.describe(), "g");
+        assert_eq!(
+            last(&keymap, "zgp"),
+            Input::Action(Action::JumpToPlaying, None)
+        );
+    }
+
+    #[test]
+    fn marks_take_the_next_key_as_their_name() {
+        let keymap = Keymap::default();
+        let (pending, inputs) = type_keys(&keymap, "ma");
+        assert_eq!(
+            inputs,
+            [Input::Pending, Input::Mark(Action::SetMark, Some('a'))]
+        );
+        assert!(pending.is_empty());
+        assert_eq!(
+            last(&keymap, "'a"),
+            Input::Mark(Action::JumpToMark, Some('a'))
+        );
+        assert_eq!(
+            last(&keymap, "3'z"),
+            Input::Mark(Action::JumpToMark, Some('z'))
+        );
+        assert_eq!(last(&keymap, "mA"), Input::Mark(Action::SetMark, None));
+        assert_eq!(last(&keymap, "m1"), Input::Mark(Action::SetMark, None));
+        // The key after an invalid name is handled as usual
+        let (_, inputs) = type_keys(&keymap, "m1j");
+        assert_eq!(inputs[2], Input::Action(Action::Down, None));
+    }
+
+    #[test]
+    fn escape_abandons_pending_input() {
+        let keymap = Keymap::default();
+        let mut pending = PendingInput::default();
+        for c in ['4', 'g'] {
+            pending.feed(KeyEvent::from(KeyCode::Char(c)), &keymap);
+        }
+        assert_eq!(
+            pending.feed(KeyEvent::from(KeyCode::Esc), &keymap),
+            Input::Pending
+        );
+        assert!(pending.is_empty());
+        assert_eq!(
+            pending.feed(KeyEvent::from(KeyCode::Char('g')), &keymap),
+            Input::Pending
+        );
+        assert_eq!(
+            pending.feed(KeyEvent::from(KeyCode::Char('g')), &keymap),
+            Input::Action(Action::GoTop, None)
+        );
+    }
+}
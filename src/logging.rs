@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use env_logger::Env;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Log filter used for the log file and log pane when `RUST_LOG` is unset
+const DEFAULT_FILTER: &str = "warn,music_player=info";
+
+/// Size at which the log file is rotated, in bytes
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+/// Number of rotated log files kept next to the current one
+const ROTATED_LOGS: usize = 3;
+
+/// Number of records kept for the in-app log pane
+const BUFFER_CAPACITY: usize = 1000;
+
+/// A log record kept for the log pane
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub level: Level,
+    /// Local wall-clock time, as `HH:MM:SS`
+    pub time: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// Ring buffer of recent log records shared with the UI
+pub struct LogBuffer {
+    entries: Mutex<VecDeque<LogEntry>>,
+}
+
+impl LogBuffer {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(BUFFER_CAPACITY)),
+        }
+    }
+
+    fn push(&self, entry: LogEntry) {
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() == BUFFER_CAPACITY {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+
+    /// The newest `count` records at `level` or more severe, oldest first
+    pub fn tail(&self, level: LevelFilter, count: usize) -> Vec<LogEntry> {
+        let Ok(entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        let mut tail: Vec<LogEntry> = entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.level <= level)
+            .take(count)
+            .cloned()
+            .collect();
+        tail.reverse();
+        tail
+    }
+}
+
+/// Log file that is renamed to `.1`, `.2`, ... once it grows too large
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create log directory: {}", dir.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open log file: {}", path.display()))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self { path, file, size })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.size + line.len() as u64 > MAX_LOG_SIZE && self.rotate().is_err() {
+            // Keep writing to the current file rather than losing records
+            self.size = 0;
+        }
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        for n in (1..ROTATED_LOGS).rev() {
+            let from = rotated(n);
+            if from.exists() {
+                fs::rename(&from, rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated(1))?;
+        *self = Self::open(self.path.clone())?;
+        Ok(())
+    }
+}
+
+/// Routes records to the log file, the log pane buffer and optionally stderr
+struct Logger {
+    filter: env_logger::Logger,
+    file: Option<Mutex<RotatingFile>>,
+    buffer: Arc<LogBuffer>,
+    /// Plain `env_logger` output, used when no TUI owns the terminal
+    stderr: Option<env_logger::Logger>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata) || self.stderr.as_ref().is_some_and(|s| s.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(stderr) = &self.stderr {
+            stderr.log(record);
+        }
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let now = jiff::Zoned::now();
+        let message = record.args().to_string();
+
+        if let Some(file) = &self.file
+            && let Ok(mut file) = file.lock()
+        {
+            file.write_line(&format!(
+                "{} {:<5} {}: {}\n",
+                now.strftime("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+                record.level(),
+                record.target(),
+                message
+            ));
+        }
+
+        self.buffer.push(LogEntry {
+            level: record.level(),
+            time: now.strftime("%H:%M:%S").to_string(),
+            target: record.target().to_string(),
+            message,
+        });
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file
+            && let Ok(mut file) = file.lock()
+        {
+            let _ = file.file.flush();
+        }
+        if let Some(stderr) = &self.stderr {
+            stderr.flush();
+        }
+    }
+}
+
+/// Default location of the log file in the XDG state directory
+pub fn log_path() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("music_player").join("music_player.log"))
+}
+
+/// Install the global logger and return the buffer feeding the log pane
+///
+/// Records go to a rotating log file and the in-app buffer, filtered by `RUST_LOG`
+/// (`warn,music_player=info` by default). With `stderr`, records are also written to
+/// stderr as plain `env_logger` output; only do that when no TUI owns the terminal.
+pub fn init(stderr: bool) -> Arc<LogBuffer> {
+    let buffer = Arc::new(LogBuffer::new());
+
+    let file = log_path().and_then(|path| match RotatingFile::open(path) {
+        Ok(file) => Some(Mutex::new(file)),
+        Err(e) => {
+            // The logger isn't installed yet, so this is the only place to report it
+            if stderr {
+                eprintln!("Warning: {:#}", e);
+            }
+            None
+        }
+    });
+
+    let logger = Logger {
+        filter: env_logger::Builder::from_env(Env::default().default_filter_or(DEFAULT_FILTER))
+            .build(),
+        file,
+        buffer: buffer.clone(),
+        stderr: stderr.then(|| env_logger::Builder::from_default_env().build()),
+    };
+
+    let max_level = logger
+        .stderr
+        .as_ref()
+        .map_or(LevelFilter::Off, |s| s.filter())
+        .max(logger.filter.filter());
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+
+    buffer
+}
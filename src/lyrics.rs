@@ -0,0 +1,404 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use id3::frame::TimestampFormat;
+use log::warn;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Style;
+use ratatui::text::Line;
+use ratatui::widgets::{Paragraph, Widget, Wrap};
+
+use crate::metadata;
+
+/// Keys of LRC ID tags such as `[ar:Artist]`; other bracketed text is part of the lyrics
+const ID_TAGS: &[&str] = &[
+    "ar", "ti", "al", "au", "by", "offset", "length", "re", "ve", "#",
+];
+
+/// One line of lyrics, with its start time when synchronized
+#[derive(Debug, Clone)]
+pub struct LyricLine {
+    pub time: Option<Duration>,
+    pub text: String,
+}
+
+/// Lyrics for a track, either time-synchronized or static text
+#[derive(Debug, Clone)]
+pub struct Lyrics {
+    pub lines: Vec<LyricLine>,
+    pub synced: bool,
+}
+
+impl Lyrics {
+    /// Load lyrics from a sidecar `.lrc` file, or else from the track's tags
+    ///
+    /// ID3 SYLT frames are preferred over USLT frames and Vorbis `LYRICS` comments, which
+    /// are parsed as LRC in case they carry timestamps.
+    pub fn load(path: &Path) -> Option<Self> {
+        if let Some(text) = read_sidecar(path) {
+            return Some(Self::parse_lrc(&text));
+        }
+
+        if let Some(lyrics) = read_sylt(path) {
+            return Some(lyrics);
+        }
+
+        match metadata::read_lyrics(path) {
+            Ok(Some(text)) => Some(Self::parse_lrc(&text)),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Failed to read lyrics from {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Parse LRC text, falling back to static lyrics when it has no timestamps
+    ///
+    /// Lines may carry several `[mm:ss.xx]` timestamps, and an `[offset:±ms]` tag shifts
+    /// them all; positive offsets show lines earlier. Word timings (`<mm:ss.xx>`) are dropped.
+    pub fn parse_lrc(text: &str) -> Self {
+        let mut offset_ms: i64 = 0;
+        let mut synced = Vec::new();
+        let mut plain = Vec::new();
+
+        for raw in text.lines() {
+            let mut rest = raw.trim();
+            let mut times = Vec::new();
+            let mut is_tag = false;
+
+            while let Some(tag) = rest.strip_prefix('[')
+                && let Some(end) = tag.find(']')
+            {
+                let (content, after) = (&tag[..end], &tag[end + 1..]);
+                if let Some(time) = parse_timestamp(content) {
+                    times.push(time);
+                } else if let Some((key, value)) = content.split_once(':')
+                    && ID_TAGS
+                        .iter()
+                        .any(|tag| key.trim().eq_ignore_ascii_case(tag))
+                {
+                    // Only the offset affects display
+                    if key.trim().eq_ignore_ascii_case("offset") {
+                        offset_ms = value.trim().trim_start_matches('+').parse().unwrap_or(0);
+                    }
+                    is_tag = true;
+                } else {
+                    break;
+                }
+                rest = after;
+            }
+
+            let text = strip_word_timings(rest).trim().to_string();
+            if !times.is_empty() {
+                synced.extend(times.into_iter().map(|time| (time, text.clone())));
+            } else if !is_tag {
+                plain.push(text);
+            }
+        }
+
+        if synced.is_empty() {
+            // Trim blank lines around static lyrics but keep stanza breaks
+            let start = plain
+                .iter()
+                .position(|l| !l.is_empty())
+                .unwrap_or(plain.len());
+            let end = plain
+                .iter()
+                .rposition(|l| !l.is_empty())
+                .map_or(start, |i| i + 1);
+            return Self {
+                lines: plain[start..end]
+                    .iter()
+                    .map(|text| LyricLine {
+                        time: None,
+                        text: text.clone(),
+                    })
+                    .collect(),
+                synced: false,
+            };
+        }
+
+        synced.sort_by_key(|(time, _)| *time);
+        Self {
+            lines: synced
+                .into_iter()
+                .map(|(time, text)| LyricLine {
+                    time: Some(shift(time, offset_ms)),
+                    text,
+                })
+                .collect(),
+            synced: true,
+        }
+    }
+
+    /// Index of the line being sung at a playback position
+    pub fn current_line(&self, position: Duration) -> Option<usize> {
+        if !self.synced {
+            return None;
+        }
+        self.lines
+            .iter()
+            .rposition(|line| line.time.is_some_and(|time| time <= position))
+    }
+
+    /// Widget showing the lyrics, scrolled to keep the current line centered
+    pub fn view(&self, position: Duration, current_style: Style) -> LyricsView<'_> {
+        LyricsView {
+            lyrics: self,
+            current: self.current_line(position),
+            current_style,
+        }
+    }
+}
+
+/// Lyrics pane contents
+pub struct LyricsView<'a> {
+    lyrics: &'a Lyrics,
+    current: Option<usize>,
+    current_style: Style,
+}
+
+impl Widget for LyricsView<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lines: Vec<Line> = self
+            .lyrics
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                if Some(i) == self.current {
+                    Line::styled(line.text.as_str(), self.current_style)
+                } else {
+                    Line::from(line.text.as_str())
+                }
+            })
+            .collect();
+
+        if self.lyrics.synced {
+            // Synced lines are kept on one row each so the scroll position stays exact
+            let scroll = self
+                .current
+                .unwrap_or(0)
+                .saturating_sub(usize::from(area.height) / 2);
+            Paragraph::new(lines)
+                .centered()
+                .scroll((scroll.min(usize::from(u16::MAX)) as u16, 0))
+                .render(area, buf);
+        } else {
+            Paragraph::new(lines)
+                .centered()
+                .wrap(Wrap { trim: true })
+                .render(area, buf);
+        }
+    }
+}
+
+/// Parse an `mm:ss`, `mm:ss.xx` or `mm:ss:xx` LRC timestamp
+fn parse_timestamp(value: &str) -> Option<Duration> {
+    let (minutes, rest) = value.split_once(':')?;
+    let minutes: u64 = minutes.trim().parse().ok()?;
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (rest, ""),
+    };
+    let seconds: u64 = seconds.trim().parse().ok()?;
+    if seconds >= 60 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    // Fractions are hundredths in most files, but accept any precision
+    let millis = fraction
+        .chars()
+        .chain("000".chars())
+        .take(3)
+        .collect::<String>()
+        .parse::<u64>()
+        .ok()?;
+
+    Some(Duration::from_millis(
+        (minutes * 60 + seconds) * 1000 + millis,
+    ))
+}
+
+/// Apply an LRC offset in milliseconds
+fn shift(time: Duration, offset_ms: i64) -> Duration {
+    let shifted = time.as_millis() as i64 - offset_ms;
+    Duration::from_millis(shifted.max(0) as u64)
+}
+
+/// Remove enhanced LRC word timings like `<00:12.34>`
+fn strip_word_timings(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        match rest[start..].find('>') {
+            Some(end) if parse_timestamp(&rest[start + 1..start + end]).is_some() => {
+                out.push_str(&rest[..start]);
+                rest = &rest[start + end + 1..];
+            }
+            _ => {
+                out.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Read `<stem>.lrc` next to a track, matching the extension case-insensitively
+fn read_sidecar(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?;
+    let dir = path.parent()?;
+    let file = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .find(|entry| {
+            let candidate = entry.path();
+            candidate.file_stem() == Some(stem)
+                && candidate
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| e.eq_ignore_ascii_case("lrc"))
+        })?;
+
+    match fs::read(file.path()) {
+        // LRC files in the wild aren't always UTF-8
+        Ok(data) => Some(String::from_utf8_lossy(&data).into_owned()),
+        Err(e) => {
+            warn!(
+                "Failed to read lyrics file {}: {}",
+                file.path().display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Read synchronized lyrics from an ID3 SYLT frame with millisecond timestamps
+fn read_sylt(path: &Path) -> Option<Lyrics> {
+    let tag = id3::Tag::read_from_path(path).ok()?;
+    let sylt = tag
+        .synchronised_lyrics()
+        .find(|sylt| sylt.timestamp_format == TimestampFormat::Ms && !sylt.content.is_empty())?;
+
+    let mut lines: Vec<LyricLine> = sylt
+        .content
+        .iter()
+        .map(|(ms, text)| LyricLine {
+            time: Some(Duration::from_millis(u64::from(*ms))),
+            text: text.trim().to_string(),
+        })
+        .collect();
+    lines.sort_by_key(|line| line.time);
+
+    Some(Lyrics {
+        lines,
+        synced: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn lines(lyrics: &Lyrics) -> Vec<(Option<Duration>, &str)> {
+        lyrics
+            .lines
+            .iter()
+            .map(|line| (line.time, line.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_timestamp_accepts_lrc_formats() {
+        assert_eq!(parse_timestamp("01:02"), Some(ms(62_000)));
+        assert_eq!(parse_timestamp("01:02.5"), Some(ms(62_500)));
+        assert_eq!(parse_timestamp("01:02.34"), Some(ms(62_340)));
+        assert_eq!(parse_timestamp("01:02:34"), Some(ms(62_340)));
+        assert_eq!(parse_timestamp("00:00.1234"), Some(ms(123)));
+        assert_eq!(parse_timestamp("123:00.00"), Some(ms(7_380_000)));
+        assert_eq!(parse_timestamp("01:60.00"), None);
+        assert_eq!(parse_timestamp("01:02.x"), None);
+        assert_eq!(parse_timestamp("ar:Low"), None);
+        assert_eq!(parse_timestamp("0102"), None);
+    }
+
+    #[test]
+    fn shift_moves_lines_earlier_for_positive_offsets() {
+        assert_eq!(shift(ms(10_000), 500), ms(9_500));
+        assert_eq!(shift(ms(10_000), -500), ms(10_500));
+        assert_eq!(shift(ms(200), 500), ms(0));
+    }
+
+    #[test]
+    fn strip_word_timings_keeps_other_angle_brackets() {
+        assert_eq!(
+            strip_word_timings("<00:01.00>Hold <00:01.50>on <00:02.00>"),
+            "Hold on "
+        );
+        assert_eq!(strip_word_timings("a <b> c"), "a <b> c");
+        assert_eq!(strip_word_timings("1 < 2 <"), "1 < 2 <");
+    }
+
+    #[test]
+    fn parse_lrc_repeats_lines_with_several_timestamps() {
+        let lyrics = Lyrics::parse_lrc(
+            "[ar:Low]\n[ti:Sunflower]\n[00:20.00][01:10.00]Chorus line\n[00:10.00]First line\n\
+             [00:15.00]<00:15.00>Word <00:16.00>timed\n",
+        );
+        assert!(lyrics.synced);
+        assert_eq!(
+            lines(&lyrics),
+            [
+                (Some(ms(10_000)), "First line"),
+                (Some(ms(15_000)), "Word timed"),
+                (Some(ms(20_000)), "Chorus line"),
+                (Some(ms(70_000)), "Chorus line"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_lrc_applies_the_offset_either_way() {
+        let earlier = Lyrics::parse_lrc("[offset:+500]\n[00:10.00]Line\n");
+        assert_eq!(earlier.lines[0].time, Some(ms(9_500)));
+        let later = Lyrics::parse_lrc("[offset: -250]\n[00:10.00]Line\n");
+        assert_eq!(later.lines[0].time, Some(ms(10_250)));
+    }
+
+    #[test]
+    fn parse_lrc_falls_back_to_static_lyrics() {
+        let lyrics = Lyrics::parse_lrc(
+            "\n[ar:Low]\n\n[Verse 1: Alan]\nFirst line\n\n[Chorus: Mimi] Sung together\n\n\n",
+        );
+        assert!(!lyrics.synced);
+        assert_eq!(
+            lines(&lyrics),
+            [
+                (None, "[Verse 1: Alan]"),
+                (None, "First line"),
+                (None, ""),
+                (None, "[Chorus: Mimi] Sung together"),
+            ]
+        );
+        assert_eq!(lyrics.current_line(ms(60_000)), None);
+    }
+
+    #[test]
+    fn current_line_is_the_last_one_started() {
+        let lyrics = Lyrics::parse_lrc("[00:10.00]One\n[00:20.00]Two\n[00:30.00]Three\n");
+        assert_eq!(lyrics.current_line(ms(5_000)), None);
+        assert_eq!(lyrics.current_line(ms(10_000)), Some(0));
+        assert_eq!(lyrics.current_line(ms(29_999)), Some(1));
+        assert_eq!(lyrics.current_line(ms(300_000)), Some(2));
+    }
+}
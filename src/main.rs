@@ -2,50 +2,157 @@ use anyhow::{Context, Result};
 use crossterm::{
     event::{
         self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
     },
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
 use ctrlc::set_handler;
-use log::{error, info, warn};
+use log::{LevelFilter, error, info, warn};
 use ratatui::{
     Frame, Terminal,
     backend::{Backend, CrosstermBackend},
     layout::{Alignment, Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    style::Style,
     text::{Line, Span},
-    widgets::{Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph},
+    widgets::{Clear, Gauge, List, ListItem, ListState, Paragraph},
 };
 use rodio::{Decoder, OutputStream, Sink, Source};
-use std::env;
-use std::fs::File;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
 use std::io::{self, BufReader};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use walkdir::WalkDir;
 
-/// Supported audio file extensions
+mod artwork;
+mod attach;
+mod cli;
+mod cmdline;
+mod command;
+mod config;
+mod ctl;
+mod hooks;
+mod http;
+mod ipc;
+mod keymap;
+mod logging;
+mod lyrics;
+mod messages;
+mod metadata;
+mod mpd;
+mod mpris;
+mod nowplaying;
+mod order;
+mod playlist;
+mod sort;
+mod stats;
+mod tap;
+mod theme;
+mod visualizer;
+mod waveform;
+
+use artwork::ArtStore;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, CliCommand};
+use cmdline::CommandLine;
+use command::{Amount, Command};
+use config::Config;
+use hooks::{HookEvent, Hooks};
+use ipc::{Event as IpcEvent, Request, Response, TrackInfo};
+use keymap::{Action, Input, Keymap, PendingInput};
+use logging::LogBuffer;
+use lyrics::Lyrics;
+use messages::Messages;
+use metadata::TrackDetails;
+use mpris::Mpris;
+use nowplaying::NowPlaying;
+use order::{PlayOrder, Step};
+use sort::SortOrder;
+use stats::PlayStats;
+use tap::{SampleTap, Tap};
+use theme::Theme;
+use visualizer::Visualizer;
+use waveform::{WaveformBar, WaveformStore};
+
+/// Default supported audio file extensions
 const SUPPORTED_EXTENSIONS: &[&str] = &["mp3", "wav", "ogg", "flac", "m4a"];
 
+/// Maximum delay between two clicks on the same track to count as a double-click
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+/// Redraw interval while the spectrum visualizer is shown
+const VISUALIZER_FRAME: Duration = Duration::from_millis(33);
+
+/// Height of the visualizer pane, including borders
+const VISUALIZER_HEIGHT: u16 = 10;
+
+/// Height of the Now Playing pane when album art is enabled, including borders
+const ART_PANE_HEIGHT: u16 = 10;
+
+/// Height of the Now Playing pane with the details panel expanded, including borders
+const DETAILS_PANE_HEIGHT: u16 = 15;
+
+/// Height of the log pane, including borders
+const LOG_PANE_HEIGHT: u16 = 8;
+
+/// Height of the progress pane when drawn as a waveform, including borders
+const WAVEFORM_HEIGHT: u16 = 5;
+
+/// Time between `position` events sent to subscribed control clients
+const POSITION_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Global flag for graceful shutdown
 static SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
+/// Set by SIGUSR1 to skip to the next track
+static SKIP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
 /// Represents a music track with metadata
 #[derive(Debug, Clone)]
 pub struct Track {
     pub path: PathBuf,
     pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
     pub duration: Option<Duration>,
+    pub date_added: Option<SystemTime>,
+    pub play_count: u32,
+    /// Why the track failed to play, until the file changes
+    pub unplayable: Option<Unplayable>,
+}
+
+/// Why a track couldn't be played, and the file's modification time at that point
+#[derive(Debug, Clone)]
+pub struct Unplayable {
+    pub reason: String,
+    modified: Option<SystemTime>,
+}
+
+/// A track passed over because it couldn't be played
+#[derive(Debug, Clone)]
+pub struct Skipped {
+    pub title: String,
+    pub reason: String,
 }
 
 impl Track {
     pub fn new(path: PathBuf) -> Self {
-        let title = path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("Unknown")
-            .to_string();
+        let tags = metadata::read_tags(&path).unwrap_or_else(|e| {
+            warn!("Failed to read tags from '{}': {}", path.display(), e);
+            Default::default()
+        });
+
+        let title = tags.title.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown")
+                .to_string()
+        });
 
         // Try to extract actual duration from MP3 file
         let duration = if let Some(ext) = path.extension() {
@@ -61,21 +168,48 @@ impl Track {
                     }
                     Err(e) => {
                         warn!("Failed to extract duration for '{}': {}", title, e);
-                        None
+                        tags.duration
                     }
                 }
             } else {
-                None
+                tags.duration
             }
         } else {
-            None
+            tags.duration
         };
 
+        // Prefer the creation time, falling back to the last modification
+        let date_added = path
+            .metadata()
+            .ok()
+            .and_then(|m| m.created().or_else(|_| m.modified()).ok());
+
         Self {
             path,
             title,
+            artist: tags.artist,
+            album: tags.album,
+            track_number: tags.track_number,
             duration,
+            date_added,
+            play_count: 0,
+            unplayable: None,
+        }
+    }
+
+    /// Check whether the track is known to be unplayable, forgetting the failure once the
+    /// file has been modified so it gets another try
+    fn is_unplayable(&mut self) -> bool {
+        let Some(unplayable) = &self.unplayable else {
+            return false;
+        };
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified != unplayable.modified {
+            info!("'{}' changed on disk, trying it again", self.title);
+            self.unplayable = None;
+            return false;
         }
+        true
     }
 }
 
@@ -86,14 +220,33 @@ pub struct MusicPlayer {
     sink: Sink,
     _stream: OutputStream,
     is_paused: bool,
+    /// Stopped by the user or after a failure, so finished tracks don't auto-advance
+    is_stopped: bool,
     is_shuffled: bool,
     repeat_mode: RepeatMode,
+    /// Order tracks play in, following the shuffle and repeat modes
+    order: PlayOrder,
     start_time: Option<Instant>,
     elapsed_time: Duration, // Track actual playback time (excluding pauses)
+    sort_order: SortOrder,
+    /// Tracks to play next, ahead of the list order
+    queue: VecDeque<PathBuf>,
+    /// Bumped whenever the track list is added to, removed from, re-sorted or retagged
+    list_version: u32,
+    /// Bumped whenever `play_current` starts a track, even the same one again
+    starts: u32,
+    /// Tracks that failed to play, with the error, until taken by `take_failures`
+    failures: Vec<(PathBuf, String)>,
+    stats: PlayStats,
+    extensions: Vec<String>,
+    follow_links: bool,
+    tap: Arc<SampleTap>,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RepeatMode {
+    #[serde(rename = "off", alias = "none")]
     None,
     One,
     All,
@@ -109,12 +262,32 @@ impl std::fmt::Display for RepeatMode {
     }
 }
 
+/// Whether a track is playing, paused or stopped
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+impl std::fmt::Display for PlaybackState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlaybackState::Playing => write!(f, "Playing"),
+            PlaybackState::Paused => write!(f, "Paused"),
+            PlaybackState::Stopped => write!(f, "Stopped"),
+        }
+    }
+}
+
 impl MusicPlayer {
-    pub fn new() -> Result<Self> {
+    pub fn new(config: &Config) -> Result<Self> {
         let (_stream, stream_handle) =
             OutputStream::try_default().context("Failed to create audio output stream")?;
 
         let sink = Sink::try_new(&stream_handle).context("Failed to create audio sink")?;
+        sink.set_volume(f32::from(config.playback.volume) / 100.0);
 
         Ok(Self {
             tracks: Vec::new(),
@@ -122,80 +295,169 @@ impl MusicPlayer {
             sink,
             _stream,
             is_paused: false,
-            is_shuffled: false,
-            repeat_mode: RepeatMode::None,
+            is_stopped: true,
+            is_shuffled: config.playback.shuffle,
+            repeat_mode: config.playback.repeat,
+            order: PlayOrder::default(),
             start_time: None,
             elapsed_time: Duration::default(),
+            sort_order: config.library.sort_order(),
+            queue: VecDeque::new(),
+            list_version: 0,
+            starts: 0,
+            failures: Vec::new(),
+            stats: PlayStats::load(),
+            extensions: config.library.extensions.clone(),
+            follow_links: config.library.follow_links,
+            tap: Arc::new(SampleTap::new()),
         })
     }
 
-    /// Load tracks from a directory or single file
+    /// Load tracks from a directory or single file, replacing the current list
     pub fn load_music<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let path = path.as_ref();
         self.tracks.clear();
+        self.current_index = 0;
+        self.add_music(path)
+    }
+
+    /// Add tracks from a directory or single file to the current list
+    pub fn add_music<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut added = Vec::new();
 
         if path.is_file() {
             if self.is_supported_audio_file(path) {
-                self.tracks.push(Track::new(path.to_path_buf()));
+                added.push(path.to_path_buf());
                 info!("Loaded single track: {}", path.display());
             } else {
                 warn!("Unsupported file format: {}", path.display());
             }
         } else if path.is_dir() {
-            let mut count = 0;
-            for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+            for entry in WalkDir::new(path)
+                .follow_links(self.follow_links)
+                .into_iter()
+                .filter_map(|e| e.ok())
+            {
                 if entry.file_type().is_file() && self.is_supported_audio_file(entry.path()) {
-                    self.tracks.push(Track::new(entry.path().to_path_buf()));
-                    count += 1;
+                    added.push(entry.path().to_path_buf());
                 }
             }
-            info!("Loaded {} tracks from directory: {}", count, path.display());
+            info!(
+                "Loaded {} tracks from directory: {}",
+                added.len(),
+                path.display()
+            );
         }
 
-        if self.tracks.is_empty() {
+        // Skip files that are already in the list (e.g. overlapping roots)
+        added.retain(|p| self.index_of(p).is_none());
+
+        if added.is_empty() {
             warn!("No supported audio files found in: {}", path.display());
         }
 
+        for path in added {
+            let mut track = Track::new(path);
+            track.play_count = self.stats.count(&track.path);
+            self.tracks.push(track);
+        }
+        // Sorting counts as a change to the list
+        self.set_sort_order(self.sort_order);
+
         Ok(())
     }
 
-    /// Check if a file has a supported audio extension
-    fn is_supported_audio_file<P: AsRef<Path>>(&self, path: P) -> bool {
-        path.as_ref()
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
-            .unwrap_or(false)
+    /// Re-sort the track list, keeping the current track pointed at the same file
+    pub fn set_sort_order(&mut self, order: SortOrder) {
+        let current_path = self.current_track().map(|t| t.path.clone());
+
+        self.sort_order = order;
+        self.sort_order.sort(&mut self.tracks);
+        self.list_version = self.list_version.wrapping_add(1);
+
+        if let Some(index) = current_path.and_then(|p| self.index_of(&p)) {
+            self.current_index = index;
+        }
+        info!("Sorted tracks by {}", self.sort_order);
     }
 
-    /// Find the next playable track starting from current index
-    pub fn find_next_playable(&mut self) -> Option<usize> {
-        if self.tracks.is_empty() {
-            return None;
+    /// Queue tracks to play next, after any already queued
+    pub fn enqueue(&mut self, indexes: &[usize]) -> usize {
+        let mut added = 0;
+        for track in indexes.iter().filter_map(|i| self.tracks.get(*i)) {
+            if !self.queue.contains(&track.path) {
+                self.queue.push_back(track.path.clone());
+                added += 1;
+            }
         }
+        added
+    }
 
-        let start_index = self.current_index;
-        let mut attempts = 0;
+    /// Position of a track in the play queue, counting from 1
+    pub fn queue_position(&self, path: &Path) -> Option<usize> {
+        self.queue.iter().position(|p| p == path).map(|i| i + 1)
+    }
 
-        loop {
-            let track = &self.tracks[self.current_index];
+    /// Take tracks out of the list and the queue, stopping playback if the current one goes
+    pub fn remove_tracks(&mut self, indexes: &[usize]) -> usize {
+        let remove: HashSet<usize> = indexes.iter().copied().collect();
+        let removes_current = remove.contains(&self.current_index);
+        let kept_before_current = (0..self.current_index)
+            .filter(|i| !remove.contains(i))
+            .count();
+
+        let before = self.tracks.len();
+        self.list_version = self.list_version.wrapping_add(1);
+        let mut index = 0;
+        self.tracks.retain(|_| {
+            let keep = !remove.contains(&index);
+            index += 1;
+            keep
+        });
+        let tracks = &self.tracks;
+        self.queue
+            .retain(|path| tracks.iter().any(|t| t.path == *path));
+
+        if removes_current {
+            self.stop();
+        }
+        // Point at the same track, or at the one that followed a removed current track
+        self.current_index = kept_before_current.min(self.tracks.len().saturating_sub(1));
+        before - self.tracks.len()
+    }
 
-            // Test if this track can be loaded
-            if self.load_track(&track.path).is_ok() {
-                return Some(self.current_index);
-            }
+    /// Get the current sort order
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort_order
+    }
 
-            // Move to next track
-            self.current_index = (self.current_index + 1) % self.tracks.len();
-            attempts += 1;
+    /// Counter that changes whenever the track list does
+    pub fn list_version(&self) -> u32 {
+        self.list_version
+    }
 
-            // If we've tried all tracks, give up
-            if attempts >= self.tracks.len() || self.current_index == start_index {
-                break;
-            }
-        }
+    /// Counter that changes whenever a track starts playing
+    pub fn starts(&self) -> u32 {
+        self.starts
+    }
 
-        None
+    /// Take the tracks that failed to play since the last call, with their errors
+    pub fn take_failures(&mut self) -> Vec<(PathBuf, String)> {
+        std::mem::take(&mut self.failures)
+    }
+
+    /// Find the index of a track by its path
+    pub fn index_of<P: AsRef<Path>>(&self, path: P) -> Option<usize> {
+        self.tracks.iter().position(|t| t.path == path.as_ref())
+    }
+
+    /// Check if a file has a supported audio extension
+    fn is_supported_audio_file<P: AsRef<Path>>(&self, path: P) -> bool {
+        path.as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| self.extensions.contains(&ext.to_lowercase()))
+            .unwrap_or(false)
     }
 
     /// Play the current track
@@ -205,7 +467,9 @@ impl MusicPlayer {
             return Ok(());
         }
 
-        let track = &self.tracks[self.current_index];
+        let Some(track) = self.tracks.get(self.current_index) else {
+            anyhow::bail!("No track at position {}", self.current_index + 1);
+        };
         info!("Playing: {}", track.title);
 
         // Stop current playback
@@ -217,13 +481,93 @@ impl MusicPlayer {
                 self.sink.append(source);
                 self.sink.play();
                 self.is_paused = false;
+                self.is_stopped = false;
                 self.start_time = Some(Instant::now());
                 self.elapsed_time = Duration::default();
+
+                let path = track.path.clone();
+                let track = &mut self.tracks[self.current_index];
+                track.unplayable = None;
+                track.play_count = self.stats.record_play(path);
+                self.starts = self.starts.wrapping_add(1);
                 Ok(())
             }
             Err(e) => {
-                error!("Failed to play track '{}': {}", track.title, e);
-                Ok(())
+                let error = e.context(format!("Failed to play '{}'", track.title));
+                let modified = fs::metadata(&track.path).and_then(|m| m.modified()).ok();
+                self.failures
+                    .push((track.path.clone(), format!("{:#}", error)));
+                self.tracks[self.current_index].unplayable = Some(Unplayable {
+                    reason: format!("{:#}", error.root_cause()),
+                    modified,
+                });
+                self.is_stopped = true;
+                Err(error)
+            }
+        }
+    }
+
+    /// Step through the play order from the current track and play the first playable one
+    ///
+    /// Moving forward, queued tracks are played first. Tracks already known to be unplayable
+    /// are passed over without another try. Playback stops when the order runs out, and after
+    /// a full cycle without success an error is returned instead of retrying forever.
+    fn play_next_playable(&mut self, mut step: Step) -> Result<Vec<Skipped>> {
+        let len = self.tracks.len();
+        let mut skipped = Vec::new();
+        if len == 0 {
+            return Ok(skipped);
+        }
+
+        if step != Step::Previous {
+            while let Some(path) = self.queue.pop_front() {
+                if let Some(index) = self.index_of(&path)
+                    && self.try_play(index, &mut skipped)
+                {
+                    return Ok(skipped);
+                }
+            }
+        }
+
+        for _ in 0..len {
+            let Some(index) = self.next_index(step) else {
+                info!("Reached the end of the track list");
+                self.stop();
+                return Ok(skipped);
+            };
+            if self.try_play(index, &mut skipped) {
+                return Ok(skipped);
+            }
+            // A track that can't be repeated is passed over like any other
+            if step == Step::Finished {
+                step = Step::Next;
+            }
+        }
+
+        self.stop();
+        anyhow::bail!("No playable tracks left, playback stopped")
+    }
+
+    /// Make a track current and play it, noting it in `skipped` if that fails
+    fn try_play(&mut self, index: usize, skipped: &mut Vec<Skipped>) -> bool {
+        self.current_index = index;
+        if self.tracks[index].is_unplayable() {
+            return false;
+        }
+        match self.play_current() {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("{:#}", e);
+                let track = &self.tracks[index];
+                skipped.push(Skipped {
+                    title: track.title.clone(),
+                    reason: track
+                        .unplayable
+                        .as_ref()
+                        .map(|u| u.reason.clone())
+                        .unwrap_or_default(),
+                });
+                false
             }
         }
     }
@@ -237,31 +581,58 @@ impl MusicPlayer {
         let source = Decoder::new(reader)
             .with_context(|| format!("Failed to decode audio file: {}", path.as_ref().display()))?;
 
-        Ok(Box::new(source.convert_samples()))
+        // Copy samples to the visualizer as they are played
+        Ok(Box::new(Tap::new(
+            source.convert_samples(),
+            self.tap.clone(),
+        )))
+    }
+
+    /// The track after the current one in play order, or `None` when playback should stop
+    fn next_index(&mut self, step: Step) -> Option<usize> {
+        self.order.step(
+            self.current_index,
+            self.tracks.len(),
+            self.list_version,
+            self.is_shuffled,
+            self.repeat_mode,
+            step,
+        )
     }
 
-    /// Move to the next track
-    pub fn next_track(&mut self) -> Result<()> {
-        if !self.tracks.is_empty() {
-            self.current_index = (self.current_index + 1) % self.tracks.len();
-            self.play_current()
-        } else {
-            Ok(())
-        }
+    /// Make the first track in play order current, ready to start playback
+    pub fn rewind(&mut self) {
+        self.current_index =
+            self.order
+                .first(self.tracks.len(), self.list_version, self.is_shuffled);
     }
 
-    /// Move to the previous track
-    pub fn previous_track(&mut self) -> Result<()> {
-        if !self.tracks.is_empty() {
-            self.current_index = if self.current_index == 0 {
-                self.tracks.len() - 1
-            } else {
-                self.current_index - 1
-            };
-            self.play_current()
-        } else {
-            Ok(())
+    /// Move to the next playable track, returning the tracks skipped on the way
+    pub fn next_track(&mut self) -> Result<Vec<Skipped>> {
+        self.play_next_playable(Step::Next)
+    }
+
+    /// Move on from a track that played to its end, replaying it with repeat one
+    pub fn finish_track(&mut self) -> Result<Vec<Skipped>> {
+        self.play_next_playable(Step::Finished)
+    }
+
+    /// Move to the previous playable track, returning the tracks skipped on the way
+    pub fn previous_track(&mut self) -> Result<Vec<Skipped>> {
+        self.play_next_playable(Step::Previous)
+    }
+
+    /// Move `count` tracks forward or back in play order, then on to the next playable one
+    pub fn skip_tracks(&mut self, count: usize, forward: bool) -> Result<Vec<Skipped>> {
+        let step = if forward { Step::Next } else { Step::Previous };
+        // The last step is taken by `play_next_playable`
+        for _ in 1..count.max(1).min(self.tracks.len().max(1)) {
+            match self.next_index(step) {
+                Some(index) => self.current_index = index,
+                None => break,
+            }
         }
+        self.play_next_playable(step)
     }
 
     /// Pause or resume playback
@@ -286,8 +657,10 @@ impl MusicPlayer {
     pub fn stop(&mut self) {
         self.sink.stop();
         self.is_paused = false;
+        self.is_stopped = true;
         self.start_time = None;
         self.elapsed_time = Duration::default();
+        self.tap.clear();
         info!("Playback stopped");
     }
 
@@ -296,6 +669,38 @@ impl MusicPlayer {
         self.tracks.get(self.current_index)
     }
 
+    /// Get whether playback is running, paused or stopped
+    pub fn state(&self) -> PlaybackState {
+        if self.is_stopped || self.tracks.is_empty() {
+            PlaybackState::Stopped
+        } else if self.is_paused {
+            PlaybackState::Paused
+        } else {
+            PlaybackState::Playing
+        }
+    }
+
+    /// Snapshot of the playback state for control clients
+    pub fn status(&self) -> ipc::Status {
+        let state = self.state();
+        let playing = state != PlaybackState::Stopped;
+        ipc::Status {
+            state,
+            track: self.current_track().map(TrackInfo::from),
+            position: if playing {
+                self.position().as_secs_f64()
+            } else {
+                0.0
+            },
+            volume: self.volume(),
+            shuffle: self.is_shuffled,
+            repeat: self.repeat_mode.to_string().to_lowercase(),
+            index: self.current_index,
+            tracks: self.tracks.len(),
+            queue: self.queue.len(),
+        }
+    }
+
     /// Get total number of tracks
     pub fn track_count(&self) -> usize {
         self.tracks.len()
@@ -308,7 +713,13 @@ impl MusicPlayer {
 
     /// Toggle shuffle mode
     pub fn toggle_shuffle(&mut self) {
-        self.is_shuffled = !self.is_shuffled;
+        self.set_shuffle(!self.is_shuffled);
+    }
+
+    /// Switch shuffle on or off; switching it on shuffles the list afresh
+    pub fn set_shuffle(&mut self, on: bool) {
+        self.is_shuffled = on;
+        self.order = PlayOrder::default();
     }
 
     /// Cycle through repeat modes
@@ -320,49 +731,209 @@ impl MusicPlayer {
         };
     }
 
-    /// Get current playback progress (0.0 to 1.0)
-    pub fn get_progress(&self) -> f64 {
+    /// Get the volume in percent
+    pub fn volume(&self) -> u8 {
+        (self.sink.volume() * 100.0).round() as u8
+    }
+
+    /// Set the volume in percent, capped at 100
+    pub fn set_volume(&mut self, volume: u8) {
+        self.sink.set_volume(f32::from(volume.min(100)) / 100.0);
+    }
+
+    /// Run a `:` or `-c` command, returning a short description of what it did
+    ///
+    /// Track commands such as `enqueue` work on the tracks at `targets`. `quit` is left to
+    /// the caller, since it ends the interface rather than playback.
+    pub fn execute(&mut self, command: &Command, targets: &[usize]) -> Result<String> {
+        if command.takes_targets() && targets.is_empty() {
+            anyhow::bail!("No tracks selected");
+        }
+        let target_tracks = || targets.iter().filter_map(|i| self.tracks.get(*i));
+
+        let message = match command {
+            Command::Add(path) => {
+                if !path.exists() {
+                    anyhow::bail!("No such file or directory: {}", path.display());
+                }
+                let before = self.tracks.len();
+                self.add_music(path)?;
+                match self.tracks.len() - before {
+                    0 => anyhow::bail!("No new audio files in {}", path.display()),
+                    added => format!("Added {} tracks", added),
+                }
+            }
+            Command::Append(path) => {
+                let count = playlist::append(path, target_tracks())?;
+                format!("Added {} tracks to {}", count, path.display())
+            }
+            Command::Enqueue => {
+                let added = self.enqueue(targets);
+                format!("Queued {} tracks ({} in queue)", added, self.queue.len())
+            }
+            Command::Next | Command::Previous => {
+                let skipped = self.skip_tracks(1, *command == Command::Next)?;
+                let title = self.current_track().map_or("", |t| t.title.as_str());
+                match skipped.len() {
+                    _ if self.is_stopped => "Reached the end of the track list".to_string(),
+                    0 => format!("Playing {}", title),
+                    n => format!("Playing {} (skipped {} unplayable)", title, n),
+                }
+            }
+            Command::Pause => {
+                if self.is_stopped {
+                    anyhow::bail!("Nothing is playing");
+                }
+                if !self.is_paused {
+                    self.toggle_pause();
+                }
+                "Paused".to_string()
+            }
+            Command::Play => {
+                if self.is_stopped {
+                    self.play_current()?;
+                } else if self.is_paused {
+                    self.toggle_pause();
+                }
+                let title = self.current_track().map_or("", |t| t.title.as_str());
+                format!("Playing {}", title)
+            }
+            Command::Quit => "Quit".to_string(),
+            Command::Remove => {
+                let removed = self.remove_tracks(targets);
+                format!("Removed {} tracks from the list", removed)
+            }
+            Command::Repeat(mode) => {
+                self.repeat_mode = *mode;
+                format!("Repeat: {}", mode)
+            }
+            Command::Save(path) => {
+                let count = playlist::save(path, &self.tracks)?;
+                format!("Saved {} tracks to {}", count, path.display())
+            }
+            Command::Seek(amount) => {
+                if self.is_stopped || self.sink.empty() {
+                    anyhow::bail!("Nothing is playing");
+                }
+                let position = self.position();
+                let mut target = match *amount {
+                    Amount::To(time) => time,
+                    Amount::Up(step) => position + step,
+                    Amount::Down(step) => position.saturating_sub(step),
+                };
+                if let Some(duration) = self.current_track().and_then(|t| t.duration) {
+                    target = target.min(duration);
+                }
+                self.seek(target)?;
+                format!("Seeked to {}", format_time(target))
+            }
+            Command::Shuffle(state) => {
+                self.set_shuffle(state.unwrap_or(!self.is_shuffled));
+                format!("Shuffle {}", if self.is_shuffled { "on" } else { "off" })
+            }
+            Command::Sort(order) => {
+                self.set_sort_order(*order);
+                format!("Sorted by {}", order)
+            }
+            Command::Stop => {
+                self.stop();
+                "Stopped".to_string()
+            }
+            Command::Tag(field, value) => {
+                let mut tagged = 0;
+                for &index in targets {
+                    let Some(track) = self.tracks.get_mut(index) else {
+                        continue;
+                    };
+                    metadata::write_tag(&track.path, *field, value).with_context(|| {
+                        format!(
+                            "Failed to tag '{}' after tagging {} tracks",
+                            track.title, tagged
+                        )
+                    })?;
+
+                    // Re-read the tags so the list shows the new values
+                    let mut updated = Track::new(track.path.clone());
+                    updated.play_count = track.play_count;
+                    updated.unplayable = track.unplayable.take();
+                    *track = updated;
+                    tagged += 1;
+                }
+                self.list_version = self.list_version.wrapping_add(1);
+                format!("Tagged {} tracks", tagged)
+            }
+            Command::Trash => {
+                let paths: Vec<PathBuf> = target_tracks().map(|t| t.path.clone()).collect();
+                trash::delete_all(&paths).context("Failed to move files to the trash")?;
+                let removed = self.remove_tracks(targets);
+                format!("Moved {} tracks to the trash", removed)
+            }
+            Command::Volume(amount) => {
+                let volume = match *amount {
+                    Amount::To(volume) => volume,
+                    Amount::Up(step) => self.volume().saturating_add(step),
+                    Amount::Down(step) => self.volume().saturating_sub(step),
+                };
+                self.set_volume(volume);
+                format!("Volume {}%", self.volume())
+            }
+        };
+        Ok(message)
+    }
+
+    /// Get current playback progress (0.0 to 1.0), or `None` if the track duration is unknown
+    pub fn get_progress(&self) -> Option<f64> {
+        let duration = self.current_track()?.duration?;
+
         // If the track is finished, return 100%
         if self.is_track_finished() {
-            return 1.0;
+            return Some(1.0);
         }
 
-        // Calculate total elapsed time: previous elapsed + current session (if playing)
-        let total_elapsed = if let Some(start) = self.start_time {
-            // Currently playing - add current session time
+        let elapsed_seconds = self.position().as_secs_f64();
+        let duration_seconds = duration.as_secs_f64().max(f64::EPSILON);
+        Some((elapsed_seconds / duration_seconds).min(1.0))
+    }
+
+    /// Get the playback position within the current track
+    pub fn position(&self) -> Duration {
+        // Previous elapsed + current session (if playing)
+        if let Some(start) = self.start_time {
             self.elapsed_time + start.elapsed()
         } else {
-            // Paused or stopped - just use accumulated time
             self.elapsed_time
-        };
+        }
+    }
 
-        let elapsed_seconds = total_elapsed.as_secs() as f64;
+    /// Seek to a position within the current track
+    pub fn seek(&mut self, position: Duration) -> Result<()> {
+        if self.is_stopped || self.sink.empty() {
+            anyhow::bail!("Nothing is playing");
+        }
 
-        // Use actual track duration if available, otherwise fall back to estimate
-        let duration_seconds = if let Some(current_track) = self.current_track() {
-            if let Some(actual_duration) = current_track.duration {
-                actual_duration.as_secs() as f64
-            } else {
-                300.0 // 5 minutes fallback for non-MP3 files
-            }
+        self.sink
+            .try_seek(position)
+            .map_err(|e| anyhow::anyhow!("Failed to seek: {}", e))?;
+
+        self.elapsed_time = position;
+        self.start_time = if self.is_paused {
+            None
         } else {
-            300.0 // 5 minutes fallback if no current track
+            Some(Instant::now())
         };
+        info!("Seeked to {:.1}s", position.as_secs_f64());
+        Ok(())
+    }
 
-        let progress = (elapsed_seconds / duration_seconds).min(1.0);
-
-        // Log when track should be finishing
-        if progress >= 0.95 {
-            info!(
-                "Track nearing completion: {:.1}% - elapsed: {:.1}s, duration: {:.1}s, sink_empty: {}",
-                progress * 100.0,
-                elapsed_seconds,
-                duration_seconds,
-                self.sink.empty()
-            );
-        }
-
-        progress
+    /// Seek to a fraction (0.0 to 1.0) of the current track, returning the new position
+    pub fn seek_to_ratio(&mut self, ratio: f64) -> Result<Duration> {
+        let duration = self
+            .current_track()
+            .and_then(|t| t.duration)
+            .context("Track length unknown, can't seek")?;
+        let position = duration.mul_f64(ratio.clamp(0.0, 1.0));
+        self.seek(position)?;
+        Ok(position)
     }
 
     /// Check if current track has finished playing
@@ -373,31 +944,96 @@ impl MusicPlayer {
         }
 
         // Also check if elapsed time exceeds actual track duration
-        if let Some(current_track) = self.current_track() {
-            if let Some(actual_duration) = current_track.duration {
-                let total_elapsed = if let Some(start) = self.start_time {
-                    self.elapsed_time + start.elapsed()
-                } else {
-                    self.elapsed_time
-                };
+        if let Some(current_track) = self.current_track()
+            && let Some(actual_duration) = current_track.duration
+        {
+            let total_elapsed = self.position();
 
-                // Consider finished if we've exceeded the track duration by a small margin
-                return total_elapsed >= actual_duration + Duration::from_millis(500);
-            }
+            // Consider finished if we've exceeded the track duration by a small margin
+            return total_elapsed >= actual_duration + Duration::from_millis(500);
         }
 
         false
     }
 }
 
+/// Tracks picked for a batch operation in the TUI
+#[derive(Default)]
+struct Selection {
+    /// Tracks picked one by one
+    tracks: HashSet<PathBuf>,
+    /// Track where visual mode started; the range up to the cursor is selected too
+    anchor: Option<PathBuf>,
+}
+
 pub struct App {
     player: MusicPlayer,
     list_state: ListState,
     show_help: bool,
+    keymap: Keymap,
+    /// Count, keys and mark command typed so far
+    pending: PendingInput,
+    /// The `:` prompt, or `None` without the TUI
+    command_line: Option<CommandLine>,
+    /// Tracks marked with `m{a-z}`, by path so marks survive re-sorting
+    marks: HashMap<char, PathBuf>,
+    /// Tracks picked for a batch operation, or `None` without the TUI
+    selection: Option<Selection>,
+    theme: Theme,
+    poll_interval: Duration,
+    // Screen areas from the last draw, used to hit-test mouse events
+    track_area: Rect,
+    progress_area: Rect,
+    last_click: Option<(Instant, usize)>,
+    seek_preview: Option<f64>,
+    show_remaining: bool,
+    /// Spectrum analyzer, or `None` without the TUI
+    visualizer: Option<Visualizer>,
+    /// Waveform summaries for the seekbar, or `None` to always draw a plain gauge
+    waveforms: Option<WaveformStore>,
+    /// Album art for the Now Playing pane, or `None` when switched off or without the TUI
+    artwork: Option<ArtStore>,
+    show_lyrics: bool,
+    /// Lyrics of the last track shown in the lyrics pane
+    lyrics: Option<(PathBuf, Option<Lyrics>)>,
+    show_details: bool,
+    /// Details of the last track shown in the details panel
+    details: Option<(PathBuf, TrackDetails)>,
+    messages: Messages,
+    log: Arc<LogBuffer>,
+    show_log: bool,
+    /// Least severe level shown in the log pane
+    log_level: LevelFilter,
+    /// Control socket, or `None` when switched off or it couldn't be opened
+    ipc: Option<ipc::Server>,
+    /// MPRIS interface, or `None` when switched off or there is no session bus
+    mpris: Option<Mpris>,
+    /// MPD protocol server, or `None` when switched off or the address is taken
+    mpd: Option<mpd::Server>,
+    /// HTTP API and web remote, or `None` when switched off or the address is taken
+    http: Option<http::Server>,
+    /// Track and state last announced to control clients
+    published: (Option<PathBuf>, PlaybackState),
+    /// When the last `position` event was sent
+    position_published: Instant,
+    /// User commands run on playback events, or `None` when none are configured
+    hooks: Option<Hooks>,
+    /// Track starts and state last passed to hooks
+    hooked: (u32, PlaybackState),
+    /// Now-playing files for stream overlays, or `None` when none are configured
+    now_playing: Option<NowPlaying>,
+    /// Track starts and state last written to the now-playing files
+    written: Option<(u32, PlaybackState)>,
 }
 
 impl App {
-    pub fn new(player: MusicPlayer) -> Self {
+    pub fn new(
+        player: MusicPlayer,
+        keymap: Keymap,
+        theme: Theme,
+        config: &Config,
+        log: Arc<LogBuffer>,
+    ) -> Self {
         let mut list_state = ListState::default();
         if !player.tracks.is_empty() {
             list_state.select(Some(0));
@@ -407,9 +1043,51 @@ impl App {
             player,
             list_state,
             show_help: false,
+            keymap,
+            pending: PendingInput::default(),
+            command_line: None,
+            marks: HashMap::new(),
+            selection: None,
+            theme,
+            poll_interval: Duration::from_millis(config.ui.poll_interval_ms),
+            track_area: Rect::default(),
+            progress_area: Rect::default(),
+            last_click: None,
+            seek_preview: None,
+            show_remaining: false,
+            visualizer: None,
+            waveforms: None,
+            artwork: None,
+            show_lyrics: false,
+            lyrics: None,
+            show_details: false,
+            details: None,
+            messages: Messages::new(),
+            log,
+            show_log: false,
+            log_level: LevelFilter::Info,
+            ipc: None,
+            mpris: None,
+            mpd: None,
+            http: None,
+            published: (None, PlaybackState::Stopped),
+            position_published: Instant::now(),
+            hooks: Hooks::new(&config.hooks),
+            hooked: (0, PlaybackState::Stopped),
+            now_playing: NowPlaying::start(&config.now_playing),
+            written: None,
         }
     }
 
+    /// Set up what only the TUI needs, including the workers preparing waveforms and album art
+    pub fn start_ui(&mut self, config: &Config) {
+        self.command_line = Some(CommandLine::new());
+        self.selection = Some(Selection::default());
+        self.visualizer = Some(Visualizer::new());
+        self.waveforms = config.ui.waveform.then(WaveformStore::new);
+        self.artwork = ArtStore::new(config.ui.album_art);
+    }
+
     pub fn next_track(&mut self) {
         let i = match self.list_state.selected() {
             Some(i) => {
@@ -440,84 +1118,823 @@ impl App {
         self.player.current_index = i;
     }
 
-    pub fn toggle_help(&mut self) {
-        self.show_help = !self.show_help;
+    /// Move the selection down, wrapping around (doesn't change playback)
+    pub fn select_next(&mut self) {
+        if self.player.tracks.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(i) => {
+                if i >= self.player.tracks.len() - 1 {
+                    0
+                } else {
+                    i + 1
+                }
+            }
+            None => 0,
+        };
+        self.list_state.select(Some(i));
     }
-}
 
-fn main() -> Result<()> {
-    // Initialize logging
-    env_logger::init();
-
-    // Set up signal handler for graceful shutdown
-    setup_signal_handlers();
-
-    info!("Starting Terminal Music Player");
+    /// Move the selection up, wrapping around (doesn't change playback)
+    pub fn select_previous(&mut self) {
+        if self.player.tracks.is_empty() {
+            return;
+        }
+        let i = match self.list_state.selected() {
+            Some(i) => {
+                if i == 0 {
+                    self.player.tracks.len() - 1
+                } else {
+                    i - 1
+                }
+            }
+            None => 0,
+        };
+        self.list_state.select(Some(i));
+    }
 
-    // Get command line arguments
-    let args: Vec<String> = env::args().collect();
+    /// Number of tracks visible in the track list
+    fn page_size(&self) -> usize {
+        usize::from(inner_area(self.track_area).height).max(1)
+    }
 
-    if args.len() < 2 {
-        println!("🎵 Terminal Music Player");
-        println!("Usage: {} <music_directory|music_file> [--test]", args[0]);
-        println!("Example: {} ./music", args[0]);
-        println!("Options:");
-        println!("  --test    Exit immediately after testing playback (for testing)");
-        println!("Supported formats: {}", SUPPORTED_EXTENSIONS.join(", "));
-        return Ok(());
+    /// Select a track by index, clamped to the track list
+    fn select_index(&mut self, index: usize) {
+        if let Some(last) = self.player.tracks.len().checked_sub(1) {
+            self.list_state.select(Some(index.min(last)));
+        }
     }
 
-    let path = PathBuf::from(&args[1]);
-    let test_mode = args.len() > 2 && args[2] == "--test";
+    /// Move the selection and the view by `delta` tracks without wrapping
+    fn scroll_by(&mut self, delta: isize) {
+        let len = self.player.tracks.len();
+        if len == 0 {
+            return;
+        }
+        let selected = self.list_state.selected().unwrap_or(0);
+        self.select_index(selected.saturating_add_signed(delta));
 
-    // Create music player
-    let mut player = MusicPlayer::new().context("Failed to initialize music player")?;
+        let max_offset = len.saturating_sub(self.page_size());
+        let offset = self.list_state.offset().saturating_add_signed(delta);
+        *self.list_state.offset_mut() = offset.min(max_offset);
+    }
 
-    // Load music
-    player
-        .load_music(&path)
-        .with_context(|| format!("Failed to load music from: {}", path.display()))?;
+    /// Select the playing track, optionally scrolling it to the middle of the list
+    fn jump_to_playing(&mut self, center: bool) {
+        if self.player.tracks.is_empty() {
+            return;
+        }
+        let index = self.player.current_index;
+        self.list_state.select(Some(index));
+        if center {
+            let page = self.page_size();
+            let max_offset = self.player.tracks.len().saturating_sub(page);
+            *self.list_state.offset_mut() = index.saturating_sub(page / 2).min(max_offset);
+        }
+    }
 
-    if player.track_count() == 0 {
-        println!("❌ No supported audio files found in: {}", path.display());
-        println!("Supported formats: {}", SUPPORTED_EXTENSIONS.join(", "));
-        return Ok(());
+    /// Remember the selected track under a mark name
+    fn set_mark(&mut self, name: char) {
+        let Some(track) = self
+            .list_state
+            .selected()
+            .and_then(|i| self.player.tracks.get(i))
+        else {
+            return;
+        };
+        let title = track.title.clone();
+        self.marks.insert(name, track.path.clone());
+        self.messages
+            .info(format!("Marked '{}' as {}", title, name));
     }
 
-    // In test mode, just verify and exit
-    if test_mode {
-        println!("📁 Loaded {} tracks", player.track_count());
-        if let Err(e) = player.play_current() {
-            error!("Failed to start playback: {}", e);
-            return Err(e);
+    /// Select the track saved under a mark name
+    fn jump_to_mark(&mut self, name: char) {
+        match self
+            .marks
+            .get(&name)
+            .and_then(|path| self.player.index_of(path))
+        {
+            Some(index) => self.list_state.select(Some(index)),
+            None if self.marks.contains_key(&name) => {
+                self.messages
+                    .warn(format!("Mark {} is no longer in the track list", name));
+            }
+            None => self.messages.warn(format!("Mark {} is not set", name)),
         }
-        println!("✅ Test mode: Playback started successfully");
-        std::thread::sleep(Duration::from_millis(500));
-        player.stop();
-        return Ok(());
     }
 
-    // Start the TUI
-    run_tui(player)
-}
+    /// Range of indexes covered by visual mode, if active
+    fn visual_range(&self) -> Option<std::ops::RangeInclusive<usize>> {
+        let anchor = self.selection.as_ref()?.anchor.as_ref()?;
+        let anchor = self.player.index_of(anchor)?;
+        let cursor = self.list_state.selected()?;
+        Some(anchor.min(cursor)..=anchor.max(cursor))
+    }
 
-fn run_tui(player: MusicPlayer) -> Result<()> {
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    /// Whether each track is selected, either on its own or within the visual range
+    fn selection_mask(&self) -> Vec<bool> {
+        let range = self.visual_range();
+        self.player
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| {
+                range.as_ref().is_some_and(|range| range.contains(&i))
+                    || self
+                        .selection
+                        .as_ref()
+                        .is_some_and(|selection| selection.tracks.contains(&track.path))
+            })
+            .collect()
+    }
 
-    // Create app state
-    let mut app = App::new(player);
+    /// Indexes of the tracks a batch operation applies to: the selection, or else the cursor
+    fn targets(&self) -> Vec<usize> {
+        let selected: Vec<usize> = self
+            .selection_mask()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, selected)| selected.then_some(i))
+            .collect();
+        if selected.is_empty() {
+            self.list_state.selected().into_iter().collect()
+        } else {
+            selected
+        }
+    }
 
-    // Start playing the first track
-    if !app.player.tracks.is_empty() {
-        app.player.play_current()?;
+    fn clear_selection(&mut self) {
+        if let Some(selection) = &mut self.selection {
+            *selection = Selection::default();
+        }
     }
 
-    let res = run_app(&mut terminal, &mut app);
+    /// Start visual mode at the cursor, or end it and keep the range selected
+    fn toggle_visual(&mut self) {
+        let range = self.visual_range();
+        let Some(selection) = &mut self.selection else {
+            return;
+        };
+        if let Some(range) = range {
+            for index in range {
+                selection
+                    .tracks
+                    .insert(self.player.tracks[index].path.clone());
+            }
+            selection.anchor = None;
+        } else {
+            selection.anchor = self
+                .list_state
+                .selected()
+                .and_then(|i| self.player.tracks.get(i))
+                .map(|track| track.path.clone());
+        }
+    }
+
+    /// Select or deselect the track at the cursor and move down
+    fn toggle_select(&mut self) {
+        let Some(index) = self.list_state.selected() else {
+            return;
+        };
+        if let Some(selection) = &mut self.selection
+            && let Some(track) = self.player.tracks.get(index)
+            && !selection.tracks.remove(&track.path)
+        {
+            selection.tracks.insert(track.path.clone());
+        }
+        self.select_index(index + 1);
+    }
+
+    /// Get the track index at a screen position in the track list, if any
+    fn track_at(&self, column: u16, row: u16) -> Option<usize> {
+        let inner = inner_area(self.track_area);
+        if !contains(inner, column, row) {
+            return None;
+        }
+        let index = self.list_state.offset() + usize::from(row - inner.y);
+        (index < self.player.tracks.len()).then_some(index)
+    }
+
+    /// Get the progress fraction at a screen column in the progress bar
+    fn progress_at(&self, column: u16) -> f64 {
+        let inner = inner_area(self.progress_area);
+        let offset = column.saturating_sub(inner.x).min(inner.width);
+        f64::from(offset) / f64::from(inner.width.max(1))
+    }
+
+    /// Show or hide the spectrum visualizer
+    pub fn toggle_visualizer(&mut self) {
+        if let Some(visualizer) = &mut self.visualizer {
+            visualizer.visible = !visualizer.visible;
+            self.player.tap.set_enabled(visualizer.visible);
+        }
+    }
+
+    fn visualizer_visible(&self) -> bool {
+        self.visualizer.as_ref().is_some_and(|v| v.visible)
+    }
+
+    /// Open the `:` prompt with a command already typed
+    fn open_command_line(&mut self, input: &str) {
+        if let Some(line) = &mut self.command_line {
+            line.open_with(input);
+        }
+    }
+
+    /// Show a toast naming the track that just started
+    fn announce_track(&mut self) {
+        if self.player.is_stopped {
+            self.messages.info("Reached the end of the track list");
+        } else if let Some(track) = self.player.current_track() {
+            let text = format!("Playing {}", track.title);
+            self.messages.info(text);
+        }
+    }
+
+    /// Warn about each track passed over because it couldn't be played
+    fn report_skipped(&mut self, skipped: Vec<Skipped>) {
+        for track in skipped {
+            self.messages
+                .warn(format!("Skipped {}: {}", track.title, track.reason));
+        }
+    }
+
+    /// Show or hide the lyrics pane
+    pub fn toggle_lyrics(&mut self) {
+        self.show_lyrics = !self.show_lyrics;
+    }
+
+    /// Get the playing track's lyrics, loading them when the track has changed
+    fn current_lyrics(&mut self) -> Option<&Lyrics> {
+        let path = self.player.current_track()?.path.clone();
+        if self
+            .lyrics
+            .as_ref()
+            .is_none_or(|(loaded, _)| *loaded != path)
+        {
+            let lyrics = Lyrics::load(&path);
+            self.lyrics = Some((path, lyrics));
+        }
+        self.lyrics.as_ref().and_then(|(_, lyrics)| lyrics.as_ref())
+    }
+
+    /// Expand or collapse the track details panel
+    pub fn toggle_details(&mut self) {
+        self.show_details = !self.show_details;
+    }
+
+    /// Get the playing track's details, reading them when the track has changed
+    fn current_details(&mut self) -> Option<&TrackDetails> {
+        let track = self.player.current_track()?;
+        if self
+            .details
+            .as_ref()
+            .is_none_or(|(loaded, _)| *loaded != track.path)
+        {
+            let details = metadata::read_details(&track.path, track.duration).unwrap_or_else(|e| {
+                warn!("Failed to read details of {}: {}", track.path.display(), e);
+                TrackDetails::default()
+            });
+            self.details = Some((track.path.clone(), details));
+        }
+        self.details.as_ref().map(|(_, details)| details)
+    }
+
+    /// Run a command from the `:` prompt or `-c`, returning true when it asks to quit
+    fn run_command(&mut self, command: &Command) -> Result<bool> {
+        if *command == Command::Quit {
+            return Ok(true);
+        }
+        let message = self.apply_command(command)?;
+        self.messages.info(message);
+        Ok(false)
+    }
+
+    /// Carry out a command other than `quit`, returning a short description of what it did
+    fn apply_command(&mut self, command: &Command) -> Result<String> {
+        // Sorting through the app keeps the selection on the same track
+        if let Command::Sort(order) = command {
+            self.set_sort_order(*order);
+            return Ok(format!("Sorted by {}", order));
+        }
+
+        let targets = if command.takes_targets() {
+            self.targets()
+        } else {
+            Vec::new()
+        };
+        let result = self.player.execute(command, &targets);
+        if command.takes_targets() && result.is_ok() {
+            self.clear_selection();
+        }
+        self.sync_cursor(command);
+        result
+    }
+
+    /// Keep the cursor in the list, which may have grown or shrunk after a command
+    fn sync_cursor(&mut self, command: &Command) {
+        let len = self.player.tracks.len();
+        if matches!(command, Command::Next | Command::Previous | Command::Play) {
+            self.list_state.select(Some(self.player.current_index));
+        } else if len == 0 {
+            self.list_state.select(None);
+        } else {
+            let cursor = self.list_state.selected().unwrap_or(0);
+            self.list_state.select(Some(cursor.min(len - 1)));
+        }
+    }
+
+    /// Start playing the first playable track; failures are shown as messages rather than aborting
+    fn start_playback(&mut self) {
+        if self.player.tracks.is_empty() {
+            return;
+        }
+        self.player.rewind();
+        let result = self.player.play_current().or_else(|e| {
+            self.messages.warn(format!("{:#}", e));
+            self.player
+                .next_track()
+                .map(|skipped| self.report_skipped(skipped))
+        });
+        self.list_state.select(Some(self.player.current_index));
+        match result {
+            Ok(()) => self.announce_track(),
+            Err(e) => self.messages.error(e),
+        }
+    }
+
+    /// Start the remote interfaces enabled in the config
+    fn start_remote(&mut self, config: &Config) {
+        if config.ipc.enabled {
+            match ipc::Server::start(&config.ipc.socket_path()) {
+                Ok(server) => self.ipc = Some(server),
+                Err(e) => self
+                    .messages
+                    .warn(format!("Control socket disabled: {:#}", e)),
+            }
+        }
+        // A missing session bus is normal over SSH or on a console, so this only goes to the log
+        if config.mpris.enabled {
+            match Mpris::start(self.player.status()) {
+                Ok(mpris) => self.mpris = Some(mpris),
+                Err(e) => warn!("MPRIS disabled: {:#}", e),
+            }
+        }
+        if config.http.enabled {
+            match http::Server::start(&config.http, &self.player) {
+                Ok(server) => self.http = Some(server),
+                Err(e) => self.messages.warn(format!("Web remote disabled: {:#}", e)),
+            }
+        }
+        if config.mpd.enabled {
+            match mpd::Server::start(config, &self.player) {
+                Ok(server) => self.mpd = Some(server),
+                Err(e) => self.messages.warn(format!("MPD server disabled: {:#}", e)),
+            }
+        }
+    }
+
+    /// Run the commands given with `-c`, returning true when one of them quits
+    fn run_startup_commands(&mut self, commands: &[(String, Command)]) -> bool {
+        let mut quit = false;
+        for (line, command) in commands {
+            match self.run_command(command) {
+                Ok(true) => quit = true,
+                Ok(false) => {}
+                Err(e) => self
+                    .messages
+                    .error(e.context(format!("Command '{}' failed", line))),
+            }
+        }
+        quit
+    }
+
+    /// Move on to the next track when the current one has finished or SIGUSR1 asked to skip
+    fn advance(&mut self) {
+        let skip = SKIP_REQUESTED.swap(false, Ordering::Relaxed);
+        if !skip && (!self.player.is_empty() || self.player.is_paused || self.player.is_stopped) {
+            return;
+        }
+        let result = if skip {
+            self.player.next_track()
+        } else {
+            self.run_hook(HookEvent::TrackFinished);
+            self.player.finish_track()
+        };
+        // Sync the list selection with the new current track
+        self.list_state.select(Some(self.player.current_index));
+        match result {
+            Ok(skipped) => {
+                self.report_skipped(skipped);
+                self.announce_track();
+            }
+            Err(e) => self.messages.error(e),
+        }
+    }
+
+    /// Answer requests from the control socket and D-Bus, returning true when one asks to quit
+    fn handle_remote(&mut self) -> bool {
+        let mut quit = false;
+        while let Some(incoming) = self.ipc.as_ref().and_then(ipc::Server::try_recv) {
+            let mut response = match incoming.envelope.request {
+                Request::Subscribe => {
+                    if let Some(server) = &mut self.ipc {
+                        server.subscribe(incoming.client.clone());
+                    }
+                    Response::ok("Subscribed")
+                }
+                request => self.respond(request, &mut quit),
+            };
+            response.id = incoming.envelope.id;
+            incoming.client.send(&response);
+        }
+
+        while let Some(call) = self.mpris.as_ref().and_then(Mpris::try_recv) {
+            match self.answer(call.request.clone()) {
+                Ok(Some(_)) => {}
+                Ok(None) => quit = true,
+                Err(e) => warn!("MPRIS request failed: {:#}", e),
+            }
+            call.finish(self.player.status());
+        }
+
+        while let Some(call) = self.http.as_ref().and_then(http::Server::try_recv) {
+            let response = self.respond(call.request.clone(), &mut quit);
+            call.finish(response);
+        }
+
+        while let Some(call) = self.mpd.as_ref().and_then(mpd::Server::try_recv) {
+            let result = self.apply_mpd(&call.change);
+            // Refresh the clients' snapshot first, so their next command sees the change
+            if let Some(server) = &mut self.mpd {
+                server.update(&self.player);
+            }
+            call.finish(result);
+        }
+        quit
+    }
+
+    /// Answer a remote request with a response, setting `quit` when it asks to quit
+    fn respond(&mut self, request: Request, quit: &mut bool) -> Response {
+        match self.answer(request) {
+            Ok(Some(response)) => response,
+            Ok(None) => {
+                *quit = true;
+                Response::ok("Quit")
+            }
+            Err(e) => Response::error(&e),
+        }
+    }
+
+    /// Carry out one remote request, or return `None` when it asks to quit
+    fn answer(&mut self, request: Request) -> Result<Option<Response>> {
+        let response = match request {
+            Request::Status => Response::status(self.player.status()),
+            Request::Subscribe => anyhow::bail!("Events are only sent over the control socket"),
+            Request::Toggle => {
+                let command = if self.player.state() == PlaybackState::Playing {
+                    Command::Pause
+                } else {
+                    Command::Play
+                };
+                Response::ok(self.apply_command(&command)?)
+            }
+            Request::Enqueue { path } => {
+                if self.player.index_of(&path).is_none() {
+                    self.apply_command(&Command::Add(path.clone()))?;
+                }
+                let index = self
+                    .player
+                    .index_of(&path)
+                    .with_context(|| format!("Not a supported audio file: {}", path.display()))?;
+                Response::ok(self.player.execute(&Command::Enqueue, &[index])?)
+            }
+            request => match request.command()? {
+                Some(Command::Quit) => return Ok(None),
+                Some(command) if command.takes_targets() => {
+                    anyhow::bail!("Track commands can only be run from the player itself")
+                }
+                Some(command) => Response::ok(self.apply_command(&command)?),
+                None => unreachable!("requests without a command are answered above"),
+            },
+        };
+        Ok(Some(response))
+    }
+
+    /// Carry out a change requested by an MPD client
+    fn apply_mpd(&mut self, change: &mpd::Change) -> Result<()> {
+        change.check(self.player.track_count(), self.player.list_version())?;
+        match change {
+            mpd::Change::Command(command) => {
+                self.apply_command(command)?;
+            }
+            mpd::Change::Toggle => {
+                self.answer(Request::Toggle)?;
+            }
+            mpd::Change::Play { position, .. } => {
+                self.player.current_index = *position;
+                self.player.play_current()?;
+                self.list_state.select(Some(*position));
+                self.announce_track();
+            }
+            mpd::Change::Add(paths) => {
+                for path in paths {
+                    if self.player.index_of(path).is_none() {
+                        self.player.add_music(path)?;
+                    }
+                }
+                self.sync_cursor(&Command::Add(PathBuf::new()));
+            }
+            mpd::Change::Delete { positions, .. } => {
+                self.player.remove_tracks(positions);
+                self.sync_cursor(&Command::Remove);
+            }
+            mpd::Change::Clear => {
+                let all: Vec<usize> = (0..self.player.track_count()).collect();
+                self.player.remove_tracks(&all);
+                self.sync_cursor(&Command::Remove);
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the state to remote interfaces, and events for changes since the last call to
+    /// subscribed control clients and event streams
+    fn publish_events(&mut self) {
+        if let Some(mpris) = &self.mpris {
+            mpris.update(self.player.status());
+        }
+        if let Some(server) = &mut self.mpd {
+            server.update(&self.player);
+        }
+        if let Some(server) = &mut self.http {
+            server.update(&self.player);
+        }
+        for event in self.pending_events() {
+            if let Some(server) = &mut self.ipc {
+                server.publish(&event);
+            }
+            if let Some(server) = &self.http {
+                server.publish(&event);
+            }
+        }
+        self.run_pending_hooks();
+        self.update_now_playing();
+    }
+
+    /// Rewrite the now-playing files when a track has started or the state has changed
+    fn update_now_playing(&mut self) {
+        let Some(now_playing) = &self.now_playing else {
+            return;
+        };
+        let written = Some((self.player.starts(), self.player.state()));
+        if written != self.written {
+            now_playing.update(&self.player);
+            self.written = written;
+        }
+    }
+
+    /// Run the hooks for failed tracks and playback changes since the last call
+    fn run_pending_hooks(&mut self) {
+        for (path, error) in self.player.take_failures() {
+            if let Some(hooks) = &self.hooks {
+                let track = self.player.index_of(&path).map(|i| &self.player.tracks[i]);
+                hooks.run(HookEvent::Error, track, Duration::ZERO, Some(&error));
+            }
+        }
+
+        let (starts, state) = (self.player.starts(), self.player.state());
+        // A new start counts from playing, so a track paused straight away still reports both
+        let previous = if starts != self.hooked.0 {
+            self.run_hook(HookEvent::TrackStarted);
+            PlaybackState::Playing
+        } else {
+            self.hooked.1
+        };
+        if state != previous {
+            let event = match state {
+                PlaybackState::Playing => HookEvent::Resumed,
+                PlaybackState::Paused => HookEvent::Paused,
+                PlaybackState::Stopped => HookEvent::Stopped,
+            };
+            self.run_hook(event);
+        }
+        self.hooked = (starts, state);
+    }
+
+    /// Run the hook for an event about the current track
+    fn run_hook(&self, event: HookEvent) {
+        if let Some(hooks) = &self.hooks {
+            hooks.run(
+                event,
+                self.player.current_track(),
+                self.player.position(),
+                None,
+            );
+        }
+    }
+
+    /// Control socket events for what changed since the last call
+    fn pending_events(&mut self) -> Vec<IpcEvent> {
+        let mut events = Vec::new();
+        let state = self.player.state();
+        let track = self
+            .player
+            .current_track()
+            .filter(|_| state != PlaybackState::Stopped);
+
+        let path = track.map(|t| t.path.clone());
+        if path != self.published.0
+            && let Some(track) = track
+        {
+            events.push(IpcEvent::TrackChanged {
+                track: TrackInfo::from(track),
+            });
+        }
+        if state != self.published.1 {
+            events.push(match state {
+                PlaybackState::Playing => IpcEvent::Playing,
+                PlaybackState::Paused => IpcEvent::Paused,
+                PlaybackState::Stopped => IpcEvent::Stopped,
+            });
+        }
+        self.published = (path, state);
+
+        if state == PlaybackState::Playing
+            && self.position_published.elapsed() >= POSITION_EVENT_INTERVAL
+        {
+            events.push(IpcEvent::Position {
+                position: self.player.position().as_secs_f64(),
+                duration: track.and_then(|t| t.duration).map(|d| d.as_secs_f64()),
+            });
+            self.position_published = Instant::now();
+        }
+        events
+    }
+
+    pub fn toggle_help(&mut self) {
+        self.show_help = !self.show_help;
+    }
+
+    /// Re-sort the track list, keeping the selection on the same track
+    pub fn set_sort_order(&mut self, order: SortOrder) {
+        let selected_path = self
+            .list_state
+            .selected()
+            .and_then(|i| self.player.tracks.get(i))
+            .map(|t| t.path.clone());
+
+        self.player.set_sort_order(order);
+
+        if let Some(index) = selected_path.and_then(|p| self.player.index_of(p)) {
+            self.list_state.select(Some(index));
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    if let Some(CliCommand::Ctl(ctl)) = cli.command {
+        let socket = match ctl.socket {
+            Some(socket) => socket,
+            None => Config::load(cli.config.as_deref())?.ipc.socket_path(),
+        };
+        return ctl::run(&socket, ctl.command);
+    }
+    if let Some(CliCommand::Attach(attach)) = cli.command {
+        let config = Config::load(cli.config.as_deref())?;
+        let socket = attach.socket.unwrap_or_else(|| config.ipc.socket_path());
+        let keymap = Keymap::new(&config.keys).context("Invalid key bindings in config file")?;
+        let theme = Theme::from_config(&config)?;
+        setup_signal_handlers()?;
+        return attach::run(&socket, &keymap, &theme);
+    }
+
+    let mut commands = Vec::new();
+    for line in &cli.commands {
+        let command =
+            command::parse(line).with_context(|| format!("Invalid command '{}'", line))?;
+        commands.push((line.clone(), command));
+    }
+    let test_mode = cli.test;
+
+    // Logs go to stderr only when no TUI owns the terminal
+    let log = logging::init(test_mode || cli.daemon);
+    info!("Starting Terminal Music Player");
+
+    setup_signal_handlers()?;
+
+    // Load configuration, falling back to defaults when no file exists
+    let config = Config::load(cli.config.as_deref())?;
+    // The key bindings and theme are only needed to draw, which the daemon never does
+    let ui = if cli.daemon {
+        None
+    } else {
+        let keymap = Keymap::new(&config.keys).context("Invalid key bindings in config file")?;
+        Some((keymap, Theme::from_config(&config)?))
+    };
+
+    // Without paths on the command line, play the configured music roots
+    let mut paths = cli.paths;
+    if paths.is_empty() {
+        paths = config.library.roots.clone();
+    }
+
+    if paths.is_empty() {
+        Cli::command().print_help()?;
+        println!();
+        if let Some(path) = Config::default_path() {
+            println!("Config file: {}", path.display());
+        }
+        if let Some(path) = logging::log_path() {
+            println!("Log file: {}", path.display());
+        }
+        println!("Control socket: {}", config.ipc.socket_path().display());
+        println!(
+            "Supported formats: {}",
+            config.library.extensions.join(", ")
+        );
+        return Ok(());
+    }
+
+    // Create music player
+    let mut player = MusicPlayer::new(&config).context("Failed to initialize music player")?;
+
+    // Load music
+    for path in &paths {
+        player
+            .add_music(path)
+            .with_context(|| format!("Failed to load music from: {}", path.display()))?;
+    }
+
+    if player.track_count() == 0 {
+        let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        println!("❌ No supported audio files found in: {}", paths.join(", "));
+        println!(
+            "Supported formats: {}",
+            config.library.extensions.join(", ")
+        );
+        return Ok(());
+    }
+
+    // In test mode, just verify and exit
+    if test_mode {
+        println!("📁 Loaded {} tracks", player.track_count());
+        if let Err(e) = player.play_current() {
+            error!("Failed to start playback: {}", e);
+            return Err(e);
+        }
+        println!("✅ Test mode: Playback started successfully");
+        for (line, command) in &commands {
+            let message = player
+                .execute(command, &[player.current_index])
+                .with_context(|| format!("Command '{}' failed", line))?;
+            println!("✅ {}", message);
+        }
+        std::thread::sleep(Duration::from_millis(500));
+        player.stop();
+        return Ok(());
+    }
+
+    match ui {
+        Some((keymap, theme)) => run_tui(player, keymap, theme, &config, log, &commands),
+        None => run_daemon(player, &config, log, &commands),
+    }
+}
+
+fn run_tui(
+    player: MusicPlayer,
+    keymap: Keymap,
+    theme: Theme,
+    config: &Config,
+    log: Arc<LogBuffer>,
+    commands: &[(String, Command)],
+) -> Result<()> {
+    // Setup terminal
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    // Create app state
+    let mut app = App::new(player, keymap, theme, config, log);
+    app.start_ui(config);
+
+    app.start_playback();
+    app.start_remote(config);
+
+    // Commands from `-c` run once playback has started, as if typed at the prompt
+    let quit = app.run_startup_commands(commands);
+
+    let res = if quit {
+        Ok(())
+    } else {
+        run_app(&mut terminal, &mut app)
+    };
 
     // Restore terminal
     disable_raw_mode()?;
@@ -532,289 +1949,1012 @@ fn run_tui(player: MusicPlayer) -> Result<()> {
         println!("{err:?}");
     }
 
-    // Stop playback
+    // Stop playback, clearing the now-playing files
     app.player.stop();
+    app.update_now_playing();
 
     Ok(())
 }
 
+/// Play without a terminal UI, driven by the remote interfaces and signals until told to stop
+fn run_daemon(
+    player: MusicPlayer,
+    config: &Config,
+    log: Arc<LogBuffer>,
+    commands: &[(String, Command)],
+) -> Result<()> {
+    // Remote requests and startup commands are answered by the same `App` code as in the TUI.
+    // Its key bindings and theme are never used without a screen, so the defaults stand in,
+    // and `start_ui` is skipped so nothing is kept or prepared for drawing.
+    let mut app = App::new(player, Keymap::default(), Theme::default(), config, log);
+
+    app.start_playback();
+    app.start_remote(config);
+    // Without its socket a second daemon could only be stopped with a signal
+    if config.ipc.enabled && app.ipc.is_none() {
+        app.player.stop();
+        anyhow::bail!("Not running headless without the control socket");
+    }
+
+    let quit = app.run_startup_commands(commands);
+    if !quit {
+        info!(
+            "Running headless (pid {}); SIGUSR1 skips a track, SIGTERM stops",
+            std::process::id()
+        );
+        while !SHUTDOWN.load(Ordering::Relaxed) && !app.handle_remote() {
+            app.advance();
+            app.publish_events();
+            std::thread::sleep(app.poll_interval);
+        }
+    }
+
+    info!("Stopping headless player");
+    app.player.stop();
+    app.update_now_playing();
+    Ok(())
+}
+
 fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> io::Result<()> {
     loop {
         terminal.draw(|f| ui(f, app))?;
+        // Graphics protocol images are written around ratatui, after the frame
+        if let Some(artwork) = &mut app.artwork {
+            artwork.flush()?;
+        }
 
-        if event::poll(Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press && handle_key_event(key, app).unwrap_or(false) {
-                    return Ok(());
+        // Redraw more often while the visualizer is animating
+        let timeout = if app.visualizer_visible() {
+            app.poll_interval.min(VISUALIZER_FRAME)
+        } else {
+            app.poll_interval
+        };
+
+        if event::poll(timeout)? {
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    match handle_key_event(key, app) {
+                        Ok(true) => return Ok(()),
+                        Ok(false) => {}
+                        Err(e) => app.messages.error(e),
+                    }
                 }
+                Event::Mouse(mouse) => handle_mouse_event(mouse, app),
+                // Resizing clears the screen, taking any drawn images with it
+                Event::Resize(..) => {
+                    if let Some(artwork) = &mut app.artwork {
+                        artwork.invalidate();
+                    }
+                }
+                _ => {}
             }
         }
 
         // Check if we should exit
-        if SHUTDOWN.load(Ordering::Relaxed) {
+        if SHUTDOWN.load(Ordering::Relaxed) || app.handle_remote() {
             return Ok(());
         }
 
         // Auto-advance to next track if current one finished
-        if app.player.is_empty() && !app.player.is_paused {
-            let _ = app.player.next_track();
-            // Sync the list selection with the new current track
-            app.list_state.select(Some(app.player.current_index));
-        }
+        app.advance();
+
+        app.publish_events();
     }
 }
 
 fn handle_key_event(key: KeyEvent, app: &mut App) -> Result<bool> {
-    match key.code {
+    // The command line takes all keys while it is open
+    if app
+        .command_line
+        .as_ref()
+        .is_some_and(CommandLine::is_active)
+    {
+        return Ok(handle_command_line_key(key, app));
+    }
+
+    // Esc leaves visual mode and drops the selection before it would quit
+    if key.code == KeyCode::Esc
+        && app.pending.is_empty()
+        && !app.messages.visible
+        && app
+            .selection
+            .as_ref()
+            .is_some_and(|s| s.anchor.is_some() || !s.tracks.is_empty())
+    {
+        app.clear_selection();
+        return Ok(false);
+    }
+
+    let (action, count) = match app.pending.feed(key, &app.keymap) {
+        Input::Pending => return Ok(false),
+        Input::Action(action, count) => (action, count),
+        Input::Mark(Action::SetMark, Some(name)) => {
+            app.set_mark(name);
+            return Ok(false);
+        }
+        Input::Mark(_, Some(name)) => {
+            app.jump_to_mark(name);
+            return Ok(false);
+        }
+        Input::Mark(_, None) => {
+            app.messages.warn("Marks are named a-z");
+            return Ok(false);
+        }
+    };
+
+    // The message history popup takes over scrolling and closing while it is open
+    if app.messages.visible {
+        match action {
+            Action::Down => {
+                app.messages.scroll_down();
+                return Ok(false);
+            }
+            Action::Up => {
+                app.messages.scroll_up();
+                return Ok(false);
+            }
+            Action::Quit | Action::ToggleMessages => {
+                app.messages.toggle_history();
+                return Ok(false);
+            }
+            _ => {}
+        }
+    }
+
+    match action {
         // Quit
-        KeyCode::Char('q') | KeyCode::Esc => return Ok(true),
+        Action::Quit => return Ok(true),
 
         // Vim-style navigation (only moves selection, doesn't change playback)
-        KeyCode::Char('j') | KeyCode::Down => {
-            let i = match app.list_state.selected() {
-                Some(i) => {
-                    if i >= app.player.tracks.len() - 1 {
-                        0
-                    } else {
-                        i + 1
-                    }
-                }
-                None => 0,
-            };
-            app.list_state.select(Some(i));
+        // A bare `j` or `k` wraps around the list; with a count it stops at the end, like vim
+        Action::Down => match count {
+            Some(n) => app.select_index(app.list_state.selected().unwrap_or(0).saturating_add(n)),
+            None => app.select_next(),
+        },
+        Action::Up => match count {
+            Some(n) => app.select_index(app.list_state.selected().unwrap_or(0).saturating_sub(n)),
+            None => app.select_previous(),
+        },
+        Action::GoTop => app.select_index(count.map_or(0, |n| n.saturating_sub(1))),
+        Action::GoBottom => {
+            app.select_index(count.map_or(usize::MAX, |n| n.saturating_sub(1)));
         }
-        KeyCode::Char('k') | KeyCode::Up => {
-            let i = match app.list_state.selected() {
-                Some(i) => {
-                    if i == 0 {
-                        app.player.tracks.len() - 1
-                    } else {
-                        i - 1
-                    }
-                }
-                None => 0,
+        Action::HalfPageDown | Action::HalfPageUp | Action::PageDown | Action::PageUp => {
+            let page = match action {
+                Action::HalfPageDown | Action::HalfPageUp => (app.page_size() / 2).max(1),
+                _ => app.page_size(),
             };
-            app.list_state.select(Some(i));
+            let delta = (page * count.unwrap_or(1)) as isize;
+            match action {
+                Action::HalfPageDown | Action::PageDown => app.scroll_by(delta),
+                _ => app.scroll_by(-delta),
+            }
+        }
+        Action::JumpToPlaying => app.jump_to_playing(false),
+        Action::CenterPlaying => app.jump_to_playing(true),
+        // Handled above, once the mark name has been typed
+        Action::SetMark | Action::JumpToMark => {}
+
+        // Selection and batch operations, on the cursor track when nothing is selected
+        Action::VisualMode => app.toggle_visual(),
+        Action::ToggleSelect => {
+            for _ in 0..count.unwrap_or(1) {
+                app.toggle_select();
+            }
         }
+        Action::Enqueue => return app.run_command(&Command::Enqueue),
+        Action::RemoveTracks => return app.run_command(&Command::Remove),
+        // These open the prompt so the playlist or tag can be typed, or the deletion confirmed
+        Action::AddToPlaylist => app.open_command_line("append "),
+        Action::EditTags => app.open_command_line("tag "),
+        Action::TrashTracks => app.open_command_line("trash"),
 
         // Playback controls
-        KeyCode::Char(' ') => {
+        Action::PlayPause => {
             // If a different track is selected, play it. Otherwise, just pause/unpause
-            if let Some(selected) = app.list_state.selected() {
-                if selected != app.player.current_index {
+            match app.list_state.selected() {
+                Some(selected) if selected != app.player.current_index => {
                     app.player.current_index = selected;
                     app.player.play_current()?;
-                } else {
+                    app.announce_track();
+                }
+                // After a stop there is nothing to resume, so start the track again
+                _ if app.player.is_stopped => {
+                    app.player.play_current()?;
+                    app.announce_track();
+                }
+                _ => {
                     app.player.toggle_pause();
+                    app.messages.info(if app.player.is_paused {
+                        "Paused"
+                    } else {
+                        "Resumed"
+                    });
                 }
-            } else {
-                app.player.toggle_pause();
             }
         }
-        KeyCode::Enter => {
+        Action::PlaySelected => {
             if let Some(selected) = app.list_state.selected() {
                 app.player.current_index = selected;
                 app.player.play_current()?;
+                app.announce_track();
             }
         }
-        KeyCode::Char('n') => {
-            app.player.next_track()?;
-            // Sync the list selection with the current playing track
+        Action::NextTrack => {
+            // Keep the list selection on the playing track even if it failed to play
+            let result = app.player.skip_tracks(count.unwrap_or(1), true);
             app.list_state.select(Some(app.player.current_index));
+            app.report_skipped(result?);
+            app.announce_track();
         }
-        KeyCode::Char('p') => {
-            app.player.previous_track()?;
-            // Sync the list selection with the current playing track
+        Action::PreviousTrack => {
+            let result = app.player.skip_tracks(count.unwrap_or(1), false);
             app.list_state.select(Some(app.player.current_index));
+            app.report_skipped(result?);
+            app.announce_track();
         }
 
         // Advanced controls
-        KeyCode::Char('s') => app.player.toggle_shuffle(),
-        KeyCode::Char('r') => app.player.cycle_repeat(),
-        KeyCode::Char('S') => app.player.stop(),
+        Action::ToggleShuffle => {
+            app.player.toggle_shuffle();
+            app.messages.info(format!(
+                "Shuffle {}",
+                if app.player.is_shuffled { "on" } else { "off" }
+            ));
+        }
+        Action::CycleRepeat => {
+            app.player.cycle_repeat();
+            app.messages
+                .info(format!("Repeat: {}", app.player.repeat_mode));
+        }
+        Action::Stop => {
+            app.player.stop();
+            app.messages.info("Stopped");
+        }
+        Action::CycleSort => {
+            let mut order = app.player.sort_order();
+            order.key = order.key.next();
+            app.set_sort_order(order);
+            app.messages.info(format!("Sorted by {}", order));
+        }
+        Action::ReverseSort => {
+            let mut order = app.player.sort_order();
+            order.descending = !order.descending;
+            app.set_sort_order(order);
+            app.messages.info(format!("Sorted by {}", order));
+        }
+
+        Action::ToggleTimeDisplay => {
+            app.show_remaining = !app.show_remaining;
+            app.messages.info(if app.show_remaining {
+                "Showing remaining time"
+            } else {
+                "Showing elapsed time"
+            });
+        }
+        Action::ToggleVisualizer => {
+            app.toggle_visualizer();
+            app.messages.info(format!(
+                "Visualizer {}",
+                if app.visualizer_visible() {
+                    "on"
+                } else {
+                    "off"
+                }
+            ));
+        }
+        Action::ToggleLyrics => {
+            app.toggle_lyrics();
+            app.messages.info(format!(
+                "Lyrics {}",
+                if app.show_lyrics { "shown" } else { "hidden" }
+            ));
+        }
+        Action::ToggleDetails => {
+            app.toggle_details();
+            app.messages.info(format!(
+                "Track details {}",
+                if app.show_details { "shown" } else { "hidden" }
+            ));
+        }
 
-        // Help
-        KeyCode::Char('?') | KeyCode::Char('h') => app.toggle_help(),
+        Action::CommandLine => {
+            if let Some(line) = &mut app.command_line {
+                line.open();
+            }
+        }
 
-        _ => {}
+        // Help, messages and logs
+        Action::ToggleMessages => app.messages.toggle_history(),
+        Action::ToggleLog => app.show_log = !app.show_log,
+        Action::CycleLogLevel => {
+            app.log_level = match app.log_level {
+                LevelFilter::Error => LevelFilter::Warn,
+                LevelFilter::Warn => LevelFilter::Info,
+                LevelFilter::Info => LevelFilter::Debug,
+                LevelFilter::Debug => LevelFilter::Trace,
+                _ => LevelFilter::Error,
+            };
+            app.messages
+                .info(format!("Log pane shows {} and above", app.log_level));
+        }
+        Action::ToggleHelp => app.toggle_help(),
     }
 
     Ok(false)
 }
 
+/// Edit the `:` prompt, returning true when a command asks to quit
+fn handle_command_line_key(key: KeyEvent, app: &mut App) -> bool {
+    let Some(line) = &mut app.command_line else {
+        return false;
+    };
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Esc => line.close(),
+        KeyCode::Char('c') if ctrl => line.close(),
+        KeyCode::Enter => {
+            let input = line.submit();
+            if input.is_empty() {
+                line.close();
+                return false;
+            }
+            let result = command::parse(&input).and_then(|command| app.run_command(&command));
+            let Some(line) = &mut app.command_line else {
+                return false;
+            };
+            match result {
+                Ok(quit) => {
+                    line.close();
+                    return quit;
+                }
+                // Keep the prompt open so the command can be corrected
+                Err(e) => {
+                    warn!("Command '{}' failed: {:#}", input, e);
+                    line.error = Some(format!("{:#}", e));
+                }
+            }
+        }
+        KeyCode::Tab => line.complete(true),
+        KeyCode::BackTab => line.complete(false),
+        KeyCode::Up => line.history_previous(),
+        KeyCode::Down => line.history_next(),
+        KeyCode::Left => line.move_left(),
+        KeyCode::Right => line.move_right(),
+        KeyCode::Home => line.move_home(),
+        KeyCode::End => line.move_end(),
+        KeyCode::Char('a') if ctrl => line.move_home(),
+        KeyCode::Char('e') if ctrl => line.move_end(),
+        KeyCode::Char('u') if ctrl => line.delete_to_start(),
+        KeyCode::Char('w') if ctrl => line.delete_word(),
+        // Backspace on an empty prompt closes it, as in vim
+        KeyCode::Backspace if line.input().is_empty() => line.close(),
+        KeyCode::Backspace => line.backspace(),
+        KeyCode::Delete => line.delete(),
+        KeyCode::Char(c) if !ctrl => line.insert(c),
+        _ => {}
+    }
+    false
+}
+
+fn handle_mouse_event(mouse: MouseEvent, app: &mut App) {
+    // Popups cover the panes, so ignore the mouse while one is open
+    if app.show_help || app.messages.visible {
+        return;
+    }
+
+    let (column, row) = (mouse.column, mouse.row);
+    match mouse.kind {
+        MouseEventKind::ScrollDown if contains(app.track_area, column, row) => app.scroll_by(1),
+        MouseEventKind::ScrollUp if contains(app.track_area, column, row) => app.scroll_by(-1),
+        MouseEventKind::Down(MouseButton::Left) => {
+            if let Some(index) = app.track_at(column, row) {
+                app.list_state.select(Some(index));
+
+                // A second click on the same track plays it
+                let now = Instant::now();
+                let double_click = app.last_click.is_some_and(|(time, last)| {
+                    last == index && now.duration_since(time) <= DOUBLE_CLICK_INTERVAL
+                });
+                if double_click {
+                    app.last_click = None;
+                    app.player.current_index = index;
+                    match app.player.play_current() {
+                        Ok(()) => app.announce_track(),
+                        Err(e) => app.messages.error(e),
+                    }
+                } else {
+                    app.last_click = Some((now, index));
+                }
+            } else if contains(inner_area(app.progress_area), column, row) {
+                app.seek_preview = Some(app.progress_at(column));
+            }
+        }
+        MouseEventKind::Drag(MouseButton::Left) if app.seek_preview.is_some() => {
+            app.seek_preview = Some(app.progress_at(column));
+        }
+        MouseEventKind::Up(MouseButton::Left) => {
+            if let Some(ratio) = app.seek_preview.take() {
+                match app.player.seek_to_ratio(ratio) {
+                    Ok(position) => app
+                        .messages
+                        .info(format!("Seeked to {}", format_time(position))),
+                    Err(e) => app.messages.warn(e),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Format a duration as `m:ss`, or `h:mm:ss` for an hour or longer
+pub fn format_time(duration: Duration) -> String {
+    let total = duration.as_secs();
+    let (hours, minutes, seconds) = (total / 3600, (total / 60) % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Get the area inside a bordered block
+fn inner_area(area: Rect) -> Rect {
+    Rect {
+        x: area.x.saturating_add(1),
+        y: area.y.saturating_add(1),
+        width: area.width.saturating_sub(2),
+        height: area.height.saturating_sub(2),
+    }
+}
+
+/// Format a file size with binary units, e.g. "7.4 MiB"
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Label/value rows of the Now Playing details panel
+fn detail_lines(track: &Track, details: &TrackDetails, theme: &Theme) -> Vec<Line<'static>> {
+    let unknown = || "unknown".to_string();
+    let replay_gain = |gain: &Option<String>, peak: &Option<String>| match (gain, peak) {
+        (Some(gain), Some(peak)) => format!("{} (peak {})", gain, peak),
+        (Some(gain), None) => gain.clone(),
+        (None, Some(peak)) => format!("peak {}", peak),
+        (None, None) => "none".to_string(),
+    };
+
+    let rows = [
+        ("Artist", track.artist.clone().unwrap_or_else(unknown)),
+        ("Album", track.album.clone().unwrap_or_else(unknown)),
+        ("Year", details.year.clone().unwrap_or_else(unknown)),
+        ("Codec", details.codec.clone().unwrap_or_else(unknown)),
+        (
+            "Sample rate",
+            details
+                .sample_rate
+                .map(|rate| format!("{:.1} kHz", f64::from(rate) / 1000.0))
+                .unwrap_or_else(unknown),
+        ),
+        (
+            "Bit depth",
+            details
+                .bit_depth
+                .map(|bits| format!("{}-bit", bits))
+                .unwrap_or_else(unknown),
+        ),
+        (
+            "Channels",
+            details
+                .channels
+                .map(|channels| match channels {
+                    1 => "1 (mono)".to_string(),
+                    2 => "2 (stereo)".to_string(),
+                    n => n.to_string(),
+                })
+                .unwrap_or_else(unknown),
+        ),
+        (
+            "Bitrate",
+            details
+                .bitrate
+                .map(|kbps| format!("{} kbps (average)", kbps))
+                .unwrap_or_else(unknown),
+        ),
+        (
+            "File size",
+            details.file_size.map(format_size).unwrap_or_else(unknown),
+        ),
+        (
+            "ReplayGain",
+            format!(
+                "track {}, album {}",
+                replay_gain(&details.track_gain, &details.track_peak),
+                replay_gain(&details.album_gain, &details.album_peak)
+            ),
+        ),
+        ("Play count", track.play_count.to_string()),
+        ("Path", track.path.display().to_string()),
+    ];
+
+    rows.into_iter()
+        .map(|(label, value)| {
+            Line::from(vec![
+                Span::styled(format!("{:>11}: ", label), theme.title),
+                Span::styled(value, theme.value),
+            ])
+        })
+        .collect()
+}
+
+/// Check whether a screen position lies inside an area
+fn contains(area: Rect, column: u16, row: u16) -> bool {
+    area.contains(ratatui::layout::Position::new(column, row))
+}
+
 fn ui(f: &mut Frame, app: &mut App) {
+    let theme = app.theme;
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Min(3),    // Track list
-            Constraint::Length(3), // Currently playing
-            Constraint::Length(3), // Progress bar
+            Constraint::Min(3), // Track list
+            Constraint::Length(if app.visualizer_visible() {
+                VISUALIZER_HEIGHT
+            } else {
+                0
+            }), // Visualizer
+            Constraint::Length(if app.show_details {
+                DETAILS_PANE_HEIGHT
+            } else if app.artwork.is_some() {
+                ART_PANE_HEIGHT
+            } else {
+                3
+            }), // Currently playing
+            Constraint::Length(if app.waveforms.is_some() {
+                WAVEFORM_HEIGHT
+            } else {
+                3
+            }), // Progress bar
+            Constraint::Length(if app.show_log { LOG_PANE_HEIGHT } else { 0 }), // Log
             Constraint::Length(3), // Controls
         ])
         .split(f.area());
 
+    // The lyrics pane shares the top row with the track list
+    let (track_area, lyrics_area) = if app.show_lyrics {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+            .split(chunks[0]);
+        (columns[0], Some(columns[1]))
+    } else {
+        (chunks[0], None)
+    };
+
+    // Remember where panes were drawn so mouse events hit the current layout
+    app.track_area = track_area;
+    app.progress_area = chunks[3];
+
     // Track list
+    let selection = app.selection_mask();
     let items: Vec<ListItem> = app
         .player
         .tracks
         .iter()
         .enumerate()
         .map(|(i, track)| {
-            let style = if i == app.player.current_index {
-                Style::default()
-                    .fg(Color::Yellow)
-                    .add_modifier(Modifier::BOLD)
+            // The playing style goes over the selection; the cursor highlight goes over both
+            let mut style = if selection[i] {
+                theme.selected
             } else {
                 Style::default()
             };
+            if i == app.player.current_index {
+                style = style.patch(theme.playing);
+            }
 
-            let prefix = if i == app.player.current_index {
-                if app.player.is_paused { "⏸ " } else { "♪ " }
+            let prefix = if track.unplayable.is_some() {
+                "✖ "
+            } else if i == app.player.current_index {
+                if app.player.is_stopped {
+                    "⏹ "
+                } else if app.player.is_paused {
+                    "⏸ "
+                } else {
+                    "♪ "
+                }
             } else {
                 "  "
             };
 
-            ListItem::new(format!("{}{}", prefix, track.title)).style(style)
+            let mut spans = vec![Span::raw(format!("{}{}", prefix, track.title))];
+            if let Some(position) = app.player.queue_position(&track.path) {
+                spans.push(Span::styled(format!(" [{}]", position), theme.value));
+            }
+            if let Some(unplayable) = &track.unplayable {
+                spans.push(Span::styled(
+                    format!(" ({})", unplayable.reason),
+                    theme.unplayable,
+                ));
+            }
+            ListItem::new(Line::from(spans)).style(style)
         })
         .collect();
 
+    let mut title = format!(
+        "Tracks ({}/{})",
+        app.list_state.selected().map(|i| i + 1).unwrap_or(1),
+        app.player.tracks.len()
+    );
+    let selected = selection.iter().filter(|s| **s).count();
+    if app.selection.as_ref().is_some_and(|s| s.anchor.is_some()) {
+        title.push_str(&format!(" -- VISUAL ({}) --", selected));
+    } else if selected > 0 {
+        title.push_str(&format!(" - {} selected", selected));
+    }
+
     let tracks = List::new(items)
-        .block(Block::default().borders(Borders::ALL).title(format!(
-            "Tracks ({}/{})",
-            app.list_state.selected().map(|i| i + 1).unwrap_or(1),
-            app.player.tracks.len()
-        )))
-        .highlight_style(Style::default().bg(Color::DarkGray));
+        .block(theme.block(title))
+        .highlight_style(theme.highlight);
 
-    f.render_stateful_widget(tracks, chunks[0], &mut app.list_state);
+    f.render_stateful_widget(tracks, track_area, &mut app.list_state);
 
-    // Currently playing
-    let current_track = app
-        .player
-        .current_track()
-        .map(|t| t.title.as_str())
-        .unwrap_or("No track selected");
+    // Lyrics
+    if let Some(area) = lyrics_area {
+        let position = app.player.position();
+        let block = theme.block("Lyrics");
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+
+        match app.current_lyrics() {
+            Some(lyrics) => f.render_widget(lyrics.view(position, theme.lyrics_current), inner),
+            None => f.render_widget(Paragraph::new("No lyrics found").centered(), inner),
+        }
+    }
+
+    // Spectrum visualizer
+    if let Some(visualizer) = &mut app.visualizer
+        && visualizer.visible
+    {
+        let block = theme.block("Spectrum");
+        let inner = block.inner(chunks[1]);
+        visualizer.update(&app.player.tap, usize::from(inner.width));
+        f.render_widget(block, chunks[1]);
+        f.render_widget(
+            visualizer.spectrum(theme.spectrum, theme.spectrum_peak),
+            inner,
+        );
+    }
 
-    let status = if app.player.is_paused {
+    // Currently playing
+    let details = if app.show_details {
+        app.current_details().cloned()
+    } else {
+        None
+    };
+    let track = app.player.current_track();
+    let status = if app.player.is_stopped {
+        "⏹ Stopped"
+    } else if app.player.is_paused {
         "⏸ Paused"
     } else {
         "♪ Playing"
     };
 
-    let now_playing = Paragraph::new(format!("{}: {}", status, current_track))
-        .block(Block::default().borders(Borders::ALL).title("Now Playing"))
-        .alignment(Alignment::Center);
+    let mut lines = vec![Line::from(format!(
+        "{}: {}",
+        status,
+        track
+            .map(|t| t.title.as_str())
+            .unwrap_or("No track selected")
+    ))];
+
+    let block = theme.block("Now Playing");
+    let mut text_area = block.inner(chunks[2]);
+    f.render_widget(block, chunks[2]);
+
+    if let (Some(track), Some(details)) = (track, &details) {
+        lines.extend(detail_lines(track, details, &theme));
+    }
 
-    f.render_widget(now_playing, chunks[1]);
+    // The taller album art pane also has room for the artist and album
+    if let Some(artwork) = &mut app.artwork
+        && let Some(track) = track
+    {
+        if details.is_none() {
+            lines.extend(
+                [&track.artist, &track.album]
+                    .into_iter()
+                    .flatten()
+                    .map(|text| Line::styled(text.clone(), theme.value)),
+            );
+        }
+
+        // Leave the pane free for popups, which images would cover
+        if !app.show_help && !app.messages.visible {
+            // Covers are usually square and cells about twice as tall as wide
+            let cols = (text_area.height * 2).min(text_area.width / 2);
+            if let Some(art) = artwork.get(&track.path, cols, text_area.height) {
+                let (width, height) = art.size();
+                let top = text_area.y + text_area.height.saturating_sub(height) / 2;
+                let art_area = Rect::new(text_area.x, top, width, height).intersection(text_area);
+                artwork.render(&art, art_area, f.buffer_mut());
+
+                text_area.x += width + 1;
+                text_area.width = text_area.width.saturating_sub(width + 1);
+            }
+        }
+    }
+
+    let now_playing = if details.is_some() {
+        Paragraph::new(lines)
+    } else {
+        // Center the text vertically in the space left next to the art
+        let padding = text_area.height.saturating_sub(lines.len() as u16) / 2;
+        text_area.y += padding;
+        text_area.height -= padding;
+        Paragraph::new(lines).alignment(Alignment::Center)
+    };
+
+    f.render_widget(now_playing, text_area);
 
     // Progress bar
-    let progress = app.player.get_progress();
-    let gauge = Gauge::default()
-        .block(Block::default().borders(Borders::ALL).title("Progress"))
-        .gauge_style(Style::default().fg(Color::Green))
-        .ratio(progress);
+    let duration = app.player.current_track().and_then(|t| t.duration);
+    let progress = app.seek_preview.or_else(|| app.player.get_progress());
+    let position = match (app.seek_preview, duration) {
+        (Some(ratio), Some(duration)) => duration.mul_f64(ratio),
+        _ => app.player.position(),
+    };
+
+    let label = match duration {
+        Some(duration) if app.show_remaining => format!(
+            "-{} / {}",
+            format_time(duration.saturating_sub(position)),
+            format_time(duration)
+        ),
+        Some(duration) => format!("{} / {}", format_time(position), format_time(duration)),
+        None => format!("{} / --:-- (unknown duration)", format_time(position)),
+    };
+
+    let progress_title = format!(
+        "Progress ({}) - Track {}/{}",
+        if app.show_remaining {
+            "Remaining"
+        } else {
+            "Elapsed"
+        },
+        app.player.current_index + 1,
+        app.player.track_count()
+    );
+
+    // Draw the waveform once its summary is ready, and a plain gauge until then
+    let waveform = app.waveforms.as_ref().and_then(|store| {
+        let waveform = app
+            .player
+            .current_track()
+            .and_then(|track| store.get(&track.path));
+        // Start on the next track early so its summary is ready when it plays
+        if let Some(next) = app.player.tracks.get(app.player.current_index + 1) {
+            store.get(&next.path);
+        }
+        waveform
+    });
+
+    match waveform {
+        Some(waveform) => {
+            let block = theme
+                .block(progress_title)
+                .title_bottom(Line::from(label).centered());
+            let inner = block.inner(chunks[3]);
+            f.render_widget(block, chunks[3]);
+            f.render_widget(
+                WaveformBar::new(
+                    &waveform,
+                    progress.unwrap_or(0.0),
+                    theme.waveform_played,
+                    theme.waveform,
+                ),
+                inner,
+            );
+        }
+        None => {
+            let gauge = Gauge::default()
+                .block(theme.block(progress_title))
+                .gauge_style(theme.progress)
+                .ratio(progress.unwrap_or(0.0))
+                .label(label);
+
+            f.render_widget(gauge, chunks[3]);
+        }
+    }
 
-    f.render_widget(gauge, chunks[2]);
+    // Log pane, newest records at the bottom
+    if app.show_log {
+        let block = theme.block(format!("Log ({} and above)", app.log_level));
+        let height = usize::from(block.inner(chunks[4]).height);
+        let lines: Vec<Line> = app
+            .log
+            .tail(app.log_level, height)
+            .into_iter()
+            .map(|entry| {
+                let style = match entry.level {
+                    log::Level::Error => theme.error,
+                    log::Level::Warn => theme.warning,
+                    log::Level::Info => theme.info,
+                    log::Level::Debug | log::Level::Trace => theme.base,
+                };
+                Line::styled(
+                    format!(
+                        "{} {:<5} {}: {}",
+                        entry.time, entry.level, entry.target, entry.message
+                    ),
+                    style,
+                )
+            })
+            .collect();
+        f.render_widget(Paragraph::new(lines).block(block), chunks[4]);
+    }
 
     // Controls info
+    let help_keys = app.keymap.keys_for(Action::ToggleHelp).join("/");
     let controls_text = if app.show_help {
-        vec![
-            Line::from(vec![
-                Span::raw("Navigation: "),
-                Span::styled("j/↓", Style::default().fg(Color::Yellow)),
-                Span::raw(" next, "),
-                Span::styled("k/↑", Style::default().fg(Color::Yellow)),
-                Span::raw(" prev"),
-            ]),
-            Line::from(vec![
-                Span::raw("Playback: "),
-                Span::styled("Space", Style::default().fg(Color::Yellow)),
-                Span::raw(" play/pause, "),
-                Span::styled("Enter", Style::default().fg(Color::Yellow)),
-                Span::raw(" play selected, "),
-                Span::styled("n", Style::default().fg(Color::Yellow)),
-                Span::raw(" next, "),
-                Span::styled("p", Style::default().fg(Color::Yellow)),
-                Span::raw(" prev"),
-            ]),
-            Line::from(vec![
-                Span::raw("Other: "),
-                Span::styled("s", Style::default().fg(Color::Yellow)),
-                Span::raw(" shuffle, "),
-                Span::styled("r", Style::default().fg(Color::Yellow)),
-                Span::raw(" repeat, "),
-                Span::styled("S", Style::default().fg(Color::Yellow)),
-                Span::raw(" stop, "),
-                Span::styled("q", Style::default().fg(Color::Yellow)),
-                Span::raw(" quit, "),
-                Span::styled("?", Style::default().fg(Color::Yellow)),
-                Span::raw(" help"),
-            ]),
-        ]
+        vec![Line::from(vec![
+            Span::raw("Press "),
+            Span::styled(help_keys.clone(), theme.key),
+            Span::raw(" to close help"),
+        ])]
+    } else if let Some(message) = app.messages.toast() {
+        vec![Line::styled(
+            format!("{} {}", message.level.icon(), message.text),
+            theme.message(message.level),
+        )]
     } else {
         vec![Line::from(vec![
             Span::raw("Shuffle: "),
             Span::styled(
                 if app.player.is_shuffled { "On" } else { "Off" },
                 if app.player.is_shuffled {
-                    Style::default().fg(Color::Green)
+                    theme.enabled
                 } else {
-                    Style::default().fg(Color::Red)
+                    theme.disabled
                 },
             ),
             Span::raw(" | Repeat: "),
-            Span::styled(
-                app.player.repeat_mode.to_string(),
-                Style::default().fg(Color::Cyan),
-            ),
+            Span::styled(app.player.repeat_mode.to_string(), theme.value),
+            Span::raw(" | Sort: "),
+            Span::styled(app.player.sort_order().to_string(), theme.value),
             Span::raw(" | Press "),
-            Span::styled("?", Style::default().fg(Color::Yellow)),
+            Span::styled(help_keys.clone(), theme.key),
             Span::raw(" for help"),
         ])]
     };
 
-    let controls = Paragraph::new(controls_text)
-        .block(Block::default().borders(Borders::ALL).title("Controls"))
-        .alignment(Alignment::Center);
+    // Show the count and keys typed so far while a command is pending
+    let pending = app.pending.describe();
+    let controls_title = if pending.is_empty() {
+        "Controls".to_string()
+    } else {
+        format!("Controls [{}]", pending)
+    };
 
-    f.render_widget(controls, chunks[3]);
+    if let Some(line) = app.command_line.as_ref().filter(|line| line.is_active()) {
+        // The prompt replaces the controls, with completion candidates along the bottom border
+        let mut block = theme.block("Command");
+        if let Some((candidates, current)) = line.candidates() {
+            let mut spans = Vec::new();
+            for (i, candidate) in candidates.iter().enumerate() {
+                // Only the completed word differs between candidates
+                let word = candidate
+                    .trim_end_matches('/')
+                    .rsplit([' ', '/'])
+                    .next()
+                    .unwrap_or(candidate);
+                let style = if i == current {
+                    theme.key
+                } else {
+                    Style::default()
+                };
+                spans.push(Span::raw(" "));
+                spans.push(Span::styled(word.to_string(), style));
+            }
+            spans.push(Span::raw(" "));
+            block = block.title_bottom(Line::from(spans));
+        }
+        let inner = block.inner(chunks[5]);
+
+        let input = line.input();
+        let mut spans = vec![Span::raw(":"), Span::raw(input)];
+        if let Some(error) = &line.error {
+            spans.push(Span::styled(format!("  ✖ {}", error), theme.error));
+        }
+        f.render_widget(Paragraph::new(Line::from(spans)).block(block), chunks[5]);
+
+        let before_cursor: String = input.chars().take(line.cursor()).collect();
+        let column = inner.x + 1 + Line::from(before_cursor).width() as u16;
+        f.set_cursor_position((column.min(inner.right().saturating_sub(1)), inner.y));
+    } else {
+        let controls = Paragraph::new(controls_text)
+            .block(theme.block(controls_title))
+            .alignment(Alignment::Center);
+
+        f.render_widget(controls, chunks[5]);
+    }
 
     // Show help overlay if requested
     if app.show_help {
         let help_area = centered_rect(60, 50, f.area());
         f.render_widget(Clear, help_area);
 
-        let help_text = vec![
-            Line::from("Vim-Inspired Music Player"),
-            Line::from(""),
-            Line::from("Navigation:"),
-            Line::from("  j, ↓      - Move down in track list"),
-            Line::from("  k, ↑      - Move up in track list"),
-            Line::from(""),
-            Line::from("Playback:"),
-            Line::from("  Space     - Play selected track or pause/unpause"),
-            Line::from("  Enter     - Play selected track"),
-            Line::from("  n         - Next track"),
-            Line::from("  p         - Previous track"),
-            Line::from("  S         - Stop playback"),
-            Line::from(""),
-            Line::from("Modes:"),
-            Line::from("  s         - Toggle shuffle"),
-            Line::from("  r         - Cycle repeat mode (Off/One/All)"),
-            Line::from(""),
-            Line::from("Other:"),
-            Line::from("  q, Esc    - Quit"),
-            Line::from("  ?, h      - Toggle this help"),
-            Line::from(""),
-            Line::from("Press any key to close help..."),
-        ];
+        // Generated from the active keymap so it always matches the real bindings
+        let mut help_text = vec![Line::from("Vim-Inspired Music Player")];
+        for (category, actions) in app.keymap.help() {
+            if actions.is_empty() {
+                continue;
+            }
+            help_text.push(Line::from(""));
+            help_text.push(Line::from(format!("{}:", category)));
+            for (action, keys) in actions {
+                help_text.push(Line::from(vec![
+                    Span::raw("  "),
+                    Span::styled(format!("{:<10}", keys.join(", ")), theme.key),
+                    Span::raw(format!("- {}", action.description())),
+                ]));
+            }
+        }
+        help_text.push(Line::from(""));
+        help_text.push(Line::from(format!("Press {} to close help...", help_keys)));
 
         let help_popup = Paragraph::new(help_text)
-            .block(Block::default().borders(Borders::ALL).title("Help"))
+            .block(theme.block("Help").style(theme.popup))
+            .style(theme.popup)
             .alignment(Alignment::Left);
 
         f.render_widget(help_popup, help_area);
     }
+
+    // Message history, newest at the bottom
+    if app.messages.visible {
+        let area = centered_rect(70, 60, f.area());
+        f.render_widget(Clear, area);
+
+        let close_keys = app.keymap.keys_for(Action::ToggleMessages).join("/");
+        let block = theme
+            .block(format!("Messages ({} to close)", close_keys))
+            .style(theme.popup);
+        let height = usize::from(block.inner(area).height);
+
+        let lines: Vec<Line> = app
+            .messages
+            .history()
+            .skip(app.messages.first_visible(height))
+            .take(height)
+            .map(|message| {
+                Line::from(vec![
+                    Span::raw(format!("{:>4} ", message.age())),
+                    Span::styled(
+                        format!("{} {}", message.level.icon(), message.text),
+                        theme.message(message.level),
+                    ),
+                ])
+            })
+            .collect();
+
+        let history = if lines.is_empty() {
+            Paragraph::new("No messages yet")
+        } else {
+            Paragraph::new(lines)
+        };
+        f.render_widget(history.block(block).style(theme.popup), area);
+    }
 }
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -837,10 +2977,40 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         .split(popup_layout[1])[1]
 }
 
-fn setup_signal_handlers() {
+fn setup_signal_handlers() -> Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
     // Set up Ctrl+C handler
     set_handler(move || {
         SHUTDOWN.store(true, Ordering::Relaxed);
     })
-    .expect("Error setting Ctrl+C handler");
+    .context("Failed to set the Ctrl+C handler")?;
+
+    // SIGTERM stops as gracefully as Ctrl+C, and SIGUSR1 skips to the next track. Both are
+    // registered before returning: until then SIGUSR1 would still kill the player.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .context("Failed to watch for signals")?;
+    let (mut terminate, mut skip) = {
+        let _guard = runtime.enter();
+        (
+            signal(SignalKind::terminate()).context("Failed to watch for SIGTERM")?,
+            signal(SignalKind::user_defined1()).context("Failed to watch for SIGUSR1")?,
+        )
+    };
+    std::thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            runtime.block_on(async {
+                loop {
+                    tokio::select! {
+                        _ = terminate.recv() => SHUTDOWN.store(true, Ordering::Relaxed),
+                        _ = skip.recv() => SKIP_REQUESTED.store(true, Ordering::Relaxed),
+                    }
+                }
+            })
+        })
+        .context("Failed to watch for signals")?;
+    Ok(())
 }
@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+
+/// Number of messages kept in the history
+const HISTORY_LIMIT: usize = 200;
+
+/// Severity of a status message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    /// How long a toast of this level stays in the status line
+    fn toast_duration(self) -> Duration {
+        match self {
+            Level::Info => Duration::from_secs(3),
+            Level::Warn => Duration::from_secs(5),
+            Level::Error => Duration::from_secs(8),
+        }
+    }
+
+    pub fn icon(self) -> &'static str {
+        match self {
+            Level::Info => "ℹ",
+            Level::Warn => "⚠",
+            Level::Error => "✖",
+        }
+    }
+}
+
+/// A status message shown as a toast and kept in the history
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub level: Level,
+    pub text: String,
+    pub time: Instant,
+}
+
+impl Message {
+    /// Age of the message, formatted like "5s", "12m" or "3h"
+    pub fn age(&self) -> String {
+        let secs = self.time.elapsed().as_secs();
+        match secs {
+            0..60 => format!("{}s", secs),
+            60..3600 => format!("{}m", secs / 60),
+            _ => format!("{}h", secs / 3600),
+        }
+    }
+}
+
+/// Toasts for the status line and the scrollable message history
+pub struct Messages {
+    history: VecDeque<Message>,
+    toast_until: Option<Instant>,
+    /// Whether the history popup is open
+    pub visible: bool,
+    /// Lines scrolled up from the newest message in the history popup
+    scroll: usize,
+}
+
+impl Messages {
+    pub fn new() -> Self {
+        Self {
+            history: VecDeque::new(),
+            toast_until: None,
+            visible: false,
+            scroll: 0,
+        }
+    }
+
+    pub fn info(&mut self, text: impl fmt::Display) {
+        self.push(Level::Info, text.to_string());
+    }
+
+    pub fn warn(&mut self, text: impl fmt::Display) {
+        self.push(Level::Warn, text.to_string());
+    }
+
+    /// Show an error, including the context chain of `anyhow` errors
+    pub fn error(&mut self, error: impl fmt::Display) {
+        self.push(Level::Error, format!("{:#}", error));
+    }
+
+    fn push(&mut self, level: Level, text: String) {
+        // Also log it, so messages end up wherever logs are routed
+        match level {
+            Level::Info => info!("{}", text),
+            Level::Warn => warn!("{}", text),
+            Level::Error => error!("{}", text),
+        }
+
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        let time = Instant::now();
+        self.history.push_back(Message { level, text, time });
+        self.toast_until = Some(time + level.toast_duration());
+    }
+
+    /// The latest message, while its toast hasn't expired
+    pub fn toast(&self) -> Option<&Message> {
+        self.toast_until
+            .filter(|until| Instant::now() < *until)
+            .and(self.history.back())
+    }
+
+    /// Open or close the history popup, starting at the newest message
+    pub fn toggle_history(&mut self) {
+        self.visible = !self.visible;
+        self.scroll = 0;
+        // Opening the history shows the message, so its toast isn't needed any more
+        self.toast_until = None;
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = (self.scroll + 1).min(self.history.len().saturating_sub(1));
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    /// Messages from oldest to newest
+    pub fn history(&self) -> impl Iterator<Item = &Message> {
+        self.history.iter()
+    }
+
+    /// Index of the first message to show so the scrolled-to message is at the bottom
+    pub fn first_visible(&self, height: usize) -> usize {
+        self.history
+            .len()
+            .saturating_sub(self.scroll)
+            .saturating_sub(height)
+    }
+}
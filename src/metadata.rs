@@ -0,0 +1,357 @@
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use id3::TagLike;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+/// Tags and stream information read from an audio file
+#[derive(Debug, Clone, Default)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    /// Stands in for `artist` when a file has no artist tag
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration: Option<Duration>,
+}
+
+impl TrackTags {
+    /// Collect the tags of several revisions, earlier revisions taking precedence
+    fn from_revisions<'a>(revisions: impl IntoIterator<Item = &'a MetadataRevision>) -> Self {
+        let mut tags = TrackTags::default();
+        for revision in revisions {
+            tags.merge_revision(revision);
+        }
+        if tags.artist.is_none() {
+            tags.artist = tags.album_artist.clone();
+        }
+        tags
+    }
+
+    /// Fill in any fields that are still missing from a metadata revision
+    fn merge_revision(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let value = tag.value.to_string();
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) if self.title.is_none() => {
+                    self.title = Some(value.to_string());
+                }
+                Some(StandardTagKey::Artist) if self.artist.is_none() => {
+                    self.artist = Some(value.to_string());
+                }
+                Some(StandardTagKey::AlbumArtist) if self.album_artist.is_none() => {
+                    self.album_artist = Some(value.to_string());
+                }
+                Some(StandardTagKey::Album) if self.album.is_none() => {
+                    self.album = Some(value.to_string());
+                }
+                Some(StandardTagKey::TrackNumber) if self.track_number.is_none() => {
+                    // Track numbers are often stored as "3/12"
+                    self.track_number = value.split('/').next().and_then(|n| n.trim().parse().ok());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Stream properties and extra tags shown in the track details panel
+#[derive(Debug, Clone, Default)]
+pub struct TrackDetails {
+    pub year: Option<String>,
+    pub codec: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u32>,
+    pub channels: Option<usize>,
+    /// Average bitrate over the whole file, in kbit/s
+    pub bitrate: Option<u64>,
+    pub file_size: Option<u64>,
+    pub track_gain: Option<String>,
+    pub track_peak: Option<String>,
+    pub album_gain: Option<String>,
+    pub album_peak: Option<String>,
+}
+
+impl TrackDetails {
+    fn merge_revision(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            let value = tag.value.to_string();
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+
+            let field = match tag.std_key {
+                Some(StandardTagKey::Date | StandardTagKey::ReleaseDate) => &mut self.year,
+                Some(StandardTagKey::ReplayGainTrackGain) => &mut self.track_gain,
+                Some(StandardTagKey::ReplayGainTrackPeak) => &mut self.track_peak,
+                Some(StandardTagKey::ReplayGainAlbumGain) => &mut self.album_gain,
+                Some(StandardTagKey::ReplayGainAlbumPeak) => &mut self.album_peak,
+                _ => continue,
+            };
+            if field.is_none() {
+                *field = Some(value.to_string());
+            }
+        }
+
+        // Dates are often full "2004-05-17" timestamps; only the year is shown
+        if let Some(year) = &mut self.year
+            && year.len() > 4
+            && year[..4].chars().all(|c| c.is_ascii_digit())
+        {
+            year.truncate(4);
+        }
+    }
+}
+
+/// Open an audio file and probe its format with symphonia
+fn probe(path: &Path) -> Result<ProbeResult> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open audio file: {}", path.display()))?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .with_context(|| format!("Failed to probe audio file: {}", path.display()))
+}
+
+/// Probe an audio file with symphonia and collect its tags
+pub fn read_tags<P: AsRef<Path>>(path: P) -> Result<TrackTags> {
+    let mut probed = probe(path.as_ref())?;
+
+    // Container metadata takes precedence over tags found while probing (e.g. ID3v2)
+    let container = probed.format.metadata();
+    let probe = probed.metadata.get();
+    let revisions = container
+        .current()
+        .into_iter()
+        .chain(probe.as_ref().and_then(|m| m.current()));
+    let mut tags = TrackTags::from_revisions(revisions);
+
+    // Derive the duration from the stream's frame count when the container provides it
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        if let (Some(frames), Some(time_base)) = (params.n_frames, params.time_base) {
+            let time = time_base.calc_time(frames);
+            tags.duration =
+                Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac));
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Read the embedded cover art (ID3 APIC, FLAC PICTURE or MP4 covr) as encoded image data
+///
+/// A picture marked as the front cover wins over any other embedded picture.
+pub fn read_cover<P: AsRef<Path>>(path: P) -> Result<Option<Vec<u8>>> {
+    let mut probed = probe(path.as_ref())?;
+
+    let mut visuals = Vec::new();
+    if let Some(revision) = probed.format.metadata().current() {
+        visuals.extend(revision.visuals().iter().cloned());
+    }
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        visuals.extend(revision.visuals().iter().cloned());
+    }
+
+    let cover = visuals
+        .iter()
+        .find(|v| v.usage == Some(StandardVisualKey::FrontCover))
+        .or_else(|| visuals.first());
+
+    Ok(cover.map(|v| v.data.to_vec()))
+}
+
+/// Read embedded lyrics text (ID3 USLT or a Vorbis `LYRICS` comment)
+pub fn read_lyrics<P: AsRef<Path>>(path: P) -> Result<Option<String>> {
+    let mut probed = probe(path.as_ref())?;
+
+    let mut revisions = Vec::new();
+    if let Some(revision) = probed.format.metadata().current() {
+        revisions.push(revision.clone());
+    }
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        revisions.push(revision.clone());
+    }
+
+    Ok(revisions
+        .iter()
+        .flat_map(|revision| revision.tags())
+        .filter(|tag| tag.std_key == Some(StandardTagKey::Lyrics))
+        .map(|tag| tag.value.to_string())
+        .find(|text| !text.trim().is_empty()))
+}
+
+/// Read stream properties, the release year and ReplayGain tags for the details panel
+///
+/// `duration` is used for the average bitrate when the stream doesn't report its length.
+pub fn read_details<P: AsRef<Path>>(path: P, duration: Option<Duration>) -> Result<TrackDetails> {
+    let path = path.as_ref();
+    let mut probed = probe(path)?;
+
+    let mut details = TrackDetails::default();
+    if let Some(revision) = probed.format.metadata().current() {
+        details.merge_revision(revision);
+    }
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+        details.merge_revision(revision);
+    }
+
+    let mut duration = duration;
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+        details.codec = symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|codec| codec.long_name.to_string());
+        details.sample_rate = params.sample_rate;
+        details.bit_depth = params.bits_per_sample.or(params.bits_per_coded_sample);
+        details.channels = params
+            .channels
+            .map(|channels| channels.count())
+            .or_else(|| {
+                params
+                    .channel_layout
+                    .map(|layout| layout.into_channels().count())
+            });
+
+        if let (Some(frames), Some(time_base)) = (params.n_frames, params.time_base) {
+            let time = time_base.calc_time(frames);
+            duration = Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac));
+        }
+    }
+
+    details.file_size = fs::metadata(path).ok().map(|m| m.len());
+    if let (Some(size), Some(duration)) = (details.file_size, duration)
+        && !duration.is_zero()
+    {
+        details.bitrate =
+            Some((size as f64 * 8.0 / duration.as_secs_f64() / 1000.0).round() as u64);
+    }
+
+    Ok(details)
+}
+
+/// A tag that can be edited from the track list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    Track,
+}
+
+impl TagField {
+    /// Names accepted by the `tag` command
+    pub const NAMES: &[&str] = &["title", "artist", "album", "track"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "title" => Some(TagField::Title),
+            "artist" => Some(TagField::Artist),
+            "album" => Some(TagField::Album),
+            "track" => Some(TagField::Track),
+            _ => None,
+        }
+    }
+}
+
+/// Set or, with an empty value, remove a tag
+///
+/// Only ID3 tags are written, so this works for MP3 files and fails for other formats.
+pub fn write_tag<P: AsRef<Path>>(path: P, field: TagField, value: &str) -> Result<()> {
+    let path = path.as_ref();
+    let is_mp3 = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("mp3"));
+    if !is_mp3 {
+        anyhow::bail!("Editing tags is only supported for MP3 files");
+    }
+
+    let mut tag = id3::no_tag_ok(id3::Tag::read_from_path(path))
+        .with_context(|| format!("Failed to read tags: {}", path.display()))?
+        .unwrap_or_else(id3::Tag::new);
+
+    let value = value.trim();
+    match (field, value.is_empty()) {
+        (TagField::Title, true) => tag.remove_title(),
+        (TagField::Title, false) => tag.set_title(value),
+        (TagField::Artist, true) => tag.remove_artist(),
+        (TagField::Artist, false) => tag.set_artist(value),
+        (TagField::Album, true) => tag.remove_album(),
+        (TagField::Album, false) => tag.set_album(value),
+        (TagField::Track, true) => tag.remove_track(),
+        (TagField::Track, false) => tag.set_track(
+            value
+                .parse()
+                .with_context(|| format!("Invalid track number '{}'", value))?,
+        ),
+    }
+
+    tag.write_to_path(path, id3::Version::Id3v24)
+        .with_context(|| format!("Failed to write tags: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::meta::{MetadataBuilder, Tag, Value};
+
+    fn revision(tags: &[(StandardTagKey, &str)]) -> MetadataRevision {
+        let mut builder = MetadataBuilder::new();
+        for (key, value) in tags {
+            builder.add_tag(Tag::new(Some(*key), "", Value::from(*value)));
+        }
+        builder.metadata()
+    }
+
+    #[test]
+    fn artist_wins_over_an_album_artist_listed_first() {
+        let tags = TrackTags::from_revisions([&revision(&[
+            (StandardTagKey::AlbumArtist, "Various Artists"),
+            (StandardTagKey::Artist, "Nina Simone"),
+        ])]);
+        assert_eq!(tags.artist.as_deref(), Some("Nina Simone"));
+        assert_eq!(tags.album_artist.as_deref(), Some("Various Artists"));
+
+        // Also when the artist only turns up in a later revision
+        let tags = TrackTags::from_revisions([
+            &revision(&[(StandardTagKey::AlbumArtist, "Various Artists")]),
+            &revision(&[(StandardTagKey::Artist, "Nina Simone")]),
+        ]);
+        assert_eq!(tags.artist.as_deref(), Some("Nina Simone"));
+    }
+
+    #[test]
+    fn album_artist_stands_in_for_a_missing_artist() {
+        let tags = TrackTags::from_revisions([&revision(&[
+            (StandardTagKey::TrackTitle, "Sinnerman"),
+            (StandardTagKey::AlbumArtist, "Nina Simone"),
+            (StandardTagKey::Artist, "  "),
+        ])]);
+        assert_eq!(tags.title.as_deref(), Some("Sinnerman"));
+        assert_eq!(tags.artist.as_deref(), Some("Nina Simone"));
+    }
+}
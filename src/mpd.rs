@@ -0,0 +1,2032 @@
+//! Subset of the MPD protocol over TCP, so existing MPD clients can drive the player
+//!
+//! Enough of the [MPD protocol] is spoken for clients such as `mpc` and `ncmpcpp`: playback
+//! control, status, the queue, browsing and searching the music directory, and `idle`. The
+//! MPD queue is the player's track list. Song ids are positions in it, so they change
+//! whenever the list does; clients notice through the `playlist` version and the `playlist`
+//! idle event. Tags of files outside the track list are read on demand and cached.
+//!
+//! Each connection runs on its own thread and answers from a snapshot of the player that the
+//! main loop refreshes on every pass. Commands that change the player are handed to the main
+//! loop, which refreshes the snapshot before replying, so a following `status` sees the
+//! change.
+//!
+//! [MPD protocol]: https://mpd.readthedocs.io/en/latest/protocol.html
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::command::{Amount, Command};
+use crate::config::Config;
+use crate::ipc::{Status, TrackInfo};
+use crate::mpris::percent_decode;
+use crate::{MusicPlayer, PlaybackState, RepeatMode, metadata};
+
+/// First line sent on every connection, naming the protocol version spoken
+const GREETING: &str = "OK MPD 0.23.0\n";
+
+/// How long a connection waits for the main loop to carry out a change
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Difference from the expected position, in seconds, taken to be a seek
+const SEEK_THRESHOLD: f64 = 1.0;
+
+/// Commands listed by `commands`
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "consume",
+    "currentsong",
+    "decoders",
+    "delete",
+    "deleteid",
+    "find",
+    "findadd",
+    "getvol",
+    "idle",
+    "list",
+    "listall",
+    "listallinfo",
+    "listplaylists",
+    "lsinfo",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "plchangesposid",
+    "previous",
+    "random",
+    "repeat",
+    "replay_gain_status",
+    "rescan",
+    "search",
+    "searchadd",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "update",
+    "urlhandlers",
+    "volume",
+];
+
+/// Tags sent in song blocks, listed by `tagtypes`
+const TAG_TYPES: &[&str] = &["Artist", "Album", "Title", "Track"];
+
+// Error codes from MPD's `ack.h`
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_PERMISSION: u32 = 4;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+const ACK_ERROR_SYSTEM: u32 = 52;
+
+/// A change to the player requested by a client, for the main loop to carry out
+#[derive(Debug)]
+pub enum Change {
+    /// Run a `:` command that doesn't work on selected tracks
+    Command(Command),
+    /// Pause when playing, otherwise play
+    Toggle,
+    /// Play the track at a position in the list as of a list version
+    Play { position: usize, version: u32 },
+    /// Add files, or the audio files in directories, to the track list
+    Add(Vec<PathBuf>),
+    /// Remove the tracks at these positions in the list as of a list version
+    Delete { positions: Vec<usize>, version: u32 },
+    /// Remove every track
+    Clear,
+}
+
+impl Change {
+    /// Check that the positions a change refers to still name the tracks the client saw
+    ///
+    /// Clients check positions against their snapshot, which a change queued ahead of this
+    /// one by another client, or the player itself, may have made stale.
+    pub fn check(&self, tracks: usize, version: u32) -> Result<()> {
+        let (positions, seen) = match self {
+            Change::Play { position, version } => (std::slice::from_ref(position), *version),
+            Change::Delete { positions, version } => (positions.as_slice(), *version),
+            _ => return Ok(()),
+        };
+        if seen != version || positions.iter().any(|&position| position >= tracks) {
+            anyhow::bail!("Bad song index: the queue has changed");
+        }
+        Ok(())
+    }
+}
+
+/// A change waiting for the main loop, with the connection waiting for its result
+pub struct Call {
+    pub change: Change,
+    reply: Sender<Result<()>>,
+}
+
+impl Call {
+    /// Tell the waiting connection how the change went
+    pub fn finish(self, result: Result<()>) {
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Listens for MPD clients and keeps the snapshot they answer from up to date
+pub struct Server {
+    shared: Arc<Shared>,
+    calls: Receiver<Call>,
+    /// When the snapshot was last refreshed, to tell seeks from playback moving on
+    updated: Instant,
+}
+
+impl Server {
+    /// Listen on the configured address
+    pub fn start(config: &Config, player: &MusicPlayer) -> Result<Self> {
+        let address = &config.mpd.address;
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("Failed to listen on {}", address))?;
+
+        let (call_sender, calls) = mpsc::channel();
+        let mut state = State {
+            status: player.status(),
+            queue: Vec::new(),
+            positions: HashMap::new(),
+            version: 0,
+            changes: [0; SUBSYSTEMS],
+        };
+        state.set_queue(player);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(state),
+            watchers: Mutex::new(Vec::new()),
+            calls: call_sender,
+            music_directory: config.music_directory(),
+            extensions: config.library.extensions.clone(),
+            follow_links: config.library.follow_links,
+            tags: Mutex::new(HashMap::new()),
+            started: Instant::now(),
+        });
+
+        let accepting = Arc::clone(&shared);
+        thread::spawn(move || accept(listener, accepting));
+        info!("Listening for MPD clients on {}", address);
+
+        Ok(Self {
+            shared,
+            calls,
+            updated: Instant::now(),
+        })
+    }
+
+    /// Take the next change requested by a client, if any
+    pub fn try_recv(&self) -> Option<Call> {
+        self.calls.try_recv().ok()
+    }
+
+    /// Refresh the snapshot from the player, waking idle clients for whatever changed
+    pub fn update(&mut self, player: &MusicPlayer) {
+        let status = player.status();
+        let mut changed = Vec::new();
+        {
+            let mut state = self.shared.state();
+            if state.version != player.list_version() {
+                state.set_queue(player);
+                changed.push(Subsystem::Playlist);
+            }
+
+            let old = &state.status;
+            let expected = if old.state == PlaybackState::Playing {
+                old.position + self.updated.elapsed().as_secs_f64()
+            } else {
+                old.position
+            };
+            let track = |status: &Status| status.track.as_ref().map(|t| t.path.clone());
+            if old.state != status.state
+                || old.index != status.index
+                || track(old) != track(&status)
+                || (status.position - expected).abs() > SEEK_THRESHOLD
+            {
+                changed.push(Subsystem::Player);
+            }
+            if old.volume != status.volume {
+                changed.push(Subsystem::Mixer);
+            }
+            if old.shuffle != status.shuffle || old.repeat != status.repeat {
+                changed.push(Subsystem::Options);
+            }
+            state.status = status;
+        }
+        self.updated = Instant::now();
+        self.shared.changed(&changed);
+    }
+}
+
+/// Parts of the player that `idle` reports changes to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Subsystem {
+    Database,
+    Update,
+    StoredPlaylist,
+    Playlist,
+    Player,
+    Mixer,
+    Options,
+    Output,
+}
+
+const SUBSYSTEMS: usize = Subsystem::ALL.len();
+
+impl Subsystem {
+    const ALL: [Subsystem; 8] = [
+        Subsystem::Database,
+        Subsystem::Update,
+        Subsystem::StoredPlaylist,
+        Subsystem::Playlist,
+        Subsystem::Player,
+        Subsystem::Mixer,
+        Subsystem::Options,
+        Subsystem::Output,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Subsystem::Database => "database",
+            Subsystem::Update => "update",
+            Subsystem::StoredPlaylist => "stored_playlist",
+            Subsystem::Playlist => "playlist",
+            Subsystem::Player => "player",
+            Subsystem::Mixer => "mixer",
+            Subsystem::Options => "options",
+            Subsystem::Output => "output",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+}
+
+/// Snapshot of the player kept up to date by the main loop
+struct State {
+    status: Status,
+    /// The track list, which is the MPD queue
+    queue: Vec<TrackInfo>,
+    /// Position of each file in the queue
+    positions: HashMap<PathBuf, usize>,
+    /// Track list version, reported as the `playlist` number
+    version: u32,
+    /// How many times each subsystem has changed
+    changes: [u64; SUBSYSTEMS],
+}
+
+impl State {
+    fn set_queue(&mut self, player: &MusicPlayer) {
+        self.queue = player.tracks.iter().map(TrackInfo::from).collect();
+        self.positions = self
+            .queue
+            .iter()
+            .enumerate()
+            .map(|(i, track)| (track.path.clone(), i))
+            .collect();
+        self.version = player.list_version();
+    }
+}
+
+/// What the server and its connection threads have in common
+struct Shared {
+    state: Mutex<State>,
+    /// Connections to wake when something changes
+    watchers: Mutex<Vec<Sender<Input>>>,
+    calls: Sender<Call>,
+    /// Root of the database, or `None` when there is no directory to browse
+    music_directory: Option<PathBuf>,
+    extensions: Vec<String>,
+    follow_links: bool,
+    /// Tags of files outside the track list, by path
+    tags: Mutex<HashMap<PathBuf, TrackInfo>>,
+    started: Instant,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Count a change to each subsystem and wake every connection
+    fn changed(&self, subsystems: &[Subsystem]) {
+        if subsystems.is_empty() {
+            return;
+        }
+        {
+            let mut state = self.state();
+            for subsystem in subsystems {
+                state.changes[*subsystem as usize] += 1;
+            }
+        }
+        if let Ok(mut watchers) = self.watchers.lock() {
+            watchers.retain(|watcher| watcher.send(Input::Changed).is_ok());
+        }
+    }
+
+    fn is_supported(&self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.extensions.contains(&ext.to_lowercase()))
+    }
+}
+
+/// A failed command, reported to the client as an `ACK` line
+#[derive(Debug)]
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    fn arg(message: impl Into<String>) -> Self {
+        Self {
+            code: ACK_ERROR_ARG,
+            message: message.into(),
+        }
+    }
+
+    fn permission(message: impl Into<String>) -> Self {
+        Self {
+            code: ACK_ERROR_PERMISSION,
+            message: message.into(),
+        }
+    }
+
+    fn no_exist(message: impl Into<String>) -> Self {
+        Self {
+            code: ACK_ERROR_NO_EXIST,
+            message: message.into(),
+        }
+    }
+
+    fn system(message: impl Into<String>) -> Self {
+        Self {
+            code: ACK_ERROR_SYSTEM,
+            message: message.into(),
+        }
+    }
+
+    /// Write the `ACK` line for the command at `index` in a command list
+    fn write(&self, index: usize, line: &str, out: &mut String) {
+        let command = line.split_whitespace().next().unwrap_or("");
+        let _ = writeln!(
+            out,
+            "ACK [{}@{}] {{{}}} {}",
+            self.code, index, command, self.message
+        );
+    }
+}
+
+impl From<anyhow::Error> for Ack {
+    fn from(error: anyhow::Error) -> Self {
+        Ack::system(format!("{:#}", error))
+    }
+}
+
+/// What a connection thread waits for
+enum Input {
+    Line(String),
+    /// Something changed, which may end an `idle`
+    Changed,
+    /// The client closed the connection
+    Closed,
+}
+
+/// How a command leaves the connection
+enum Flow {
+    Done,
+    /// Wait for changes to these subsystems before answering
+    Idle(Vec<Subsystem>),
+    Close,
+}
+
+/// Accept connections until the process exits
+fn accept(listener: TcpListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, shared) {
+                        debug!("MPD connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Failed to accept MPD connection: {}", e),
+        }
+    }
+}
+
+/// Answer one client until it disconnects
+fn serve(stream: TcpStream, shared: Arc<Shared>) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    info!("MPD client connected from {}", peer);
+
+    // Lines and change notifications arrive on one channel, so `idle` can wait for either
+    let (inputs_sender, inputs) = mpsc::channel();
+    let lines = inputs_sender.clone();
+    let reader = BufReader::new(stream.try_clone()?);
+    thread::spawn(move || {
+        for line in reader.lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if lines.send(Input::Line(line)).is_err() {
+                return;
+            }
+        }
+        let _ = lines.send(Input::Closed);
+    });
+    if let Ok(mut watchers) = shared.watchers.lock() {
+        watchers.push(inputs_sender);
+    }
+
+    let mut writer = stream;
+    writer.write_all(GREETING.as_bytes())?;
+    let seen = shared.state().changes;
+    let mut session = Session {
+        shared,
+        local: peer.ip().is_loopback(),
+        seen,
+        idle: None,
+        list: None,
+    };
+    let result = session.run(inputs, &mut writer);
+    // Wakes the reader thread if the server ended the connection
+    let _ = writer.shutdown(Shutdown::Both);
+    info!("MPD client {} disconnected", peer);
+    result
+}
+
+/// State of one connection
+struct Session {
+    shared: Arc<Shared>,
+    /// Whether the client is on this machine, the only place `file://` URLs may come from
+    local: bool,
+    /// Change counters as of the last `idle` answer
+    seen: [u64; SUBSYSTEMS],
+    /// Subsystems the client is waiting on, while it is idle
+    idle: Option<Vec<Subsystem>>,
+    /// Commands collected for a command list, and whether each gets a `list_OK`
+    list: Option<(Vec<String>, bool)>,
+}
+
+impl Session {
+    fn run(&mut self, inputs: Receiver<Input>, writer: &mut impl Write) -> Result<()> {
+        for input in inputs {
+            let mut out = String::new();
+            let open = match input {
+                Input::Line(line) => self.handle_line(&line, &mut out),
+                Input::Changed => {
+                    self.report_idle(false, &mut out);
+                    true
+                }
+                Input::Closed => false,
+            };
+            writer.write_all(out.as_bytes())?;
+            if !open {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle one line from the client, returning false when the connection should close
+    fn handle_line(&mut self, line: &str, out: &mut String) -> bool {
+        debug!("MPD command: {}", line);
+
+        // Only `noidle` may be sent while idle; MPD drops clients that send anything else
+        if self.idle.is_some() {
+            if line.trim() != "noidle" {
+                return false;
+            }
+            self.report_idle(true, out);
+            return true;
+        }
+
+        if let Some((commands, _)) = &mut self.list {
+            if line.trim() != "command_list_end" {
+                commands.push(line.to_string());
+                return true;
+            }
+            let Some((commands, list_ok)) = self.list.take() else {
+                return true;
+            };
+            for (index, command) in commands.iter().enumerate() {
+                match self.execute(command, out) {
+                    Ok(Flow::Done) if list_ok => out.push_str("list_OK\n"),
+                    Ok(Flow::Done) => {}
+                    Ok(Flow::Idle(_)) => {
+                        Ack::arg("idle is not allowed in command lists").write(index, command, out);
+                        return true;
+                    }
+                    Ok(Flow::Close) => return false,
+                    Err(ack) => {
+                        ack.write(index, command, out);
+                        return true;
+                    }
+                }
+            }
+            out.push_str("OK\n");
+            return true;
+        }
+
+        match line.trim() {
+            "command_list_begin" => self.list = Some((Vec::new(), false)),
+            "command_list_ok_begin" => self.list = Some((Vec::new(), true)),
+            _ => match self.execute(line, out) {
+                Ok(Flow::Done) => out.push_str("OK\n"),
+                Ok(Flow::Idle(subsystems)) => {
+                    self.idle = Some(subsystems);
+                    self.report_idle(false, out);
+                }
+                Ok(Flow::Close) => return false,
+                Err(ack) => ack.write(0, line, out),
+            },
+        }
+        true
+    }
+
+    /// Answer a waiting `idle` with the subsystems that changed since the last answer,
+    /// or with nothing changed when `noidle` cancels it
+    fn report_idle(&mut self, cancel: bool, out: &mut String) {
+        let Some(subsystems) = &self.idle else {
+            return;
+        };
+        let changes = self.shared.state().changes;
+        let changed: Vec<Subsystem> = subsystems
+            .iter()
+            .copied()
+            .filter(|s| changes[*s as usize] != self.seen[*s as usize])
+            .collect();
+        if changed.is_empty() && !cancel {
+            return;
+        }
+        for subsystem in changed {
+            let _ = writeln!(out, "changed: {}", subsystem.name());
+            self.seen[subsystem as usize] = changes[subsystem as usize];
+        }
+        out.push_str("OK\n");
+        self.idle = None;
+    }
+
+    /// Run one command, writing its output before the final `OK`
+    fn execute(&mut self, line: &str, out: &mut String) -> Result<Flow, Ack> {
+        let words = tokenize(line)?;
+        let Some((name, args)) = words.split_first() else {
+            return Err(Ack {
+                code: ACK_ERROR_UNKNOWN,
+                message: "No command given".to_string(),
+            });
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        match name.as_str() {
+            "ping" | "noidle" => {}
+            "close" => return Ok(Flow::Close),
+            "idle" => {
+                let subsystems = if args.is_empty() {
+                    Subsystem::ALL.to_vec()
+                } else {
+                    args.iter()
+                        .map(|name| {
+                            Subsystem::from_name(name).ok_or_else(|| {
+                                Ack::arg(format!("Unrecognized idle event: {}", name))
+                            })
+                        })
+                        .collect::<Result<_, _>>()?
+                };
+                return Ok(Flow::Idle(subsystems));
+            }
+
+            "status" => self.write_status(out),
+            "currentsong" => {
+                let state = self.shared.state();
+                if let Some(track) = &state.status.track {
+                    self.write_song(out, track, Some(state.status.index));
+                }
+            }
+            "stats" => self.write_stats(out),
+
+            "play" | "playid" => match args.first() {
+                Some(position) => {
+                    let (position, version) = self.position(parse_number(position)?)?;
+                    self.call(Change::Play { position, version })?;
+                }
+                None => self.call(Change::Command(Command::Play))?,
+            },
+            "pause" => match args.first() {
+                Some(pause) if parse_bool(pause)? => {
+                    // MPD ignores a pause while stopped rather than failing
+                    if self.shared.state().status.state != PlaybackState::Stopped {
+                        self.call(Change::Command(Command::Pause))?;
+                    }
+                }
+                Some(_) => self.call(Change::Command(Command::Play))?,
+                None => self.call(Change::Toggle)?,
+            },
+            "stop" => self.call(Change::Command(Command::Stop))?,
+            "next" => self.call(Change::Command(Command::Next))?,
+            "previous" => self.call(Change::Command(Command::Previous))?,
+            "seek" | "seekid" => {
+                let (position, version) = self.position(parse_number(arg(&args, 0)?)?)?;
+                let time = parse_time(arg(&args, 1)?)?;
+                let status = self.shared.state().status.clone();
+                if status.index != position || status.state == PlaybackState::Stopped {
+                    self.call(Change::Play { position, version })?;
+                }
+                self.call(Change::Command(Command::Seek(Amount::To(time))))?;
+            }
+            "seekcur" => {
+                let time = arg(&args, 0)?;
+                let amount = if let Some(step) = time.strip_prefix('+') {
+                    Amount::Up(parse_time(step)?)
+                } else if let Some(step) = time.strip_prefix('-') {
+                    Amount::Down(parse_time(step)?)
+                } else {
+                    Amount::To(parse_time(time)?)
+                };
+                self.call(Change::Command(Command::Seek(amount)))?;
+            }
+
+            "setvol" => {
+                let volume: u8 = parse_number(arg(&args, 0)?)?;
+                if volume > 100 {
+                    return Err(Ack::arg("Invalid volume value"));
+                }
+                self.call(Change::Command(Command::Volume(Amount::To(volume))))?;
+            }
+            "volume" => {
+                let change: i16 = parse_number(arg(&args, 0)?)?;
+                let step = change.unsigned_abs().min(100) as u8;
+                let amount = if change >= 0 {
+                    Amount::Up(step)
+                } else {
+                    Amount::Down(step)
+                };
+                self.call(Change::Command(Command::Volume(amount)))?;
+            }
+            "getvol" => {
+                let volume = self.shared.state().status.volume;
+                let _ = writeln!(out, "volume: {}", volume);
+            }
+
+            // MPD's repeat and single flags map onto the three repeat modes
+            "repeat" => {
+                let mode = match (parse_bool(arg(&args, 0)?)?, self.repeat_mode()) {
+                    (false, _) => RepeatMode::None,
+                    (true, RepeatMode::One) => RepeatMode::One,
+                    (true, _) => RepeatMode::All,
+                };
+                self.call(Change::Command(Command::Repeat(mode)))?;
+            }
+            "single" => {
+                let mode = match (parse_bool(arg(&args, 0)?)?, self.repeat_mode()) {
+                    (true, _) => RepeatMode::One,
+                    (false, RepeatMode::One) => RepeatMode::All,
+                    (false, mode) => mode,
+                };
+                self.call(Change::Command(Command::Repeat(mode)))?;
+            }
+            "random" => {
+                let shuffle = parse_bool(arg(&args, 0)?)?;
+                self.call(Change::Command(Command::Shuffle(Some(shuffle))))?;
+            }
+            "consume" => {
+                if parse_bool(arg(&args, 0)?)? {
+                    return Err(Ack::arg("Consume mode is not supported"));
+                }
+            }
+
+            "playlistinfo" | "playlistid" => {
+                let state = self.shared.state();
+                let range = match args.first() {
+                    Some(range) => parse_range(range, state.queue.len())?,
+                    None => 0..state.queue.len(),
+                };
+                for position in range {
+                    self.write_song(out, &state.queue[position], Some(position));
+                }
+            }
+            "plchanges" | "plchangesposid" => {
+                let version: u32 = parse_number(arg(&args, 0)?)?;
+                let state = self.shared.state();
+                // Any change to the list is reported as a change to all of it
+                if version != state.version {
+                    let range = match args.get(1) {
+                        Some(range) => parse_range(range, state.queue.len())?,
+                        None => 0..state.queue.len(),
+                    };
+                    for position in range {
+                        if name == "plchanges" {
+                            self.write_song(out, &state.queue[position], Some(position));
+                        } else {
+                            let _ = writeln!(out, "cpos: {}\nId: {}", position, position);
+                        }
+                    }
+                }
+            }
+            "add" => {
+                let path = self.song_path(arg(&args, 0)?)?;
+                self.call(Change::Add(vec![path]))?;
+            }
+            "addid" => {
+                let path = self.song_path(arg(&args, 0)?)?;
+                if !path.is_file() {
+                    return Err(Ack::arg("addid only adds single files"));
+                }
+                self.call(Change::Add(vec![path.clone()]))?;
+                let id = self.shared.state().positions.get(&path).copied();
+                let id = id.ok_or_else(|| Ack::arg("Unsupported file format"))?;
+                let _ = writeln!(out, "Id: {}", id);
+            }
+            "clear" => self.call(Change::Clear)?,
+            "delete" => {
+                let (len, version) = {
+                    let state = self.shared.state();
+                    (state.queue.len(), state.version)
+                };
+                let positions = parse_range(arg(&args, 0)?, len)?.collect();
+                self.call(Change::Delete { positions, version })?;
+            }
+            "deleteid" => {
+                let (position, version) = self.position(parse_number(arg(&args, 0)?)?)?;
+                self.call(Change::Delete {
+                    positions: vec![position],
+                    version,
+                })?;
+            }
+
+            "lsinfo" => self.list_directory(args.first().copied().unwrap_or(""), out)?,
+            "listall" | "listallinfo" => {
+                let dir = self.database_path(args.first().copied().unwrap_or(""))?;
+                self.list_all(&dir, name == "listallinfo", out);
+            }
+            "find" | "search" | "findadd" | "searchadd" => {
+                // `search` compares without case; `find` wants exact values
+                let search = name.starts_with("search");
+                let (filter, window) = parse_filter(&args, search)?;
+                let mut songs = self.find(&filter, search)?;
+                if let Some(window) = window {
+                    let end = window.end.min(songs.len());
+                    songs = songs.drain(window.start.min(end)..end).collect();
+                }
+                if name.ends_with("add") {
+                    self.call(Change::Add(songs.into_iter().map(|s| s.path).collect()))?;
+                } else {
+                    for song in &songs {
+                        self.write_song(out, song, None);
+                    }
+                }
+            }
+            "list" => {
+                let tag = arg(&args, 0)?;
+                let tag = Tag::from_name(tag)
+                    .filter(|tag| *tag != Tag::Any)
+                    .ok_or_else(|| Ack::arg(format!("Unknown tag type: {}", tag)))?;
+                let filter = match &args[1..] {
+                    // `list album ARTIST` from before protocol version 0.21
+                    [artist] if tag == Tag::Album && !artist.starts_with('(') => Filter::Match {
+                        tag: Tag::Artist,
+                        op: Op::Equals,
+                        value: artist.to_string(),
+                    },
+                    rest => parse_filter(rest, false)?.0,
+                };
+                let mut values = BTreeSet::new();
+                for song in self.find(&filter, false)? {
+                    values.extend(tag.values(&song, &self.uri(&song.path)));
+                }
+                for value in values {
+                    let _ = writeln!(out, "{}: {}", tag.label(), value);
+                }
+            }
+            "update" | "rescan" => {
+                // Files are read from disk as they are browsed, so only cached tags go stale
+                if let Ok(mut tags) = self.shared.tags.lock() {
+                    tags.clear();
+                }
+                let _ = writeln!(out, "updating_db: 1");
+                self.shared
+                    .changed(&[Subsystem::Update, Subsystem::Database]);
+            }
+
+            "commands" => {
+                for command in COMMANDS {
+                    let _ = writeln!(out, "command: {}", command);
+                }
+            }
+            "notcommands" | "listplaylists" => {}
+            "tagtypes" => {
+                // `tagtypes clear`, `enable` and the like are accepted, but every tag is sent
+                if args.is_empty() {
+                    for tag in TAG_TYPES {
+                        let _ = writeln!(out, "tagtype: {}", tag);
+                    }
+                }
+            }
+            "urlhandlers" => out.push_str("handler: file://\n"),
+            "decoders" => {
+                out.push_str("plugin: symphonia\n");
+                for extension in &self.shared.extensions {
+                    let _ = writeln!(out, "suffix: {}", extension);
+                }
+            }
+            "outputs" => {
+                out.push_str("outputid: 0\noutputname: Default\nplugin: rodio\noutputenabled: 1\n");
+            }
+            "replay_gain_status" => out.push_str("replay_gain_mode: off\n"),
+            _ => {
+                return Err(Ack {
+                    code: ACK_ERROR_UNKNOWN,
+                    message: format!("unknown command \"{}\"", name),
+                });
+            }
+        }
+        Ok(Flow::Done)
+    }
+
+    /// Hand a change to the main loop and wait until it has been carried out
+    fn call(&self, change: Change) -> Result<(), Ack> {
+        let (reply, result) = mpsc::channel();
+        self.shared
+            .calls
+            .send(Call { change, reply })
+            .map_err(|_| Ack::system("The player has stopped"))?;
+        match result.recv_timeout(CALL_TIMEOUT) {
+            Ok(result) => result.map_err(Ack::from),
+            Err(_) => Err(Ack::system("The player did not respond")),
+        }
+    }
+
+    fn repeat_mode(&self) -> RepeatMode {
+        match self.shared.state().status.repeat.as_str() {
+            "one" => RepeatMode::One,
+            "all" => RepeatMode::All,
+            _ => RepeatMode::None,
+        }
+    }
+
+    /// Check that a song position or id is in the queue, returning it with the queue version
+    fn position(&self, position: usize) -> Result<(usize, u32), Ack> {
+        let state = self.shared.state();
+        if position < state.queue.len() {
+            Ok((position, state.version))
+        } else {
+            Err(Ack::arg("Bad song index"))
+        }
+    }
+
+    fn write_status(&self, out: &mut String) {
+        let state = self.shared.state();
+        let status = &state.status;
+        let flag = |on: bool| if on { 1 } else { 0 };
+        let _ = writeln!(out, "volume: {}", status.volume);
+        let _ = writeln!(out, "repeat: {}", flag(status.repeat != "off"));
+        let _ = writeln!(out, "random: {}", flag(status.shuffle));
+        let _ = writeln!(out, "single: {}", flag(status.repeat == "one"));
+        let _ = writeln!(out, "consume: 0");
+        let _ = writeln!(out, "playlist: {}", state.version);
+        let _ = writeln!(out, "playlistlength: {}", state.queue.len());
+        let _ = writeln!(
+            out,
+            "state: {}",
+            match status.state {
+                PlaybackState::Playing => "play",
+                PlaybackState::Paused => "pause",
+                PlaybackState::Stopped => "stop",
+            }
+        );
+        let Some(track) = &status.track else {
+            return;
+        };
+        let _ = writeln!(out, "song: {}\nsongid: {}", status.index, status.index);
+        if status.state != PlaybackState::Stopped {
+            let duration = track.duration.unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "time: {}:{}",
+                status.position.round(),
+                duration.round()
+            );
+            let _ = writeln!(out, "elapsed: {:.3}", status.position);
+            if let Some(duration) = track.duration {
+                let _ = writeln!(out, "duration: {:.3}", duration);
+            }
+        }
+    }
+
+    fn write_stats(&self, out: &mut String) {
+        let state = self.shared.state();
+        let artists: HashSet<&String> = state
+            .queue
+            .iter()
+            .filter_map(|t| t.artist.as_ref())
+            .collect();
+        let albums: HashSet<&String> = state
+            .queue
+            .iter()
+            .filter_map(|t| t.album.as_ref())
+            .collect();
+        let playtime: f64 = state.queue.iter().filter_map(|t| t.duration).sum();
+        let _ = writeln!(out, "artists: {}", artists.len());
+        let _ = writeln!(out, "albums: {}", albums.len());
+        let _ = writeln!(out, "songs: {}", state.queue.len());
+        let _ = writeln!(out, "uptime: {}", self.shared.started.elapsed().as_secs());
+        let _ = writeln!(out, "db_playtime: {}", playtime.round());
+    }
+
+    /// Write a song block, with its queue position and id when it is in the queue
+    fn write_song(&self, out: &mut String, song: &TrackInfo, position: Option<usize>) {
+        let _ = writeln!(out, "file: {}", self.uri(&song.path));
+        let _ = writeln!(out, "Title: {}", song.title);
+        if let Some(artist) = &song.artist {
+            let _ = writeln!(out, "Artist: {}", artist);
+        }
+        if let Some(album) = &song.album {
+            let _ = writeln!(out, "Album: {}", album);
+        }
+        if let Some(track) = song.track_number {
+            let _ = writeln!(out, "Track: {}", track);
+        }
+        if let Some(duration) = song.duration {
+            let _ = writeln!(out, "Time: {}", duration.round());
+            let _ = writeln!(out, "duration: {:.3}", duration);
+        }
+        if let Some(position) = position {
+            let _ = writeln!(out, "Pos: {}\nId: {}", position, position);
+        }
+    }
+
+    /// Write the entries of a directory, or the song block of a file
+    fn list_directory(&self, uri: &str, out: &mut String) -> Result<(), Ack> {
+        let path = self.database_path(uri)?;
+        if path.is_file() {
+            self.write_song(out, &self.song(&path), None);
+            return Ok(());
+        }
+
+        let mut entries: Vec<PathBuf> = fs::read_dir(&path)
+            .map_err(|e| Ack::system(format!("Failed to read {}: {}", uri, e)))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| !is_hidden(&entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .collect();
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() {
+                let _ = writeln!(out, "directory: {}", self.uri(&entry));
+            } else if self.shared.is_supported(&entry) {
+                self.write_song(out, &self.song(&entry), None);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write every directory and audio file under a directory, with tags when `info` is set
+    fn list_all(&self, dir: &Path, info: bool, out: &mut String) {
+        for entry in self.walk(dir) {
+            if entry.file_type().is_dir() {
+                let _ = writeln!(out, "directory: {}", self.uri(entry.path()));
+            } else if self.shared.is_supported(entry.path()) {
+                if info {
+                    self.write_song(out, &self.song(entry.path()), None);
+                } else {
+                    let _ = writeln!(out, "file: {}", self.uri(entry.path()));
+                }
+            }
+        }
+    }
+
+    /// Songs in the music directory matching a filter, in path order
+    fn find(&self, filter: &Filter, ignore_case: bool) -> Result<Vec<TrackInfo>, Ack> {
+        let dir = self.music_directory()?;
+        Ok(self
+            .walk(dir)
+            .filter(|entry| entry.file_type().is_file() && self.shared.is_supported(entry.path()))
+            .map(|entry| self.song(entry.path()))
+            .filter(|song| filter.matches(song, &self.uri(&song.path), ignore_case))
+            .collect())
+    }
+
+    /// Entries below a directory, skipping hidden ones
+    fn walk(&self, dir: &Path) -> impl Iterator<Item = DirEntry> {
+        WalkDir::new(dir)
+            .min_depth(1)
+            .follow_links(self.shared.follow_links)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !is_hidden(&entry.file_name().to_string_lossy()))
+            .filter_map(|entry| entry.ok())
+    }
+
+    /// Tags of a file, from the track list when it is loaded, otherwise read and cached
+    fn song(&self, path: &Path) -> TrackInfo {
+        {
+            let state = self.shared.state();
+            if let Some(position) = state.positions.get(path) {
+                return state.queue[*position].clone();
+            }
+        }
+        if let Some(song) = self
+            .shared
+            .tags
+            .lock()
+            .ok()
+            .and_then(|tags| tags.get(path).cloned())
+        {
+            return song;
+        }
+
+        let tags = metadata::read_tags(path).unwrap_or_else(|e| {
+            debug!("Failed to read tags from '{}': {}", path.display(), e);
+            Default::default()
+        });
+        let song = TrackInfo {
+            path: path.to_path_buf(),
+            title: tags.title.unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            }),
+            artist: tags.artist,
+            album: tags.album,
+            track_number: tags.track_number,
+            duration: tags.duration.map(|d| d.as_secs_f64()),
+        };
+        if let Ok(mut cache) = self.shared.tags.lock() {
+            cache.insert(path.to_path_buf(), song.clone());
+        }
+        song
+    }
+
+    fn music_directory(&self) -> Result<&Path, Ack> {
+        self.shared
+            .music_directory
+            .as_deref()
+            .ok_or_else(|| Ack::no_exist("No music directory is configured"))
+    }
+
+    /// URI of a file: relative to the music directory when inside it, otherwise absolute
+    fn uri(&self, path: &Path) -> String {
+        self.shared
+            .music_directory
+            .as_deref()
+            .and_then(|dir| path.strip_prefix(dir).ok())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Path of a URI in the music directory, refusing to leave it
+    fn database_path(&self, uri: &str) -> Result<PathBuf, Ack> {
+        let relative = Path::new(uri.trim_matches('/'));
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(Ack::arg(format!("Malformed URI: {}", uri)));
+        }
+        let path = self.music_directory()?.join(relative);
+        if !path.exists() {
+            return Err(Ack::no_exist(format!("No such file or directory: {}", uri)));
+        }
+        Ok(path)
+    }
+
+    /// Path of a URI to add: a file or directory in the music directory, or, for local
+    /// clients only, a `file://` URL anywhere
+    fn song_path(&self, uri: &str) -> Result<PathBuf, Ack> {
+        let Some(url) = uri.strip_prefix("file://") else {
+            return self.database_path(uri);
+        };
+        // As in MPD, so other machines can't load arbitrary files
+        if !self.local {
+            return Err(Ack::permission("Access denied"));
+        }
+        let path = percent_decode(url)
+            .map(PathBuf::from)
+            .ok_or_else(|| Ack::arg(format!("Malformed URI: {}", uri)))?;
+        if !path.is_absolute() || !path.exists() {
+            return Err(Ack::no_exist(format!("No such file or directory: {}", uri)));
+        }
+        Ok(path)
+    }
+}
+
+/// Tags that filters match on and `list` lists
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    /// Any of the others
+    Any,
+    File,
+    Title,
+    Artist,
+    Album,
+    Track,
+}
+
+impl Tag {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "any" => Some(Tag::Any),
+            "file" => Some(Tag::File),
+            "title" => Some(Tag::Title),
+            "artist" | "albumartist" => Some(Tag::Artist),
+            "album" => Some(Tag::Album),
+            "track" => Some(Tag::Track),
+            _ => None,
+        }
+    }
+
+    /// Name used for the tag in responses
+    fn label(self) -> &'static str {
+        match self {
+            Tag::Any => "any",
+            Tag::File => "file",
+            Tag::Title => "Title",
+            Tag::Artist => "Artist",
+            Tag::Album => "Album",
+            Tag::Track => "Track",
+        }
+    }
+
+    /// The song's values for this tag
+    fn values(self, song: &TrackInfo, uri: &str) -> Vec<String> {
+        match self {
+            Tag::Any => [Some(uri.to_string()), Some(song.title.clone())]
+                .into_iter()
+                .chain([song.artist.clone(), song.album.clone()])
+                .flatten()
+                .collect(),
+            Tag::File => vec![uri.to_string()],
+            Tag::Title => vec![song.title.clone()],
+            Tag::Artist => song.artist.iter().cloned().collect(),
+            Tag::Album => song.album.iter().cloned().collect(),
+            Tag::Track => song.track_number.iter().map(u32::to_string).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Equals,
+    NotEquals,
+    Contains,
+}
+
+/// Which songs `find`, `search` and `list` cover
+#[derive(Debug)]
+enum Filter {
+    Match {
+        tag: Tag,
+        op: Op,
+        value: String,
+    },
+    Not(Box<Filter>),
+    /// Every song when empty
+    And(Vec<Filter>),
+}
+
+impl Filter {
+    fn matches(&self, song: &TrackInfo, uri: &str, ignore_case: bool) -> bool {
+        match self {
+            Filter::Match { tag, op, value } => {
+                let fold = |text: &str| {
+                    if ignore_case {
+                        text.to_lowercase()
+                    } else {
+                        text.to_string()
+                    }
+                };
+                let value = fold(value);
+                let mut values = tag.values(song, uri).into_iter().map(|v| fold(&v));
+                match op {
+                    Op::Equals => values.any(|v| v == value),
+                    Op::NotEquals => !values.any(|v| v == value),
+                    Op::Contains => values.any(|v| v.contains(&value)),
+                }
+            }
+            Filter::Not(filter) => !filter.matches(song, uri, ignore_case),
+            Filter::And(filters) => filters.iter().all(|f| f.matches(song, uri, ignore_case)),
+        }
+    }
+}
+
+/// Parse the arguments of `find` and `search`: filter expressions or old-style tag and value
+/// pairs, with an optional `window START:END`; `sort` and `group` are ignored
+fn parse_filter(args: &[&str], substring: bool) -> Result<(Filter, Option<Range<usize>>), Ack> {
+    let mut filters = Vec::new();
+    let mut window = None;
+    let mut rest = args;
+    while let Some((first, tail)) = rest.split_first() {
+        if first.starts_with('(') {
+            filters.push(Expression::parse(first)?);
+            rest = tail;
+            continue;
+        }
+        let Some((value, tail)) = tail.split_first() else {
+            return Err(Ack::arg("Incorrect number of filter arguments"));
+        };
+        match first.to_lowercase().as_str() {
+            "sort" | "group" => {}
+            "window" => {
+                let (start, end) = parse_span(value)?;
+                window = Some(start..end.unwrap_or(usize::MAX));
+            }
+            name => {
+                let tag = Tag::from_name(name)
+                    .ok_or_else(|| Ack::arg(format!("Unknown filter type: {}", name)))?;
+                filters.push(Filter::Match {
+                    tag,
+                    op: if substring { Op::Contains } else { Op::Equals },
+                    value: value.to_string(),
+                });
+            }
+        }
+        rest = tail;
+    }
+    Ok((Filter::And(filters), window))
+}
+
+/// Parser for filter expressions such as `((artist == 'Low') AND (album contains "Long"))`
+struct Expression<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Expression<'a> {
+    fn parse(text: &'a str) -> Result<Filter, Ack> {
+        let mut expression = Self { rest: text };
+        let filter = expression.filter()?;
+        if !expression.rest.trim().is_empty() {
+            return Err(Ack::arg(format!(
+                "Unparsed garbage after expression: {}",
+                expression.rest
+            )));
+        }
+        Ok(filter)
+    }
+
+    fn filter(&mut self) -> Result<Filter, Ack> {
+        self.expect('(')?;
+        let filter = if self.eat('!') {
+            Filter::Not(Box::new(self.filter()?))
+        } else if self.peek() == Some('(') {
+            let mut filters = vec![self.filter()?];
+            while self.eat_word("AND") {
+                filters.push(self.filter()?);
+            }
+            Filter::And(filters)
+        } else {
+            let tag = self.word();
+            let tag = Tag::from_name(tag)
+                .ok_or_else(|| Ack::arg(format!("Unknown filter type: {}", tag)))?;
+            let op = match self.word() {
+                "==" => Op::Equals,
+                "!=" => Op::NotEquals,
+                "contains" => Op::Contains,
+                op => return Err(Ack::arg(format!("Unknown filter operator: {}", op))),
+            };
+            let value = self.string()?;
+            Filter::Match { tag, op, value }
+        };
+        self.expect(')')?;
+        Ok(filter)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.rest = self.rest.trim_start();
+        self.rest.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.rest = &self.rest[c.len_utf8()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Ack> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(Ack::arg(format!("'{}' expected", c)))
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        self.peek();
+        match self.rest.strip_prefix(word) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A tag name or operator, up to the next space, bracket or quote
+    fn word(&mut self) -> &'a str {
+        self.peek();
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '"' | '\''))
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        word
+    }
+
+    /// A single or double quoted value with backslash escapes
+    fn string(&mut self) -> Result<String, Ack> {
+        self.peek();
+        let mut chars = self.rest.char_indices();
+        let Some((_, quote @ ('"' | '\''))) = chars.next() else {
+            return Err(Ack::arg("Quoted string expected"));
+        };
+        let mut value = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                c if c == quote => {
+                    self.rest = &self.rest[i + 1..];
+                    return Ok(value);
+                }
+                c => value.push(c),
+            }
+        }
+        Err(Ack::arg("Closing quote expected"))
+    }
+}
+
+/// Split a command line into words, unquoting `"..."` arguments with backslash escapes
+fn tokenize(line: &str) -> Result<Vec<String>, Ack> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Ok(words);
+        };
+        let mut word = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => word.extend(chars.next()),
+                    Some(c) => word.push(c),
+                    None => return Err(Ack::arg("Missing closing '\"'")),
+                }
+            }
+        } else {
+            word.push(first);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+}
+
+fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+fn arg<'a>(args: &[&'a str], index: usize) -> Result<&'a str, Ack> {
+    args.get(index)
+        .copied()
+        .ok_or_else(|| Ack::arg("Wrong number of arguments"))
+}
+
+fn parse_number<T: FromStr>(text: &str) -> Result<T, Ack> {
+    text.parse()
+        .map_err(|_| Ack::arg(format!("Integer expected: {}", text)))
+}
+
+fn parse_bool(text: &str) -> Result<bool, Ack> {
+    match text {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(Ack::arg(format!("Boolean (0/1) expected: {}", text))),
+    }
+}
+
+/// Seconds, possibly fractional
+fn parse_time(text: &str) -> Result<Duration, Ack> {
+    text.parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| Ack::arg(format!("Float expected: {}", text)))
+}
+
+/// `POS`, `START:END` or `START:`
+fn parse_span(text: &str) -> Result<(usize, Option<usize>), Ack> {
+    match text.split_once(':') {
+        Some((start, "")) => Ok((parse_number(start)?, None)),
+        Some((start, end)) => Ok((parse_number(start)?, Some(parse_number(end)?))),
+        None => {
+            let position: usize = parse_number(text)?;
+            let end = position
+                .checked_add(1)
+                .ok_or_else(|| Ack::arg("Bad song index"))?;
+            Ok((position, Some(end)))
+        }
+    }
+}
+
+/// Positions in a queue of `len` songs, from a `POS` or `START:END` argument
+fn parse_range(text: &str, len: usize) -> Result<Range<usize>, Ack> {
+    let (start, end) = parse_span(text)?;
+    let end = end.unwrap_or(len);
+    if start > end || end > len {
+        return Err(Ack::arg("Bad song index"));
+    }
+    Ok(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn song(path: &str, title: &str, duration: f64) -> TrackInfo {
+        TrackInfo {
+            path: PathBuf::from(path),
+            title: title.to_string(),
+            artist: Some("Low".to_string()),
+            album: Some("Things We Lost in the Fire".to_string()),
+            track_number: Some(1),
+            duration: Some(duration),
+        }
+    }
+
+    /// Shared state for a three song queue with the second song playing
+    fn shared() -> (Arc<Shared>, Receiver<Call>) {
+        let queue = vec![
+            song("/music/Sunflower.flac", "Sunflower", 245.0),
+            song("/music/Whore.flac", "Whore", 181.4),
+            song("/music/Dinosaur Act.flac", "Dinosaur Act", 300.0),
+        ];
+        let status = Status {
+            state: PlaybackState::Playing,
+            track: Some(queue[1].clone()),
+            position: 12.5,
+            volume: 70,
+            shuffle: false,
+            repeat: "off".to_string(),
+            index: 1,
+            tracks: queue.len(),
+            queue: 0,
+        };
+        let positions = queue
+            .iter()
+            .enumerate()
+            .map(|(i, track)| (track.path.clone(), i))
+            .collect();
+        let (calls_sender, calls) = mpsc::channel();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                status,
+                queue,
+                positions,
+                version: 4,
+                changes: [0; SUBSYSTEMS],
+            }),
+            watchers: Mutex::new(Vec::new()),
+            calls: calls_sender,
+            music_directory: Some(PathBuf::from("/music")),
+            extensions: vec!["flac".to_string()],
+            follow_links: false,
+            tags: Mutex::new(HashMap::new()),
+            started: Instant::now(),
+        });
+        (shared, calls)
+    }
+
+    /// Carry out calls the way the main loop does, on the snapshot alone, reporting each one
+    fn main_loop(shared: Arc<Shared>, calls: Receiver<Call>) -> Receiver<String> {
+        let (log, changes) = mpsc::channel();
+        thread::spawn(move || {
+            for call in calls {
+                let mut changed = Vec::new();
+                {
+                    let mut state = shared.state();
+                    match &call.change {
+                        Change::Command(Command::Repeat(mode)) => {
+                            state.status.repeat = mode.to_string().to_lowercase();
+                            changed.push(Subsystem::Options);
+                        }
+                        Change::Command(Command::Shuffle(Some(shuffle))) => {
+                            state.status.shuffle = *shuffle;
+                            changed.push(Subsystem::Options);
+                        }
+                        Change::Add(paths) => {
+                            for path in paths {
+                                let position = state.queue.len();
+                                state.positions.insert(path.clone(), position);
+                                state
+                                    .queue
+                                    .push(song(&path.to_string_lossy(), "Added", 60.0));
+                            }
+                            state.status.tracks = state.queue.len();
+                            state.version += 1;
+                            changed.push(Subsystem::Playlist);
+                        }
+                        Change::Play { position, .. } => {
+                            state.status.index = *position;
+                            state.status.track = Some(state.queue[*position].clone());
+                            state.status.position = 0.0;
+                            changed.push(Subsystem::Player);
+                        }
+                        _ => {}
+                    }
+                }
+                shared.changed(&changed);
+                let _ = log.send(format!("{:?}", call.change));
+                call.finish(Ok(()));
+            }
+        });
+        changes
+    }
+
+    fn session(shared: &Arc<Shared>) -> Session {
+        Session {
+            shared: Arc::clone(shared),
+            local: true,
+            seen: shared.state().changes,
+            idle: None,
+            list: None,
+        }
+    }
+
+    /// Send lines to a session, returning what it wrote
+    fn send(session: &mut Session, lines: &[&str]) -> String {
+        let mut out = String::new();
+        for line in lines {
+            assert!(session.handle_line(line, &mut out), "closed on {}", line);
+        }
+        out
+    }
+
+    /// Connect to a server on a free local port
+    fn connect(shared: &Arc<Shared>) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let shared = Arc::clone(shared);
+        thread::spawn(move || accept(listener, shared));
+        TcpStream::connect(address).unwrap()
+    }
+
+    /// A client connected to a server on a free local port
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn connect(shared: &Arc<Shared>) -> Self {
+            let writer = connect(shared);
+            writer
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut client = Self {
+                reader: BufReader::new(writer.try_clone().unwrap()),
+                writer,
+            };
+            assert_eq!(client.read_line(), GREETING);
+            client
+        }
+
+        fn read_line(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line
+        }
+
+        /// Send a command and read its answer, up to and including `OK` or `ACK`
+        fn command(&mut self, line: &str) -> String {
+            writeln!(self.writer, "{}", line).unwrap();
+            self.answer()
+        }
+
+        fn answer(&mut self) -> String {
+            let mut answer = String::new();
+            loop {
+                let line = self.read_line();
+                assert!(!line.is_empty(), "connection closed");
+                answer.push_str(&line);
+                if line == "OK\n" || line.starts_with("ACK ") {
+                    return answer;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tokenize_unquotes_arguments() {
+        assert_eq!(tokenize("  play   2 ").unwrap(), ["play", "2"]);
+        assert_eq!(
+            tokenize(r#"add "Low/Things We Lost/01 Sunflower.flac""#).unwrap(),
+            ["add", "Low/Things We Lost/01 Sunflower.flac"]
+        );
+        assert_eq!(
+            tokenize(r#"find "(title == \"Dinosaur Act\")" window 0:1"#).unwrap(),
+            ["find", r#"(title == "Dinosaur Act")"#, "window", "0:1"]
+        );
+        assert_eq!(
+            tokenize(r#"add "back\\slash" """#).unwrap(),
+            ["add", r"back\slash", ""]
+        );
+        assert!(tokenize("").unwrap().is_empty());
+        assert!(tokenize(r#"add "unfinished"#).is_err());
+        assert!(tokenize(r#"add "escaped quote\""#).is_err());
+    }
+
+    #[test]
+    fn parse_range_takes_positions_and_spans() {
+        assert_eq!(parse_range("1", 3).unwrap(), 1..2);
+        assert_eq!(parse_range("0:2", 3).unwrap(), 0..2);
+        assert_eq!(parse_range("1:", 3).unwrap(), 1..3);
+        assert_eq!(parse_range("3:", 3).unwrap(), 3..3);
+        assert!(parse_range("3", 3).is_err());
+        assert!(parse_range("2:1", 3).is_err());
+        assert!(parse_range("0:4", 3).is_err());
+        assert!(parse_range("-1", 3).is_err());
+        assert!(parse_range("one", 3).is_err());
+        assert!(parse_range(&usize::MAX.to_string(), 3).is_err());
+    }
+
+    #[test]
+    fn command_lists_stop_at_the_first_error() {
+        let (shared, _calls) = shared();
+        let mut session = session(&shared);
+
+        assert_eq!(
+            send(
+                &mut session,
+                &["command_list_begin", "ping", "getvol", "command_list_end"]
+            ),
+            "volume: 70\nOK\n"
+        );
+        assert_eq!(
+            send(
+                &mut session,
+                &[
+                    "command_list_ok_begin",
+                    "ping",
+                    "getvol",
+                    "command_list_end"
+                ]
+            ),
+            "list_OK\nvolume: 70\nlist_OK\nOK\n"
+        );
+        assert_eq!(
+            send(
+                &mut session,
+                &[
+                    "command_list_ok_begin",
+                    "ping",
+                    "frobnicate now",
+                    "ping",
+                    "command_list_end"
+                ]
+            ),
+            "list_OK\nACK [5@1] {frobnicate} unknown command \"frobnicate\"\n"
+        );
+        assert_eq!(
+            send(
+                &mut session,
+                &["command_list_begin", "ping", "idle", "command_list_end"]
+            ),
+            "ACK [2@1] {idle} idle is not allowed in command lists\n"
+        );
+        assert_eq!(
+            send(&mut session, &["setvol 101"]),
+            "ACK [2@0] {setvol} Invalid volume value\n"
+        );
+    }
+
+    #[test]
+    fn idle_waits_for_changes_until_noidle() {
+        let (shared, _calls) = shared();
+        let mut session = session(&shared);
+
+        // Nothing has changed yet, so idle doesn't answer
+        assert_eq!(send(&mut session, &["idle player mixer"]), "");
+        shared.changed(&[Subsystem::Options]);
+        let mut out = String::new();
+        session.report_idle(false, &mut out);
+        assert_eq!(out, "");
+        shared.changed(&[Subsystem::Mixer]);
+        session.report_idle(false, &mut out);
+        assert_eq!(out, "changed: mixer\nOK\n");
+
+        // Changes while not idle are reported by the next idle straight away
+        shared.changed(&[Subsystem::Player]);
+        assert_eq!(
+            send(&mut session, &["idle"]),
+            "changed: player\nchanged: options\nOK\n"
+        );
+
+        assert_eq!(send(&mut session, &["idle playlist"]), "");
+        assert_eq!(send(&mut session, &["noidle"]), "OK\n");
+        // noidle without idle is answered like any other command
+        assert_eq!(send(&mut session, &["noidle"]), "OK\n");
+
+        assert_eq!(
+            send(&mut session, &["idle lunch"]),
+            "ACK [2@0] {idle} Unrecognized idle event: lunch\n"
+        );
+        // Anything but noidle while idle drops the client
+        assert_eq!(send(&mut session, &["idle"]), "");
+        assert!(!session.handle_line("status", &mut String::new()));
+    }
+
+    #[test]
+    fn answers_status_and_songs_over_tcp() {
+        let (shared, calls) = shared();
+        let _changes = main_loop(Arc::clone(&shared), calls);
+        let mut client = Client::connect(&shared);
+
+        assert_eq!(
+            client.command("status"),
+            "volume: 70\nrepeat: 0\nrandom: 0\nsingle: 0\nconsume: 0\nplaylist: 4\n\
+             playlistlength: 3\nstate: play\nsong: 1\nsongid: 1\ntime: 13:181\n\
+             elapsed: 12.500\nduration: 181.400\nOK\n"
+        );
+        assert_eq!(
+            client.command("currentsong"),
+            "file: Whore.flac\nTitle: Whore\nArtist: Low\nAlbum: Things We Lost in the Fire\n\
+             Track: 1\nTime: 181\nduration: 181.400\nPos: 1\nId: 1\nOK\n"
+        );
+
+        let info = client.command("playlistinfo");
+        let files: Vec<&str> = info
+            .lines()
+            .filter_map(|line| line.strip_prefix("file: "))
+            .collect();
+        assert_eq!(files, ["Sunflower.flac", "Whore.flac", "Dinosaur Act.flac"]);
+        assert!(info.contains("Pos: 2\nId: 2\n"));
+        let info = client.command("playlistinfo 2");
+        assert!(info.starts_with("file: Dinosaur Act.flac\n"));
+        assert_eq!(info.matches("file: ").count(), 1);
+        assert_eq!(
+            client.command("playlistinfo 1:5"),
+            "ACK [2@0] {playlistinfo} Bad song index\n"
+        );
+
+        client.command("play 2");
+        assert!(
+            client
+                .command("currentsong")
+                .contains("Title: Dinosaur Act\n")
+        );
+    }
+
+    #[test]
+    fn repeat_single_and_random_set_the_play_modes() {
+        let (shared, calls) = shared();
+        let changes = main_loop(Arc::clone(&shared), calls);
+        let mut client = Client::connect(&shared);
+        let mut change = |line: &str| {
+            assert_eq!(client.command(line), "OK\n");
+            let change = changes.recv_timeout(Duration::from_secs(5)).unwrap();
+            let status = client.command("status");
+            let flags: Vec<&str> = status
+                .lines()
+                .filter(|line| {
+                    ["repeat", "random", "single"]
+                        .iter()
+                        .any(|f| line.starts_with(f))
+                })
+                .collect();
+            (change, flags.join(" "))
+        };
+
+        assert_eq!(
+            change("repeat 1"),
+            (
+                "Command(Repeat(All))".to_string(),
+                "repeat: 1 random: 0 single: 0".to_string()
+            )
+        );
+        assert_eq!(
+            change("single 1"),
+            (
+                "Command(Repeat(One))".to_string(),
+                "repeat: 1 random: 0 single: 1".to_string()
+            )
+        );
+        // Turning repeat back on keeps single
+        assert_eq!(change("repeat 1").0, "Command(Repeat(One))");
+        assert_eq!(
+            change("single 0"),
+            (
+                "Command(Repeat(All))".to_string(),
+                "repeat: 1 random: 0 single: 0".to_string()
+            )
+        );
+        assert_eq!(
+            change("repeat 0"),
+            (
+                "Command(Repeat(None))".to_string(),
+                "repeat: 0 random: 0 single: 0".to_string()
+            )
+        );
+        assert_eq!(
+            change("random 1"),
+            (
+                "Command(Shuffle(Some(true)))".to_string(),
+                "repeat: 0 random: 1 single: 0".to_string()
+            )
+        );
+        assert_eq!(
+            change("random 0"),
+            (
+                "Command(Shuffle(Some(false)))".to_string(),
+                "repeat: 0 random: 0 single: 0".to_string()
+            )
+        );
+        assert_eq!(
+            client.command("random on"),
+            "ACK [2@0] {random} Boolean (0/1) expected: on\n"
+        );
+    }
+
+    #[test]
+    fn idle_clients_are_woken_by_changes_from_others() {
+        let (shared, calls) = shared();
+        let _changes = main_loop(Arc::clone(&shared), calls);
+        let mut idle = Client::connect(&shared);
+        let mut other = Client::connect(&shared);
+
+        writeln!(idle.writer, "idle options").unwrap();
+        assert_eq!(other.command("random 1"), "OK\n");
+        assert_eq!(idle.answer(), "changed: options\nOK\n");
+
+        writeln!(idle.writer, "idle options").unwrap();
+        assert_eq!(idle.command("noidle"), "OK\n");
+        assert_eq!(idle.command("ping"), "OK\n");
+
+        // Sending anything else while idle closes the connection
+        writeln!(idle.writer, "idle\nstatus").unwrap();
+        let mut rest = String::new();
+        idle.reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn changes_made_stale_by_another_client_are_refused() {
+        let (shared, calls) = shared();
+        let mut deleting = Client::connect(&shared);
+        let mut playing = Client::connect(&shared);
+
+        // Both commands pass their checks on the snapshot before the main loop gets to either
+        writeln!(deleting.writer, "delete 1:3").unwrap();
+        let delete = calls.recv_timeout(Duration::from_secs(5)).unwrap();
+        writeln!(playing.writer, "play 2").unwrap();
+        let play = calls.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        for call in [delete, play] {
+            let result = {
+                let mut state = shared.state();
+                let result = call.change.check(state.queue.len(), state.version);
+                if let (Ok(()), Change::Delete { positions, .. }) = (&result, &call.change) {
+                    for position in positions.iter().rev() {
+                        state.queue.remove(*position);
+                    }
+                    state.version += 1;
+                }
+                result
+            };
+            call.finish(result);
+        }
+
+        assert_eq!(deleting.answer(), "OK\n");
+        assert_eq!(
+            playing.answer(),
+            "ACK [52@0] {play} Bad song index: the queue has changed\n"
+        );
+
+        // A position still in range is refused too once the list has changed under it
+        let stale = Change::Play {
+            position: 0,
+            version: 4,
+        };
+        assert!(stale.check(1, 5).is_err());
+        assert!(stale.check(1, 4).is_ok());
+    }
+
+    #[test]
+    fn file_urls_are_only_added_for_local_clients() {
+        let (shared, calls) = shared();
+        let changes = main_loop(Arc::clone(&shared), calls);
+        let url = format!("file://{}/Cargo.toml", env!("CARGO_MANIFEST_DIR"));
+
+        let mut remote = session(&shared);
+        remote.local = false;
+        assert_eq!(
+            send(&mut remote, &[&format!("add \"{}\"", url)]),
+            "ACK [4@0] {add} Access denied\n"
+        );
+        assert!(changes.try_recv().is_err());
+
+        let mut local = session(&shared);
+        assert_eq!(send(&mut local, &[&format!("add \"{}\"", url)]), "OK\n");
+        let change = changes.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(change.starts_with("Add(") && change.contains("Cargo.toml"));
+    }
+
+    #[test]
+    fn works_with_an_mpd_client_library() {
+        use mpd::{Idle, Song, State, Subsystem};
+
+        let (shared, calls) = shared();
+        let _changes = main_loop(Arc::clone(&shared), calls);
+        let library_client = || {
+            let stream = connect(&shared);
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            mpd::Client::new(stream).unwrap()
+        };
+        let mut client = library_client();
+
+        let status = client.status().unwrap();
+        assert_eq!(status.state, State::Play);
+        assert_eq!(status.volume, 70);
+        assert!(!status.repeat && !status.random && !status.single);
+        assert_eq!((status.queue_version, status.queue_len), (4, 3));
+        assert_eq!(status.song.map(|song| song.pos), Some(1));
+        assert_eq!(status.elapsed, Some(Duration::from_millis(12500)));
+        assert_eq!(status.duration, Some(Duration::from_millis(181400)));
+
+        let current = client.currentsong().unwrap().unwrap();
+        assert_eq!(current.file, "Whore.flac");
+        assert_eq!(current.title.as_deref(), Some("Whore"));
+        assert_eq!(current.artist.as_deref(), Some("Low"));
+        assert_eq!(current.place.map(|place| place.id.0), Some(1));
+
+        let files: Vec<String> = client
+            .queue()
+            .unwrap()
+            .into_iter()
+            .map(|song| song.file)
+            .collect();
+        assert_eq!(files, ["Sunflower.flac", "Whore.flac", "Dinosaur Act.flac"]);
+
+        // An idle client hears about a song another one adds
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Like a Forest.flac");
+        fs::write(&path, b"").unwrap();
+        let song = Song {
+            file: format!("file://{}", path.display()),
+            ..Song::default()
+        };
+        let idle = client.idle(&[Subsystem::Queue]).unwrap();
+        let id = library_client().push(&song).unwrap();
+        assert_eq!(idle.get().unwrap(), [Subsystem::Queue]);
+        assert_eq!(id.0, 3);
+
+        let queue = client.queue().unwrap();
+        assert_eq!(queue.len(), 4);
+        assert_eq!(queue[3].file, path.to_string_lossy());
+        assert_eq!(client.status().unwrap().queue_version, 5);
+    }
+}
@@ -0,0 +1,876 @@
+//! MPRIS interface on the D-Bus session bus
+//!
+//! Exposes `org.mpris.MediaPlayer2` and `org.mpris.MediaPlayer2.Player` so desktop media
+//! keys, `playerctl` and status bars can control the player. The bus runs on its own thread;
+//! method calls become control requests for the main loop, and the main loop sends back a
+//! status snapshot on every pass, from which property changes and `Seeked` are signalled.
+//! Property setters wait for the main loop to apply the change, since zbus signals the new
+//! value as soon as a setter returns.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::sync::oneshot;
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+use zbus::zvariant::{ObjectPath, Value};
+use zbus::{fdo, interface};
+
+use crate::ipc::{Request, Status};
+use crate::{PlaybackState, artwork};
+
+/// Well-known name claimed on the session bus
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.music_player";
+
+/// Object path required by the MPRIS specification
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+
+/// Track id reported when nothing is loaded
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// Difference from the expected position, in seconds, taken to be a seek
+const SEEK_THRESHOLD: f64 = 1.0;
+
+/// A request from a D-Bus client, for the main loop to carry out
+pub struct Call {
+    pub request: Request,
+    /// Where a waiting property setter wants the status after the change
+    reply: Option<oneshot::Sender<Status>>,
+}
+
+impl Call {
+    /// Tell a waiting setter the request has been carried out
+    pub fn finish(self, status: Status) {
+        if let Some(reply) = self.reply {
+            let _ = reply.send(status);
+        }
+    }
+}
+
+/// Connection to the session bus, owned by the main loop
+pub struct Mpris {
+    requests: Receiver<Call>,
+    updates: UnboundedSender<Status>,
+}
+
+impl Mpris {
+    /// Claim the bus name and export the interfaces, failing when there is no session bus
+    pub fn start(status: Status) -> Result<Self> {
+        let (request_sender, requests) = mpsc::channel();
+        let (updates, update_receiver) = unbounded_channel();
+        let (ready_sender, ready) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("mpris".to_string())
+            .spawn(move || {
+                let runtime = match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        let _ = ready_sender.send(Err(e.into()));
+                        return;
+                    }
+                };
+                runtime.block_on(serve(status, request_sender, update_receiver, ready_sender));
+            })
+            .context("Failed to start the MPRIS thread")?;
+
+        ready
+            .recv()
+            .context("The MPRIS thread stopped")?
+            .context("Failed to register on the session bus")?;
+        info!("Registered {} on the session bus", BUS_NAME);
+
+        Ok(Self { requests, updates })
+    }
+
+    /// Take the next request from a D-Bus client, if any
+    pub fn try_recv(&self) -> Option<Call> {
+        self.requests.try_recv().ok()
+    }
+
+    /// Report the current state, signalling whatever changed since the last update
+    pub fn update(&self, status: Status) {
+        let _ = self.updates.send(status);
+    }
+}
+
+/// State shared by the exported interfaces
+struct Shared {
+    status: Status,
+    /// `file://` URL of the current track's cover, for the track it was looked up for
+    art: Option<(PathBuf, Option<String>)>,
+}
+
+impl Shared {
+    /// Look up the cover when the track has changed
+    fn refresh_art(&mut self) {
+        let Some(track) = &self.status.track else {
+            self.art = None;
+            return;
+        };
+        if self
+            .art
+            .as_ref()
+            .is_none_or(|(path, _)| *path != track.path)
+        {
+            let url = artwork::cover_file(&track.path).map(|file| file_url(&file));
+            self.art = Some((track.path.clone(), url));
+        }
+    }
+}
+
+/// Run the bus connection until the main loop goes away
+async fn serve(
+    status: Status,
+    requests: Sender<Call>,
+    mut updates: UnboundedReceiver<Status>,
+    ready: Sender<Result<()>>,
+) {
+    let mut shared = Shared { status, art: None };
+    shared.refresh_art();
+    let shared = Arc::new(Mutex::new(shared));
+
+    let connection = zbus::connection::Builder::session()
+        .and_then(|builder| builder.name(BUS_NAME))
+        .and_then(|builder| builder.serve_at(OBJECT_PATH, Root(requests.clone())))
+        .and_then(|builder| {
+            builder.serve_at(
+                OBJECT_PATH,
+                Player {
+                    shared: shared.clone(),
+                    requests,
+                },
+            )
+        });
+    let connection = match connection {
+        Ok(builder) => builder.build().await,
+        Err(e) => Err(e),
+    };
+    let connection = match connection {
+        Ok(connection) => connection,
+        Err(e) => {
+            let _ = ready.send(Err(e.into()));
+            return;
+        }
+    };
+    let player = match connection
+        .object_server()
+        .interface::<_, Player>(OBJECT_PATH)
+        .await
+    {
+        Ok(player) => player,
+        Err(e) => {
+            let _ = ready.send(Err(e.into()));
+            return;
+        }
+    };
+    let _ = ready.send(Ok(()));
+
+    let mut updated = Instant::now();
+    while let Some(status) = updates.recv().await {
+        let (previous, seeked) = {
+            let Ok(mut shared) = shared.lock() else {
+                return;
+            };
+            let previous = std::mem::replace(&mut shared.status, status);
+            shared.refresh_art();
+            let seeked = seeked(&previous, &shared.status, updated);
+            (previous, seeked)
+        };
+        updated = Instant::now();
+        if let Err(e) = signal_changes(&player, &previous, seeked).await {
+            warn!("Failed to send MPRIS signals: {}", e);
+        }
+    }
+}
+
+/// New position in microseconds, when it jumped rather than moved with playback
+fn seeked(previous: &Status, status: &Status, since: Instant) -> Option<i64> {
+    let same_track =
+        previous.track.as_ref().map(|t| &t.path) == status.track.as_ref().map(|t| &t.path);
+    if !same_track || status.state == PlaybackState::Stopped {
+        return None;
+    }
+    let expected = if previous.state == PlaybackState::Playing {
+        previous.position + since.elapsed().as_secs_f64()
+    } else {
+        previous.position
+    };
+    ((status.position - expected).abs() > SEEK_THRESHOLD).then(|| micros(status.position))
+}
+
+/// Emit `PropertiesChanged` for what differs from `previous`, and `Seeked` after a jump
+async fn signal_changes(
+    player: &InterfaceRef<Player>,
+    previous: &Status,
+    seeked: Option<i64>,
+) -> zbus::Result<()> {
+    let emitter = player.signal_emitter();
+    let player = player.get().await;
+    let status = player.shared.lock().map(|s| s.status.clone()).ok();
+    let Some(status) = status else {
+        return Ok(());
+    };
+
+    if status.state != previous.state {
+        player.playback_status_changed(emitter).await?;
+    }
+    if status.track != previous.track {
+        player.metadata_changed(emitter).await?;
+    }
+    if status.volume != previous.volume {
+        player.volume_changed(emitter).await?;
+    }
+    if status.shuffle != previous.shuffle {
+        player.shuffle_changed(emitter).await?;
+    }
+    if status.repeat != previous.repeat {
+        player.loop_status_changed(emitter).await?;
+    }
+    if let Some(position) = seeked {
+        Player::seeked(emitter, position).await?;
+    }
+    Ok(())
+}
+
+/// `org.mpris.MediaPlayer2`: identity and capabilities of the application
+struct Root(Sender<Call>);
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {
+        let _ = self.0.send(Call {
+            request: Request::Exec {
+                line: "quit".to_string(),
+            },
+            reply: None,
+        });
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "Terminal Music Player"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["file".to_string()]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        [
+            "audio/mpeg",
+            "audio/wav",
+            "audio/ogg",
+            "audio/flac",
+            "audio/mp4",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+}
+
+/// `org.mpris.MediaPlayer2.Player`: playback control and the playing track
+struct Player {
+    shared: Arc<Mutex<Shared>>,
+    requests: Sender<Call>,
+}
+
+impl Player {
+    fn send(&self, request: Request) {
+        let _ = self.requests.send(Call {
+            request,
+            reply: None,
+        });
+    }
+
+    /// Send a request and wait until the main loop has carried it out
+    async fn apply(&self, request: Request) {
+        let (reply, done) = oneshot::channel();
+        let call = Call {
+            request,
+            reply: Some(reply),
+        };
+        if self.requests.send(call).is_err() {
+            return;
+        }
+        if let Ok(status) = done.await
+            && let Ok(mut shared) = self.shared.lock()
+        {
+            shared.status = status;
+        }
+    }
+
+    fn status(&self) -> fdo::Result<Status> {
+        self.shared
+            .lock()
+            .map(|shared| shared.status.clone())
+            .map_err(|_| fdo::Error::Failed("Player state is unavailable".to_string()))
+    }
+
+    async fn exec(&self, line: &str) {
+        self.apply(Request::Exec {
+            line: line.to_string(),
+        })
+        .await;
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) {
+        self.send(Request::Next);
+    }
+
+    fn previous(&self) {
+        self.send(Request::Previous);
+    }
+
+    fn pause(&self) {
+        self.send(Request::Pause);
+    }
+
+    fn play_pause(&self) {
+        self.send(Request::Toggle);
+    }
+
+    fn stop(&self) {
+        self.send(Request::Stop);
+    }
+
+    fn play(&self) {
+        self.send(Request::Play);
+    }
+
+    /// Seek by an offset in microseconds
+    fn seek(&self, offset: i64) {
+        self.send(Request::Seek {
+            position: None,
+            offset: Some(seconds(offset)),
+        });
+    }
+
+    /// Seek to a position in microseconds, if `track_id` is still the current track
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let status = self.status()?;
+        if position < 0 || track_id.as_str() != track_id_of(&status) {
+            return Ok(());
+        }
+        self.send(Request::Seek {
+            position: Some(seconds(position)),
+            offset: None,
+        });
+        Ok(())
+    }
+
+    /// Play a `file://` URI next, adding it to the track list if needed
+    fn open_uri(&self, uri: &str) -> fdo::Result<()> {
+        let path = uri
+            .strip_prefix("file://")
+            .and_then(percent_decode)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("Unsupported URI: {}", uri)))?;
+        self.send(Request::Enqueue {
+            path: PathBuf::from(path),
+        });
+        self.send(Request::Next);
+        Ok(())
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> fdo::Result<&'static str> {
+        Ok(match self.status()?.state {
+            PlaybackState::Playing => "Playing",
+            PlaybackState::Paused => "Paused",
+            PlaybackState::Stopped => "Stopped",
+        })
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> fdo::Result<&'static str> {
+        Ok(match self.status()?.repeat.as_str() {
+            "one" => "Track",
+            "all" => "Playlist",
+            _ => "None",
+        })
+    }
+
+    #[zbus(property)]
+    async fn set_loop_status(&self, value: &str) -> zbus::Result<()> {
+        let mode = match value {
+            "None" => "off",
+            "Track" => "one",
+            "Playlist" => "all",
+            _ => {
+                return Err(
+                    fdo::Error::InvalidArgs(format!("Unknown loop status: {}", value)).into(),
+                );
+            }
+        };
+        self.exec(&format!("repeat {}", mode)).await;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> fdo::Result<bool> {
+        Ok(self.status()?.shuffle)
+    }
+
+    #[zbus(property)]
+    async fn set_shuffle(&self, value: bool) {
+        self.exec(if value { "shuffle on" } else { "shuffle off" })
+            .await;
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> fdo::Result<f64> {
+        Ok(f64::from(self.status()?.volume) / 100.0)
+    }
+
+    #[zbus(property)]
+    async fn set_volume(&self, value: f64) {
+        self.apply(Request::Volume {
+            volume: Some((value.clamp(0.0, 1.0) * 100.0).round() as u8),
+            offset: None,
+        })
+        .await;
+    }
+
+    /// Position in the current track, in microseconds
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> fdo::Result<i64> {
+        Ok(micros(self.status()?.position))
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> fdo::Result<HashMap<String, Value<'static>>> {
+        let shared = self
+            .shared
+            .lock()
+            .map_err(|_| fdo::Error::Failed("Player state is unavailable".to_string()))?;
+        let status = &shared.status;
+
+        let mut metadata = HashMap::new();
+        let track_id = ObjectPath::try_from(track_id_of(status))
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+        metadata.insert("mpris:trackid".to_string(), Value::from(track_id));
+        let Some(track) = &status.track else {
+            return Ok(metadata);
+        };
+
+        metadata.insert("xesam:title".to_string(), Value::from(track.title.clone()));
+        metadata.insert("xesam:url".to_string(), Value::from(file_url(&track.path)));
+        if let Some(artist) = &track.artist {
+            metadata.insert(
+                "xesam:artist".to_string(),
+                Value::from(vec![artist.clone()]),
+            );
+        }
+        if let Some(album) = &track.album {
+            metadata.insert("xesam:album".to_string(), Value::from(album.clone()));
+        }
+        if let Some(duration) = track.duration {
+            metadata.insert("mpris:length".to_string(), Value::from(micros(duration)));
+        }
+        if let Some((_, Some(url))) = &shared.art {
+            metadata.insert("mpris:artUrl".to_string(), Value::from(url.clone()));
+        }
+        Ok(metadata)
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    /// Playback speed can't be changed, so requested rates are ignored
+    #[zbus(property)]
+    fn set_rate(&self, _value: f64) {}
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// D-Bus object path identifying the current track
+fn track_id_of(status: &Status) -> String {
+    if status.track.is_some() {
+        format!("/org/music_player/track/{}", status.index)
+    } else {
+        NO_TRACK.to_string()
+    }
+}
+
+fn micros(seconds: f64) -> i64 {
+    (seconds * 1_000_000.0) as i64
+}
+
+fn seconds(micros: i64) -> f64 {
+    micros as f64 / 1_000_000.0
+}
+
+/// `file://` URL of a path, percent-encoding everything but unreserved characters and `/`
+fn file_url(path: &Path) -> String {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut url = String::from("file://");
+    for byte in absolute.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                url.push(char::from(byte))
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+/// Undo `%XX` escapes in a URI path
+pub fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.bytes();
+    while let Some(byte) = rest.next() {
+        if byte == b'%' {
+            let hex = [rest.next()?, rest.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::time::Duration;
+
+    use futures_lite::StreamExt;
+    use zbus::proxy::CacheProperties;
+    use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+    use super::*;
+    use crate::RepeatMode;
+    use crate::command::Command as PlayerCommand;
+    use crate::ipc::TrackInfo;
+
+    /// How long to wait for a call or signal before failing
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A session bus of our own, so the test never talks to the desktop's
+    struct PrivateBus(Child);
+
+    impl PrivateBus {
+        /// Start `dbus-daemon` and point the session bus address at it, or `None` without one
+        fn start() -> Option<Self> {
+            let mut child = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(child.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            // SAFETY: no other test changes the environment, and the rest only read it through std
+            unsafe { std::env::set_var("DBUS_SESSION_BUS_ADDRESS", address.trim()) };
+            Some(Self(child))
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn status() -> Status {
+        Status {
+            state: PlaybackState::Playing,
+            track: Some(TrackInfo {
+                path: PathBuf::from("/music/Ask Me #2.flac"),
+                title: "Ask Me".to_string(),
+                artist: Some("The Smiths".to_string()),
+                album: Some("Louder Than Bombs".to_string()),
+                track_number: Some(2),
+                duration: Some(200.5),
+            }),
+            position: 10.0,
+            volume: 80,
+            shuffle: false,
+            repeat: "off".to_string(),
+            index: 3,
+            tracks: 10,
+            queue: 0,
+        }
+    }
+
+    /// Wait for the next request from the bus
+    async fn next_call(mpris: &Mpris) -> Call {
+        let started = Instant::now();
+        loop {
+            if let Some(call) = mpris.try_recv() {
+                return call;
+            }
+            assert!(started.elapsed() < TIMEOUT, "no request arrived");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Set a property the way a client would, answering the call as the main loop does
+    async fn set(
+        mpris: &Mpris,
+        player: &zbus::Proxy<'static>,
+        name: &'static str,
+        value: impl Into<Value<'static>> + Send + 'static,
+        after: Status,
+    ) -> Request {
+        let player = player.clone();
+        let setter = tokio::spawn(async move { player.set_property(name, value).await });
+        let call = next_call(mpris).await;
+        let request = call.request.clone();
+        call.finish(after);
+        setter.await.unwrap().unwrap();
+        request
+    }
+
+    #[test]
+    fn file_urls_are_percent_encoded_and_decoded() {
+        let url = file_url(Path::new("/music/Ask Me #2.flac"));
+        assert_eq!(url, "file:///music/Ask%20Me%20%232.flac");
+        assert_eq!(
+            percent_decode(url.strip_prefix("file://").unwrap()).as_deref(),
+            Some("/music/Ask Me #2.flac")
+        );
+        assert_eq!(percent_decode("/music/%2"), None);
+    }
+
+    #[test]
+    fn only_jumps_in_position_are_seeks() {
+        let before = status();
+        let mut after = status();
+        after.position = 10.5;
+        assert_eq!(seeked(&before, &after, Instant::now()), None);
+        after.position = 70.0;
+        assert_eq!(seeked(&before, &after, Instant::now()), Some(70_000_000));
+        after.track = None;
+        assert_eq!(seeked(&before, &after, Instant::now()), None);
+    }
+
+    #[tokio::test]
+    async fn serves_the_player_on_the_session_bus() {
+        let Some(_bus) = PrivateBus::start() else {
+            eprintln!("dbus-daemon is not installed, skipping");
+            return;
+        };
+        let mpris = Mpris::start(status()).unwrap();
+
+        let connection = zbus::Connection::session().await.unwrap();
+        let player: zbus::Proxy<'static> = zbus::proxy::Builder::new(&connection)
+            .destination(BUS_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .interface("org.mpris.MediaPlayer2.Player")
+            .unwrap()
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+        let properties = fdo::PropertiesProxy::builder(&connection)
+            .destination(BUS_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let mut seeks = player.receive_signal("Seeked").await.unwrap();
+
+        // Methods become requests for the main loop
+        player.call_method("PlayPause", &()).await.unwrap();
+        assert!(matches!(next_call(&mpris).await.request, Request::Toggle));
+        player.call_method("Next", &()).await.unwrap();
+        assert!(matches!(next_call(&mpris).await.request, Request::Next));
+        player.call_method("Seek", &(-5_000_000i64)).await.unwrap();
+        assert!(matches!(
+            next_call(&mpris).await.request,
+            Request::Seek {
+                position: None,
+                offset: Some(-5.0)
+            }
+        ));
+        let track = ObjectPath::try_from("/org/music_player/track/3").unwrap();
+        player
+            .call_method("SetPosition", &(&track, 30_000_000i64))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_call(&mpris).await.request,
+            Request::Seek {
+                position: Some(30.0),
+                offset: None
+            }
+        ));
+        // A position for a track that is no longer playing is ignored
+        let stale = ObjectPath::try_from("/org/music_player/track/2").unwrap();
+        player
+            .call_method("SetPosition", &(&stale, 30_000_000i64))
+            .await
+            .unwrap();
+        assert!(mpris.try_recv().is_none());
+
+        // Setters wait for the change, then report the new value
+        let mut after = status();
+        after.volume = 50;
+        let request = set(&mpris, &player, "Volume", 0.5, after.clone()).await;
+        assert!(matches!(
+            request,
+            Request::Volume {
+                volume: Some(50),
+                offset: None
+            }
+        ));
+        assert_eq!(player.get_property::<f64>("Volume").await.unwrap(), 0.5);
+
+        after.repeat = "one".to_string();
+        let request = set(&mpris, &player, "LoopStatus", "Track", after.clone()).await;
+        assert_eq!(
+            request.command().unwrap(),
+            Some(PlayerCommand::Repeat(RepeatMode::One))
+        );
+        assert_eq!(
+            player.get_property::<String>("LoopStatus").await.unwrap(),
+            "Track"
+        );
+        after.repeat = "all".to_string();
+        let request = set(&mpris, &player, "LoopStatus", "Playlist", after.clone()).await;
+        assert_eq!(
+            request.command().unwrap(),
+            Some(PlayerCommand::Repeat(RepeatMode::All))
+        );
+        assert!(
+            player
+                .set_property("LoopStatus", "Sometimes")
+                .await
+                .is_err()
+        );
+
+        after.shuffle = true;
+        let request = set(&mpris, &player, "Shuffle", true, after.clone()).await;
+        assert_eq!(
+            request.command().unwrap(),
+            Some(PlayerCommand::Shuffle(Some(true)))
+        );
+        assert!(player.get_property::<bool>("Shuffle").await.unwrap());
+
+        let metadata: HashMap<String, OwnedValue> = player.get_property("Metadata").await.unwrap();
+        let text = |key: &str| String::try_from(metadata[key].try_clone().unwrap()).unwrap();
+        assert_eq!(
+            OwnedObjectPath::try_from(metadata["mpris:trackid"].try_clone().unwrap()).unwrap(),
+            OwnedObjectPath::from(track)
+        );
+        assert_eq!(text("xesam:title"), "Ask Me");
+        assert_eq!(text("xesam:album"), "Louder Than Bombs");
+        assert_eq!(text("xesam:url"), "file:///music/Ask%20Me%20%232.flac");
+        assert_eq!(
+            Vec::<String>::try_from(metadata["xesam:artist"].try_clone().unwrap()).unwrap(),
+            ["The Smiths"]
+        );
+        assert_eq!(
+            i64::try_from(metadata["mpris:length"].try_clone().unwrap()).unwrap(),
+            200_500_000
+        );
+
+        // Updates from the main loop are signalled
+        let mut changes = properties.receive_properties_changed().await.unwrap();
+        after.state = PlaybackState::Paused;
+        mpris.update(after.clone());
+        loop {
+            let signal = tokio::time::timeout(TIMEOUT, changes.next())
+                .await
+                .unwrap()
+                .unwrap();
+            let args = signal.args().unwrap();
+            if let Some(state) = args.changed_properties().get("PlaybackStatus") {
+                assert_eq!(*state, Value::from("Paused"));
+                break;
+            }
+        }
+
+        after.position = 120.0;
+        mpris.update(after);
+        let seek = tokio::time::timeout(TIMEOUT, seeks.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(seek.body().deserialize::<i64>().unwrap(), 120_000_000);
+    }
+}
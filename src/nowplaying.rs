@@ -0,0 +1,370 @@
+//! Now-playing files for stream overlays, such as OBS text sources
+//!
+//! Each configured file is rewritten when a track starts and when playback is paused, resumed
+//! or stopped. Files are written under a temporary name next to the target and renamed over it,
+//! so an overlay never reads half a file. The writing happens on a worker thread, since finding
+//! an embedded cover means reading the audio file.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config::NowPlayingConfig;
+use crate::ipc::TrackInfo;
+use crate::{MusicPlayer, PlaybackState, artwork, format_time};
+
+/// Placeholders a text template may use
+const FIELDS: &[&str] = &[
+    "title", "artist", "album", "elapsed", "duration", "cover", "state",
+];
+
+/// How a now-playing file is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// The template with its placeholders filled in
+    #[default]
+    Text,
+    /// A JSON object with every field
+    Json,
+}
+
+/// Check that a template only uses known placeholders
+pub fn check_template(template: &str) -> Result<()> {
+    render(template, |name| {
+        FIELDS
+            .contains(&name)
+            .then(String::new)
+            .with_context(|| format!("unknown placeholder {{{}}}", name))
+    })
+    .map(drop)
+}
+
+/// Fill in the `{name}` placeholders of a template; `{{` and `}}` stand for literal braces
+fn render(template: &str, mut field: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let brace = &rest[i..i + 1];
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            out.push_str(brace);
+            rest = after;
+        } else if brace == "{"
+            && let Some(end) = rest.find('}')
+        {
+            out.push_str(&field(&rest[..end])?);
+            rest = &rest[end + 1..];
+        } else {
+            bail!("unmatched '{}'", brace);
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Playback as it was when a change happened
+struct Snapshot {
+    state: PlaybackState,
+    track: Option<TrackInfo>,
+    elapsed: Duration,
+    at: SystemTime,
+}
+
+/// Keeps the now-playing files up to date
+pub struct NowPlaying {
+    updates: Option<Sender<Snapshot>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl NowPlaying {
+    /// Start writing the configured files, or `None` when there are none
+    pub fn start(outputs: &[NowPlayingConfig]) -> Option<Self> {
+        if outputs.is_empty() {
+            return None;
+        }
+        let outputs = outputs.to_vec();
+        let (updates, receiver) = mpsc::channel();
+        let worker = thread::Builder::new()
+            .name("now-playing".to_string())
+            .spawn(move || write_all(&outputs, receiver));
+        match worker {
+            Ok(worker) => Some(Self {
+                updates: Some(updates),
+                worker: Some(worker),
+            }),
+            Err(e) => {
+                warn!("Failed to start writing now-playing files: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Rewrite the files for the player's current state
+    pub fn update(&self, player: &MusicPlayer) {
+        let state = player.state();
+        let snapshot = Snapshot {
+            state,
+            track: player
+                .current_track()
+                .filter(|_| state != PlaybackState::Stopped)
+                .map(TrackInfo::from),
+            elapsed: player.position(),
+            at: SystemTime::now(),
+        };
+        if let Some(updates) = &self.updates {
+            let _ = updates.send(snapshot);
+        }
+    }
+}
+
+impl Drop for NowPlaying {
+    /// Wait for the last update to be written, so stopping clears the files before exit
+    fn drop(&mut self) {
+        self.updates = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Write each snapshot to every output until the player goes away
+fn write_all(outputs: &[NowPlayingConfig], receiver: Receiver<Snapshot>) {
+    while let Ok(mut snapshot) = receiver.recv() {
+        // Only the latest state matters when changes arrive faster than they are written
+        while let Ok(next) = receiver.try_recv() {
+            snapshot = next;
+        }
+
+        let cover = snapshot
+            .track
+            .as_ref()
+            .and_then(|track| artwork::cover_file(&track.path));
+        for output in outputs {
+            let contents = match output.format {
+                OutputFormat::Text => text(&output.template, &snapshot, cover.as_deref()),
+                OutputFormat::Json => json(&snapshot, cover.as_deref()),
+            };
+            if let Err(e) = contents.and_then(|contents| replace(&output.path, &contents)) {
+                warn!(
+                    "Failed to write now-playing file {}: {:#}",
+                    output.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// The template filled in, or nothing once playback has stopped
+fn text(template: &str, snapshot: &Snapshot, cover: Option<&Path>) -> Result<String> {
+    let Some(track) = &snapshot.track else {
+        return Ok(String::new());
+    };
+    render(template, |name| {
+        Ok(match name {
+            "title" => track.title.clone(),
+            "artist" => track.artist.clone().unwrap_or_default(),
+            "album" => track.album.clone().unwrap_or_default(),
+            "elapsed" => format_time(snapshot.elapsed),
+            "duration" => track
+                .duration
+                .map(|d| format_time(Duration::from_secs_f64(d)))
+                .unwrap_or_default(),
+            "cover" => cover.map(|c| c.display().to_string()).unwrap_or_default(),
+            "state" => snapshot.state.to_string(),
+            _ => bail!("unknown placeholder {{{}}}", name),
+        })
+    })
+}
+
+#[derive(Serialize)]
+struct Fields<'a> {
+    state: PlaybackState,
+    title: Option<&'a str>,
+    artist: Option<&'a str>,
+    album: Option<&'a str>,
+    path: Option<&'a Path>,
+    /// Position when the file was written, in seconds
+    elapsed: f64,
+    duration: Option<f64>,
+    cover: Option<&'a Path>,
+    /// Seconds since the Unix epoch when the file was written, to extrapolate `elapsed`
+    updated: f64,
+}
+
+fn json(snapshot: &Snapshot, cover: Option<&Path>) -> Result<String> {
+    let track = snapshot.track.as_ref();
+    let fields = Fields {
+        state: snapshot.state,
+        title: track.map(|t| t.title.as_str()),
+        artist: track.and_then(|t| t.artist.as_deref()),
+        album: track.and_then(|t| t.album.as_deref()),
+        path: track.map(|t| t.path.as_path()),
+        elapsed: snapshot.elapsed.as_secs_f64(),
+        duration: track.and_then(|t| t.duration),
+        cover,
+        updated: snapshot
+            .at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0.0, |d| d.as_secs_f64()),
+    };
+    Ok(serde_json::to_string_pretty(&fields)? + "\n")
+}
+
+/// Write a file under a temporary name and rename it over `path`
+fn replace(path: &Path, contents: &str) -> Result<()> {
+    let name = path.file_name().context("Not a file path")?;
+    let mut temporary = PathBuf::from(".");
+    temporary.as_mut_os_string().push(name);
+    temporary.as_mut_os_string().push(".tmp");
+    let temporary = path.with_file_name(temporary);
+
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+    {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&temporary, contents)
+        .with_context(|| format!("Failed to write {}", temporary.display()))?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    fn playing() -> Snapshot {
+        Snapshot {
+            state: PlaybackState::Playing,
+            track: Some(TrackInfo {
+                path: PathBuf::from("/music/Low/Sunflower.flac"),
+                title: "Sunflower".to_string(),
+                artist: Some("Low".to_string()),
+                album: None,
+                track_number: Some(1),
+                duration: Some(245.0),
+            }),
+            elapsed: Duration::from_secs(75),
+            at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_000),
+        }
+    }
+
+    fn stopped() -> Snapshot {
+        Snapshot {
+            state: PlaybackState::Stopped,
+            track: None,
+            elapsed: Duration::ZERO,
+            at: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    fn upper(name: &str) -> Result<String> {
+        Ok(name.to_uppercase())
+    }
+
+    #[test]
+    fn render_fills_placeholders_and_unescapes_braces() {
+        assert_eq!(
+            render("{title} - {artist}", upper).unwrap(),
+            "TITLE - ARTIST"
+        );
+        assert_eq!(
+            render("{{title}} {{{title}}}", upper).unwrap(),
+            "{title} {TITLE}"
+        );
+        assert_eq!(render("no fields", upper).unwrap(), "no fields");
+        assert_eq!(render("", upper).unwrap(), "");
+        assert_eq!(
+            render("{title} }", upper).unwrap_err().to_string(),
+            "unmatched '}'"
+        );
+        assert_eq!(
+            render("{title", upper).unwrap_err().to_string(),
+            "unmatched '{'"
+        );
+    }
+
+    #[test]
+    fn check_template_rejects_unknown_placeholders() {
+        check_template("{artist} - {title} [{elapsed}/{duration}] {state} {cover} {album}")
+            .unwrap();
+        check_template("{{year}}").unwrap();
+        assert_eq!(
+            check_template("{title} ({year})").unwrap_err().to_string(),
+            "unknown placeholder {year}"
+        );
+        assert!(check_template("{title}}").is_err());
+    }
+
+    #[test]
+    fn text_fills_in_the_track() {
+        let cover = Path::new("/music/Low/cover.jpg");
+        assert_eq!(
+            text(
+                "{artist} - {title} ({album}) {elapsed}/{duration} {state} {cover}",
+                &playing(),
+                Some(cover)
+            )
+            .unwrap(),
+            "Low - Sunflower () 1:15/4:05 Playing /music/Low/cover.jpg"
+        );
+        assert_eq!(text("{title} {cover}", &stopped(), None).unwrap(), "");
+    }
+
+    #[test]
+    fn json_has_every_field() {
+        let fields: Value = serde_json::from_str(&json(&playing(), None).unwrap()).unwrap();
+        assert_eq!(
+            fields,
+            json!({
+                "state": "playing",
+                "title": "Sunflower",
+                "artist": "Low",
+                "album": null,
+                "path": "/music/Low/Sunflower.flac",
+                "elapsed": 75.0,
+                "duration": 245.0,
+                "cover": null,
+                "updated": 1000.0,
+            })
+        );
+
+        let fields: Value = serde_json::from_str(&json(&stopped(), None).unwrap()).unwrap();
+        assert_eq!(fields["state"], "stopped");
+        for field in ["title", "artist", "album", "path", "duration", "cover"] {
+            assert_eq!(fields[field], Value::Null, "{}", field);
+        }
+    }
+
+    #[test]
+    fn replace_creates_the_directory_and_leaves_no_temporary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("obs").join("now-playing.txt");
+
+        replace(&path, "Low - Sunflower").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Low - Sunflower");
+        replace(&path, "").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        let names: Vec<_> = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["now-playing.txt"]);
+
+        // The temporary file is hidden, next to the target
+        fs::create_dir(dir.path().join(".now-playing.txt.tmp")).unwrap();
+        assert!(replace(&dir.path().join("now-playing.txt"), "x").is_err());
+    }
+}
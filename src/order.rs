@@ -0,0 +1,264 @@
+//! Which track plays next, following the shuffle and repeat modes
+//!
+//! Without shuffle the list plays in order. With shuffle the player walks a random permutation
+//! of the list that starts at the track playing when it was made; the permutation is rebuilt
+//! whenever the list changes, and reshuffled each time round with repeat all.
+
+use rand::seq::SliceRandom;
+
+use crate::RepeatMode;
+
+/// Why playback is moving on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// The current track played to its end
+    Finished,
+    /// The user asked for the next track
+    Next,
+    /// The user asked for the previous track
+    Previous,
+}
+
+/// Play order of the track list
+#[derive(Debug, Default)]
+pub struct PlayOrder {
+    /// Track indexes in shuffled order, empty while shuffle is off
+    shuffled: Vec<usize>,
+    /// List version the shuffled order was made for
+    version: u32,
+}
+
+impl PlayOrder {
+    /// The track to play after `current` in a list of `len` tracks, or `None` to stop
+    ///
+    /// Repeat one only replays a track that finished; asking for the next or previous track
+    /// moves on as with repeat all. With repeat off, playback stops after the last track, and
+    /// going back from the first one restarts it.
+    pub fn step(
+        &mut self,
+        current: usize,
+        len: usize,
+        version: u32,
+        shuffle: bool,
+        repeat: RepeatMode,
+        step: Step,
+    ) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        let current = current.min(len - 1);
+        if step == Step::Finished && repeat == RepeatMode::One {
+            return Some(current);
+        }
+        let wrap = repeat != RepeatMode::None;
+
+        if !shuffle {
+            self.shuffled.clear();
+            return match step {
+                Step::Finished | Step::Next if current + 1 < len => Some(current + 1),
+                Step::Finished | Step::Next => wrap.then_some(0),
+                Step::Previous if current > 0 => Some(current - 1),
+                Step::Previous => Some(if wrap { len - 1 } else { 0 }),
+            };
+        }
+
+        let position = match self.position(current, len, version) {
+            Some(position) => position,
+            None => {
+                self.reshuffle(len, Some(current), version);
+                0
+            }
+        };
+        match step {
+            Step::Finished | Step::Next if position + 1 < len => Some(self.shuffled[position + 1]),
+            Step::Finished | Step::Next if wrap => {
+                // A new round in a new order, not starting with the track that just played
+                self.reshuffle(len, None, version);
+                if len > 1 && self.shuffled[0] == current {
+                    self.shuffled.swap(0, len - 1);
+                }
+                Some(self.shuffled[0])
+            }
+            Step::Finished | Step::Next => None,
+            Step::Previous if position > 0 => Some(self.shuffled[position - 1]),
+            Step::Previous if wrap => self.shuffled.last().copied(),
+            Step::Previous => Some(current),
+        }
+    }
+
+    /// The track to start playing from, the first in play order
+    pub fn first(&mut self, len: usize, version: u32, shuffle: bool) -> usize {
+        if !shuffle || len == 0 {
+            self.shuffled.clear();
+            return 0;
+        }
+        self.reshuffle(len, None, version);
+        self.shuffled[0]
+    }
+
+    /// Where `current` is in the shuffled order, if that order is still valid
+    fn position(&self, current: usize, len: usize, version: u32) -> Option<usize> {
+        if self.shuffled.len() != len || self.version != version {
+            return None;
+        }
+        self.shuffled.iter().position(|&index| index == current)
+    }
+
+    /// Make a new shuffled order, starting with `first` when given
+    fn reshuffle(&mut self, len: usize, first: Option<usize>, version: u32) {
+        self.shuffled = (0..len).collect();
+        self.shuffled.shuffle(&mut rand::thread_rng());
+        if let Some(first) = first
+            && let Some(position) = self.shuffled.iter().position(|&index| index == first)
+        {
+            self.shuffled.swap(0, position);
+        }
+        self.version = version;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Follow `steps` steps from track 0, collecting the tracks played
+    fn walk(len: usize, shuffle: bool, repeat: RepeatMode, step: Step, steps: usize) -> Vec<usize> {
+        let mut order = PlayOrder::default();
+        let mut current = 0;
+        let mut played = vec![current];
+        for _ in 0..steps {
+            match order.step(current, len, 0, shuffle, repeat, step) {
+                Some(next) => current = next,
+                None => break,
+            }
+            played.push(current);
+        }
+        played
+    }
+
+    #[test]
+    fn repeat_off_stops_after_the_last_track() {
+        assert_eq!(
+            walk(3, false, RepeatMode::None, Step::Finished, 10),
+            [0, 1, 2]
+        );
+        assert_eq!(walk(3, false, RepeatMode::None, Step::Next, 10), [0, 1, 2]);
+    }
+
+    #[test]
+    fn repeat_off_restarts_the_first_track_going_back() {
+        let mut order = PlayOrder::default();
+        assert_eq!(
+            order.step(0, 3, 0, false, RepeatMode::None, Step::Previous),
+            Some(0)
+        );
+        assert_eq!(
+            order.step(2, 3, 0, false, RepeatMode::None, Step::Previous),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn repeat_all_wraps_both_ways() {
+        assert_eq!(
+            walk(3, false, RepeatMode::All, Step::Finished, 4),
+            [0, 1, 2, 0, 1]
+        );
+        assert_eq!(
+            walk(3, false, RepeatMode::All, Step::Previous, 3),
+            [0, 2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn repeat_one_replays_a_finished_track() {
+        assert_eq!(
+            walk(3, false, RepeatMode::One, Step::Finished, 3),
+            [0, 0, 0, 0]
+        );
+        assert_eq!(walk(3, true, RepeatMode::One, Step::Finished, 2), [0, 0, 0]);
+    }
+
+    #[test]
+    fn repeat_one_still_moves_on_when_asked() {
+        assert_eq!(walk(2, false, RepeatMode::One, Step::Next, 2), [0, 1, 0]);
+        assert_eq!(walk(2, false, RepeatMode::One, Step::Previous, 1), [0, 1]);
+    }
+
+    #[test]
+    fn shuffle_plays_every_track_once_then_stops() {
+        let played = walk(20, true, RepeatMode::None, Step::Finished, 100);
+        let mut sorted = played.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+        // Twenty tracks in list order by chance is vanishingly unlikely
+        assert_ne!(played, sorted);
+    }
+
+    #[test]
+    fn shuffle_with_repeat_all_starts_a_new_round() {
+        let played = walk(10, true, RepeatMode::All, Step::Finished, 19);
+        assert_eq!(played.len(), 20);
+        for round in played.chunks(10) {
+            let mut sorted = round.to_vec();
+            sorted.sort();
+            assert_eq!(sorted, (0..10).collect::<Vec<_>>());
+        }
+        // The new round doesn't repeat the track that just played
+        assert_ne!(played[9], played[10]);
+    }
+
+    #[test]
+    fn shuffle_goes_back_through_the_tracks_played() {
+        let mut order = PlayOrder::default();
+        let mut played = vec![3];
+        for _ in 0..4 {
+            let current = *played.last().unwrap();
+            let next = order.step(current, 8, 0, true, RepeatMode::None, Step::Next);
+            played.push(next.unwrap());
+        }
+
+        let mut current = *played.last().unwrap();
+        let mut back = vec![current];
+        for _ in 0..4 {
+            current = order
+                .step(current, 8, 0, true, RepeatMode::None, Step::Previous)
+                .unwrap();
+            back.push(current);
+        }
+        back.reverse();
+        assert_eq!(back, played);
+    }
+
+    #[test]
+    fn shuffle_order_is_rebuilt_when_the_list_changes() {
+        let mut order = PlayOrder::default();
+        order.step(0, 5, 0, true, RepeatMode::None, Step::Next);
+        let before = order.shuffled.clone();
+        order.step(0, 6, 1, true, RepeatMode::None, Step::Next);
+        assert_eq!(order.shuffled.len(), 6);
+        assert_eq!(order.version, 1);
+        assert_eq!(order.shuffled[0], 0);
+        assert_ne!(order.shuffled, before);
+    }
+
+    #[test]
+    fn first_track_follows_the_shuffle_order() {
+        let mut order = PlayOrder::default();
+        assert_eq!(order.first(5, 0, false), 0);
+        let first = order.first(5, 0, true);
+        assert_eq!(order.shuffled[0], first);
+        assert_eq!(order.position(first, 5, 0), Some(0));
+    }
+
+    #[test]
+    fn turning_shuffle_off_returns_to_list_order() {
+        let mut order = PlayOrder::default();
+        order.step(0, 5, 0, true, RepeatMode::None, Step::Next);
+        assert_eq!(
+            order.step(2, 5, 0, false, RepeatMode::None, Step::Next),
+            Some(3)
+        );
+        assert!(order.shuffled.is_empty());
+    }
+}
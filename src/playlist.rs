@@ -0,0 +1,61 @@
+use std::fs;
+use std::io::Write;
+use std::path::{self, Path};
+
+use anyhow::{Context, Result};
+
+use crate::Track;
+
+/// Write tracks to an extended M3U playlist, replacing the file
+///
+/// Paths are written absolute so the playlist works wherever it is saved.
+pub fn save<'a>(path: &Path, tracks: impl IntoIterator<Item = &'a Track>) -> Result<usize> {
+    let (entries, count) = entries(tracks);
+    fs::write(path, format!("#EXTM3U\n{}", entries))
+        .with_context(|| format!("Failed to write playlist: {}", path.display()))?;
+    Ok(count)
+}
+
+/// Add tracks to the end of an M3U playlist, creating it if needed
+pub fn append<'a>(path: &Path, tracks: impl IntoIterator<Item = &'a Track>) -> Result<usize> {
+    let existing = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(_) if !path.exists() => return save(path, tracks),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read playlist: {}", path.display()));
+        }
+    };
+
+    let (mut entries, count) = entries(tracks);
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        entries.insert(0, '\n');
+    }
+    fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(entries.as_bytes()))
+        .with_context(|| format!("Failed to write playlist: {}", path.display()))?;
+    Ok(count)
+}
+
+/// `#EXTINF` lines and paths for each track, with the number of tracks
+fn entries<'a>(tracks: impl IntoIterator<Item = &'a Track>) -> (String, usize) {
+    let mut data = String::new();
+    let mut count = 0;
+    for track in tracks {
+        let seconds = track.duration.map_or(-1, |d| d.as_secs() as i64);
+        let title = match &track.artist {
+            Some(artist) => format!("{} - {}", artist, track.title),
+            None => track.title.clone(),
+        };
+        let file = path::absolute(&track.path).unwrap_or_else(|_| track.path.clone());
+        data.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            seconds,
+            title,
+            file.display()
+        ));
+        count += 1;
+    }
+    (data, count)
+}
@@ -0,0 +1,297 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::Track;
+
+/// Field the track list is ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Path,
+    Filename,
+    Title,
+    Artist,
+    Duration,
+    DateAdded,
+    PlayCount,
+}
+
+impl SortKey {
+    /// All sort keys in the order they are cycled through
+    pub const ALL: [SortKey; 7] = [
+        SortKey::Path,
+        SortKey::Filename,
+        SortKey::Title,
+        SortKey::Artist,
+        SortKey::Duration,
+        SortKey::DateAdded,
+        SortKey::PlayCount,
+    ];
+
+    /// Get the next sort key, wrapping around
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|k| *k == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortKey::Path => write!(f, "Path"),
+            SortKey::Filename => write!(f, "Filename"),
+            SortKey::Title => write!(f, "Title"),
+            SortKey::Artist => write!(f, "Artist"),
+            SortKey::Duration => write!(f, "Duration"),
+            SortKey::DateAdded => write!(f, "Date Added"),
+            SortKey::PlayCount => write!(f, "Play Count"),
+        }
+    }
+}
+
+/// A sort key together with its direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        Self {
+            key: SortKey::Path,
+            descending: false,
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.key,
+            if self.descending { "↓" } else { "↑" }
+        )
+    }
+}
+
+impl SortOrder {
+    /// Compare two tracks according to this order
+    pub fn compare(&self, a: &Track, b: &Track) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Path => a.path.cmp(&b.path),
+            SortKey::Filename => natural_cmp(&file_name(a), &file_name(b)),
+            SortKey::Title => natural_cmp(&a.title, &b.title),
+            SortKey::Artist => cmp_missing_last(&a.artist, &b.artist, |x, y| natural_cmp(x, y))
+                .then_with(|| cmp_missing_last(&a.album, &b.album, |x, y| natural_cmp(x, y)))
+                .then_with(|| cmp_missing_last(&a.track_number, &b.track_number, Ord::cmp))
+                .then_with(|| natural_cmp(&a.title, &b.title)),
+            SortKey::Duration => cmp_missing_last(&a.duration, &b.duration, Ord::cmp),
+            SortKey::DateAdded => cmp_missing_last(&a.date_added, &b.date_added, Ord::cmp),
+            SortKey::PlayCount => a.play_count.cmp(&b.play_count),
+        };
+
+        let ordering = if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+
+        // Fall back to the path so equal keys keep a stable, predictable order
+        ordering.then_with(|| a.path.cmp(&b.path))
+    }
+
+    /// Sort tracks in place according to this order
+    pub fn sort(&self, tracks: &mut [Track]) {
+        tracks.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+fn file_name(track: &Track) -> String {
+    track
+        .path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Compare optional values, placing missing values after present ones
+fn cmp_missing_last<T>(a: &Option<T>, b: &Option<T>, cmp: impl Fn(&T, &T) -> Ordering) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => cmp(a, b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Case-insensitive comparison that orders runs of digits numerically, so "2" sorts before "10"
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_num = take_digits(&mut a_chars);
+                let y_num = take_digits(&mut b_chars);
+
+                // Compare by magnitude first (ignoring leading zeros), then digit by digit
+                let x_trimmed = x_num.trim_start_matches('0');
+                let y_trimmed = y_num.trim_start_matches('0');
+                let ordering = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.peek().copied().filter(|c| c.is_ascii_digit()) {
+        digits.push(c);
+        chars.next();
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn track(path: &str, artist: Option<&str>, album: Option<&str>, number: Option<u32>) -> Track {
+        Track {
+            path: PathBuf::from(path),
+            title: path.trim_end_matches(".flac").to_string(),
+            artist: artist.map(String::from),
+            album: album.map(String::from),
+            track_number: number,
+            duration: None,
+            date_added: None,
+            play_count: 0,
+            unplayable: None,
+        }
+    }
+
+    fn sorted(mut names: Vec<&str>) -> Vec<&str> {
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn digit_runs_compare_as_numbers() {
+        assert_eq!(
+            sorted(vec!["Track 10", "Track 2", "Track 1", "Track 100"]),
+            ["Track 1", "Track 2", "Track 10", "Track 100"]
+        );
+        assert_eq!(
+            sorted(vec![
+                "disc2-track1",
+                "disc10-track1",
+                "disc2-track10",
+                "disc2-track9"
+            ]),
+            [
+                "disc2-track1",
+                "disc2-track9",
+                "disc2-track10",
+                "disc10-track1"
+            ]
+        );
+        assert_eq!(natural_cmp("9", "10"), Ordering::Less);
+        // Longer than any integer type
+        assert_eq!(
+            natural_cmp("99999999999999999999999", "100000000000000000000000"),
+            Ordering::Less
+        );
+        assert_eq!(natural_cmp("a1", "a"), Ordering::Greater);
+        assert_eq!(natural_cmp("1a", "a"), Ordering::Less);
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(natural_cmp("007", "7"), Ordering::Less);
+        assert_eq!(natural_cmp("007", "8"), Ordering::Less);
+        assert_eq!(natural_cmp("010", "9"), Ordering::Greater);
+        assert_eq!(
+            sorted(vec!["02 b", "1 c", "01 a", "2 a"]),
+            ["01 a", "1 c", "2 a", "02 b"]
+        );
+    }
+
+    #[test]
+    fn case_only_breaks_ties() {
+        assert_eq!(
+            sorted(vec!["beta", "Alpha", "alpha", "Beta", "gamma"]),
+            ["Alpha", "alpha", "Beta", "beta", "gamma"]
+        );
+        assert_eq!(natural_cmp("ABC", "abd"), Ordering::Less);
+        assert_eq!(natural_cmp("abc", "abc"), Ordering::Equal);
+    }
+
+    #[test]
+    fn non_ascii_text_is_compared_by_character() {
+        assert_eq!(natural_cmp("Ärger", "ärger"), Ordering::Less);
+        assert_eq!(natural_cmp("ÄRGER 2", "ärger 10"), Ordering::Less);
+        assert_eq!(natural_cmp("Ωmega", "ωmega"), Ordering::Less);
+        assert_eq!(natural_cmp("日本 2", "日本 10"), Ordering::Less);
+        // Only ASCII digits form numbers
+        assert_eq!(natural_cmp("٣", "٢"), Ordering::Greater);
+        assert_eq!(natural_cmp("x٢", "x10"), Ordering::Greater);
+    }
+
+    #[test]
+    fn artist_order_falls_back_to_album_track_and_title() {
+        let mut tracks = vec![
+            track("z.flac", None, None, None),
+            track("b-2.flac", Some("Low"), Some("Secret Name"), Some(2)),
+            track("c.flac", Some("Low"), None, Some(1)),
+            track("b-10.flac", Some("Low"), Some("Secret Name"), Some(10)),
+            track("a-1.flac", Some("Low"), Some("Long Division"), Some(1)),
+            // Artists spelled with different case are kept apart
+            track("y.flac", Some("low"), Some("Secret Name"), None),
+            track("x.flac", Some("Low"), Some("Secret Name"), None),
+            track("d.flac", Some("Cat Power"), Some("Moon Pix"), Some(5)),
+        ];
+        SortOrder {
+            key: SortKey::Artist,
+            descending: false,
+        }
+        .sort(&mut tracks);
+        let paths: Vec<_> = tracks.iter().map(|t| t.path.to_str().unwrap()).collect();
+        assert_eq!(
+            paths,
+            [
+                "d.flac",
+                "a-1.flac",
+                "b-2.flac",
+                "b-10.flac",
+                "x.flac",
+                "c.flac",
+                "y.flac",
+                "z.flac"
+            ]
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::warn;
+
+/// File name of the play count store inside the data directory
+const STATS_FILE: &str = "play_counts.json";
+
+/// Persistent per-track play counts, keyed by file path
+#[derive(Debug, Default)]
+pub struct PlayStats {
+    file: Option<PathBuf>,
+    counts: HashMap<PathBuf, u32>,
+}
+
+impl PlayStats {
+    /// Load play counts from the XDG data directory, starting empty if none exist
+    pub fn load() -> Self {
+        let file = dirs::data_dir().map(|dir| dir.join("music_player").join(STATS_FILE));
+
+        let counts = file
+            .as_ref()
+            .filter(|f| f.exists())
+            .and_then(|f| match Self::read(f) {
+                Ok(counts) => Some(counts),
+                Err(e) => {
+                    warn!("Failed to read play counts from {}: {}", f.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self { file, counts }
+    }
+
+    fn read(file: &Path) -> Result<HashMap<PathBuf, u32>> {
+        let data = fs::read_to_string(file)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// Get the play count for a track
+    pub fn count<P: AsRef<Path>>(&self, path: P) -> u32 {
+        self.counts.get(path.as_ref()).copied().unwrap_or(0)
+    }
+
+    /// Increment the play count for a track and persist it, returning the new count
+    pub fn record_play<P: AsRef<Path>>(&mut self, path: P) -> u32 {
+        let count = self.counts.entry(path.as_ref().to_path_buf()).or_insert(0);
+        *count += 1;
+        let count = *count;
+
+        if let Err(e) = self.save() {
+            warn!("Failed to save play counts: {}", e);
+        }
+
+        count
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+
+        let data = serde_json::to_string_pretty(&self.counts)?;
+        fs::write(file, data).with_context(|| format!("Failed to write: {}", file.display()))
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rodio::Source;
+use rodio::source::SeekError;
+
+/// Number of samples kept for analysis (all channels interleaved)
+const TAP_CAPACITY: usize = 16384;
+
+/// Lock-free ring buffer of the most recently decoded samples
+///
+/// The audio thread writes through [`Tap`]; the UI thread takes snapshots without blocking it.
+pub struct SampleTap {
+    samples: Box<[AtomicU32]>,
+    write: AtomicUsize,
+    channels: AtomicU16,
+    sample_rate: AtomicU32,
+    enabled: AtomicBool,
+}
+
+impl SampleTap {
+    pub fn new() -> Self {
+        Self {
+            samples: (0..TAP_CAPACITY).map(|_| AtomicU32::new(0)).collect(),
+            write: AtomicUsize::new(0),
+            channels: AtomicU16::new(2),
+            sample_rate: AtomicU32::new(44100),
+            enabled: AtomicBool::new(false),
+        }
+    }
+
+    /// Start or stop copying samples; playback is unaffected either way
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Forget all buffered samples, e.g. after playback stops
+    pub fn clear(&self) {
+        for sample in self.samples.iter() {
+            sample.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the sample rate of the stream being tapped
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    fn set_format(&self, channels: u16, sample_rate: u32) {
+        self.channels.store(channels.max(1), Ordering::Relaxed);
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    fn push(&self, sample: f32) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let index = self.write.fetch_add(1, Ordering::Relaxed) % TAP_CAPACITY;
+        self.samples[index].store(sample.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Copy the latest `frames` frames into `out`, mixed down to mono
+    pub fn snapshot(&self, frames: usize, out: &mut Vec<f32>) {
+        let channels = usize::from(self.channels.load(Ordering::Relaxed));
+        let frames = frames.min(TAP_CAPACITY / channels);
+        let end = self.write.load(Ordering::Relaxed);
+        // Align to a frame boundary so channels aren't mixed up
+        let end = end - end % channels;
+        let start = end.wrapping_sub(frames * channels);
+
+        out.clear();
+        for frame in 0..frames {
+            let mut sum = 0.0;
+            for channel in 0..channels {
+                let index = start.wrapping_add(frame * channels + channel) % TAP_CAPACITY;
+                sum += f32::from_bits(self.samples[index].load(Ordering::Relaxed));
+            }
+            out.push(sum / channels as f32);
+        }
+    }
+}
+
+/// Source wrapper that copies every sample it yields into a [`SampleTap`]
+pub struct Tap<S> {
+    inner: S,
+    tap: Arc<SampleTap>,
+}
+
+impl<S: Source<Item = f32>> Tap<S> {
+    pub fn new(inner: S, tap: Arc<SampleTap>) -> Self {
+        tap.set_format(inner.channels(), inner.sample_rate());
+        Self { inner, tap }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Tap<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.inner.next()?;
+        self.tap.push(sample);
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Tap<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::{Block, Borders};
+use serde::Deserialize;
+
+use crate::config::{self, Config};
+use crate::messages::Level;
+
+/// Names of the themes compiled into the player
+pub const BUILTIN_THEMES: &[&str] = &["default", "high-contrast", "monochrome"];
+
+/// Styles for every part of the terminal UI
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    /// Text and background of every pane
+    pub base: Style,
+    /// Pane borders
+    pub border: Style,
+    /// Pane titles
+    pub title: Style,
+    /// Cursor row in the track list
+    pub highlight: Style,
+    /// Playing track in the track list
+    pub playing: Style,
+    /// Tracks selected for a batch operation, under the playing and cursor styles
+    pub selected: Style,
+    /// Reason shown next to tracks that failed to play
+    pub unplayable: Style,
+    /// Filled portion of the progress bar
+    pub progress: Style,
+    /// Unplayed part of the waveform seekbar
+    pub waveform: Style,
+    /// Played part of the waveform seekbar
+    pub waveform_played: Style,
+    /// Spectrum visualizer bars
+    pub spectrum: Style,
+    /// Spectrum visualizer peak markers
+    pub spectrum_peak: Style,
+    /// Line being sung in the lyrics pane
+    pub lyrics_current: Style,
+    /// Key names in the controls bar and help
+    pub key: Style,
+    /// Mode values in the controls bar
+    pub value: Style,
+    /// Toggles that are switched on
+    pub enabled: Style,
+    /// Toggles that are switched off
+    pub disabled: Style,
+    /// Informational status messages
+    pub info: Style,
+    /// Warning status messages
+    pub warning: Style,
+    /// Error status messages
+    pub error: Style,
+    /// Popup windows such as the help overlay
+    pub popup: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            base: Style::default(),
+            border: Style::default(),
+            title: Style::default(),
+            highlight: Style::default().bg(Color::DarkGray),
+            playing: Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+            selected: Style::default().bg(Color::Blue),
+            unplayable: Style::default().fg(Color::Red),
+            progress: Style::default().fg(Color::Green),
+            waveform: Style::default().fg(Color::DarkGray),
+            waveform_played: Style::default().fg(Color::Green),
+            spectrum: Style::default().fg(Color::Cyan),
+            spectrum_peak: Style::default().fg(Color::Yellow),
+            lyrics_current: Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+            key: Style::default().fg(Color::Yellow),
+            value: Style::default().fg(Color::Cyan),
+            enabled: Style::default().fg(Color::Green),
+            disabled: Style::default().fg(Color::Red),
+            info: Style::default().fg(Color::Cyan),
+            warning: Style::default().fg(Color::Yellow),
+            error: Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            popup: Style::default(),
+        }
+    }
+}
+
+/// A style in a theme file: either a plain color or a table of attributes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StyleSpec {
+    Color(String),
+    Style {
+        fg: Option<String>,
+        bg: Option<String>,
+        #[serde(default)]
+        modifiers: Vec<String>,
+    },
+}
+
+/// A user theme file in the themes directory
+#[derive(Debug, Deserialize)]
+struct ThemeFile {
+    /// Built-in theme the file starts from; not `base`, which is an element
+    #[serde(default)]
+    extends: Option<String>,
+    #[serde(flatten)]
+    styles: BTreeMap<String, StyleSpec>,
+}
+
+impl Theme {
+    /// Get a built-in theme by name
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "high-contrast" => Some(Self::high_contrast()),
+            "monochrome" => Some(Self::monochrome()),
+            _ => None,
+        }
+    }
+
+    fn high_contrast() -> Self {
+        let base = Style::default().fg(Color::White).bg(Color::Black);
+        Self {
+            base,
+            border: base,
+            title: base.add_modifier(Modifier::BOLD),
+            highlight: Style::default()
+                .fg(Color::Black)
+                .bg(Color::LightYellow)
+                .add_modifier(Modifier::BOLD),
+            playing: Style::default()
+                .fg(Color::LightYellow)
+                .add_modifier(Modifier::BOLD),
+            selected: Style::default()
+                .bg(Color::Blue)
+                .add_modifier(Modifier::UNDERLINED),
+            unplayable: Style::default().fg(Color::LightRed),
+            progress: Style::default().fg(Color::LightGreen).bg(Color::Black),
+            waveform: Style::default().fg(Color::Gray),
+            waveform_played: Style::default()
+                .fg(Color::LightGreen)
+                .add_modifier(Modifier::BOLD),
+            spectrum: Style::default().fg(Color::LightCyan),
+            spectrum_peak: Style::default().fg(Color::White),
+            lyrics_current: Style::default()
+                .fg(Color::Black)
+                .bg(Color::LightYellow)
+                .add_modifier(Modifier::BOLD),
+            key: Style::default()
+                .fg(Color::LightYellow)
+                .add_modifier(Modifier::BOLD),
+            value: Style::default().fg(Color::LightCyan),
+            enabled: Style::default()
+                .fg(Color::LightGreen)
+                .add_modifier(Modifier::BOLD),
+            disabled: Style::default()
+                .fg(Color::LightRed)
+                .add_modifier(Modifier::BOLD),
+            info: Style::default().fg(Color::LightCyan),
+            warning: Style::default()
+                .fg(Color::LightYellow)
+                .add_modifier(Modifier::BOLD),
+            error: Style::default()
+                .fg(Color::White)
+                .bg(Color::Red)
+                .add_modifier(Modifier::BOLD),
+            popup: base,
+        }
+    }
+
+    /// A theme that relies only on text attributes, for `NO_COLOR` and monochrome terminals
+    fn monochrome() -> Self {
+        Self {
+            base: Style::default(),
+            border: Style::default(),
+            title: Style::default().add_modifier(Modifier::BOLD),
+            highlight: Style::default().add_modifier(Modifier::REVERSED),
+            playing: Style::default().add_modifier(Modifier::BOLD),
+            selected: Style::default().add_modifier(Modifier::UNDERLINED),
+            unplayable: Style::default().add_modifier(Modifier::DIM | Modifier::ITALIC),
+            progress: Style::default(),
+            waveform: Style::default().add_modifier(Modifier::DIM),
+            waveform_played: Style::default().add_modifier(Modifier::BOLD),
+            spectrum: Style::default(),
+            spectrum_peak: Style::default().add_modifier(Modifier::BOLD),
+            lyrics_current: Style::default().add_modifier(Modifier::REVERSED),
+            key: Style::default().add_modifier(Modifier::BOLD),
+            value: Style::default(),
+            enabled: Style::default().add_modifier(Modifier::BOLD),
+            disabled: Style::default().add_modifier(Modifier::DIM),
+            info: Style::default(),
+            warning: Style::default().add_modifier(Modifier::BOLD),
+            error: Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            popup: Style::default(),
+        }
+    }
+
+    /// Resolve the theme named in the config and apply its `[theme]` overrides
+    ///
+    /// Without an explicit theme, a non-empty `NO_COLOR` selects the monochrome theme.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let name = match &config.ui.theme {
+            Some(name) => name.clone(),
+            None if env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) => {
+                "monochrome".to_string()
+            }
+            None => "default".to_string(),
+        };
+
+        let mut theme = Self::load(&name)?;
+        theme
+            .apply(&config.theme)
+            .context("Invalid [theme] overrides in config file")?;
+        Ok(theme)
+    }
+
+    /// Load a built-in theme, a theme from the themes directory, or a theme file by path
+    fn load(name: &str) -> Result<Self> {
+        if let Some(theme) = Self::builtin(name) {
+            return Ok(theme);
+        }
+
+        let path = if name.contains('/') || name.ends_with(".toml") {
+            config::expand_home(name.as_ref())
+        } else {
+            match themes_dir() {
+                Some(dir) => dir.join(format!("{}.toml", name)),
+                None => bail!("Unknown theme '{}'", name),
+            }
+        };
+
+        if !path.exists() {
+            bail!(
+                "Unknown theme '{}' (built-in themes: {}; no file at {})",
+                name,
+                BUILTIN_THEMES.join(", "),
+                path.display()
+            );
+        }
+
+        let data = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read theme file: {}", path.display()))?;
+        let file: ThemeFile = toml::from_str(&data)
+            .with_context(|| format!("Invalid theme file: {}", path.display()))?;
+
+        let parent = file.extends.as_deref().unwrap_or("default");
+        let mut theme = Self::builtin(parent).with_context(|| {
+            format!(
+                "Invalid theme file: {}: unknown theme '{}' to extend",
+                path.display(),
+                parent
+            )
+        })?;
+        theme
+            .apply(&file.styles)
+            .with_context(|| format!("Invalid theme file: {}", path.display()))?;
+
+        Ok(theme)
+    }
+
+    /// Override styles by element name, reporting every problem at once
+    fn apply(&mut self, styles: &BTreeMap<String, StyleSpec>) -> Result<()> {
+        let mut errors = Vec::new();
+
+        for (element, spec) in styles {
+            let Some(target) = self.element_mut(element) else {
+                errors.push(format!("{}: unknown theme element", element));
+                continue;
+            };
+
+            match spec.to_style(element == "highlight") {
+                Ok(style) => *target = style,
+                Err(e) => errors.push(format!("{}: {}", element, e)),
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    fn element_mut(&mut self, element: &str) -> Option<&mut Style> {
+        Some(match element {
+            "base" => &mut self.base,
+            "border" => &mut self.border,
+            "title" => &mut self.title,
+            "highlight" => &mut self.highlight,
+            "playing" => &mut self.playing,
+            "selected" => &mut self.selected,
+            "unplayable" => &mut self.unplayable,
+            "progress" => &mut self.progress,
+            "waveform" => &mut self.waveform,
+            "waveform_played" => &mut self.waveform_played,
+            "spectrum" => &mut self.spectrum,
+            "spectrum_peak" => &mut self.spectrum_peak,
+            "lyrics_current" => &mut self.lyrics_current,
+            "key" => &mut self.key,
+            "value" => &mut self.value,
+            "enabled" => &mut self.enabled,
+            "disabled" => &mut self.disabled,
+            "info" => &mut self.info,
+            "warning" => &mut self.warning,
+            "error" => &mut self.error,
+            "popup" => &mut self.popup,
+            _ => return None,
+        })
+    }
+
+    /// Style for a status message of the given level
+    pub fn message(&self, level: Level) -> Style {
+        match level {
+            Level::Info => self.info,
+            Level::Warn => self.warning,
+            Level::Error => self.error,
+        }
+    }
+
+    /// A bordered pane styled with this theme
+    pub fn block<'a>(&self, title: impl Into<ratatui::text::Line<'a>>) -> Block<'a> {
+        Block::default()
+            .borders(Borders::ALL)
+            .title(title)
+            .style(self.base)
+            .border_style(self.border)
+            .title_style(self.title)
+    }
+}
+
+impl StyleSpec {
+    /// Build a style; a plain color sets the background for backgrounds-first elements
+    fn to_style(&self, color_is_bg: bool) -> Result<Style> {
+        match self {
+            StyleSpec::Color(color) => {
+                let color = parse_color(color)?;
+                Ok(if color_is_bg {
+                    Style::default().bg(color)
+                } else {
+                    Style::default().fg(color)
+                })
+            }
+            StyleSpec::Style { fg, bg, modifiers } => {
+                let mut style = Style::default();
+                if let Some(fg) = fg {
+                    style = style.fg(parse_color(fg)?);
+                }
+                if let Some(bg) = bg {
+                    style = style.bg(parse_color(bg)?);
+                }
+                for modifier in modifiers {
+                    style = style.add_modifier(parse_modifier(modifier)?);
+                }
+                Ok(style)
+            }
+        }
+    }
+}
+
+/// Parse a color name, index or `#rrggbb` hex value
+fn parse_color(value: &str) -> Result<Color> {
+    value.parse().map_err(|_| {
+        anyhow::anyhow!(
+            "invalid color '{}' (expected a name like \"yellow\", an index 0-255 or \"#rrggbb\")",
+            value
+        )
+    })
+}
+
+fn parse_modifier(value: &str) -> Result<Modifier> {
+    Ok(match value.to_lowercase().as_str() {
+        "bold" => Modifier::BOLD,
+        "dim" => Modifier::DIM,
+        "italic" => Modifier::ITALIC,
+        "underlined" | "underline" => Modifier::UNDERLINED,
+        "reversed" | "reverse" => Modifier::REVERSED,
+        "crossed_out" | "strikethrough" => Modifier::CROSSED_OUT,
+        "slow_blink" | "blink" => Modifier::SLOW_BLINK,
+        _ => bail!(
+            "invalid modifier '{}' (expected bold, dim, italic, underlined, reversed, crossed_out or blink)",
+            value
+        ),
+    })
+}
+
+/// Directory holding user theme files
+fn themes_dir() -> Option<PathBuf> {
+    Config::default_path().and_then(|p| p.parent().map(|dir| dir.join("themes")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_files_extend_a_builtin_and_style_base() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dusk.toml");
+        fs::write(
+            &path,
+            r##"
+extends = "high-contrast"
+base = { fg = "white", bg = "#102030" }
+playing = "magenta"
+"##,
+        )
+        .unwrap();
+
+        let theme = Theme::load(path.to_str().unwrap()).unwrap();
+        let parent = Theme::high_contrast();
+        assert_eq!(
+            theme.base,
+            Style::default()
+                .fg(Color::White)
+                .bg(Color::Rgb(0x10, 0x20, 0x30))
+        );
+        assert_eq!(theme.playing, Style::default().fg(Color::Magenta));
+        assert_eq!(theme.highlight, parent.highlight);
+        assert_eq!(theme.popup, parent.popup);
+    }
+
+    #[test]
+    fn theme_files_name_a_builtin_to_extend() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bad.toml");
+        fs::write(&path, "extends = \"solarized\"\n").unwrap();
+        let error = format!("{:#}", Theme::load(path.to_str().unwrap()).unwrap_err());
+        assert!(
+            error.contains("unknown theme 'solarized' to extend"),
+            "{}",
+            error
+        );
+
+        fs::write(&path, "base = \"white\"\n").unwrap();
+        let theme = Theme::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(theme.base, Style::default().fg(Color::White));
+        assert_eq!(theme.highlight, Theme::default().highlight);
+    }
+}
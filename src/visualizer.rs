@@ -0,0 +1,191 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Style;
+use ratatui::widgets::Widget;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+use crate::tap::SampleTap;
+
+/// Number of samples per FFT window
+const FFT_SIZE: usize = 2048;
+
+/// Frequency range shown by the bars, in Hz
+const MIN_FREQUENCY: f32 = 30.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+
+/// Level range mapped onto the bar height, in dB
+const FLOOR_DB: f32 = -70.0;
+
+/// How fast bars fall back down, in full heights per second
+const BAR_FALL_RATE: f32 = 1.5;
+
+/// How long peak markers stay put before falling
+const PEAK_HOLD: Duration = Duration::from_millis(600);
+const PEAK_FALL_RATE: f32 = 0.8;
+
+/// Bar heights from empty to a full cell, in eighths
+const BAR_SYMBOLS: [&str; 9] = [" ", "▁", "▂", "▃", "▄", "▅", "▆", "▇", "█"];
+
+/// A peak marker and when it was last pushed up
+#[derive(Clone, Copy)]
+struct Peak {
+    level: f32,
+    set_at: Instant,
+}
+
+/// FFT spectrum analyzer fed from the playback [`SampleTap`]
+pub struct Visualizer {
+    pub visible: bool,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    samples: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    bars: Vec<f32>,
+    peaks: Vec<Peak>,
+    last_update: Instant,
+}
+
+impl Visualizer {
+    pub fn new() -> Self {
+        // Hann window to reduce spectral leakage between bins
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / (FFT_SIZE - 1) as f32).cos())
+            .collect();
+
+        Self {
+            visible: false,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            samples: Vec::with_capacity(FFT_SIZE),
+            spectrum: Vec::with_capacity(FFT_SIZE),
+            bars: Vec::new(),
+            peaks: Vec::new(),
+            last_update: Instant::now(),
+        }
+    }
+
+    /// Analyze the latest samples and update `bar_count` smoothed bars
+    pub fn update(&mut self, tap: &SampleTap, bar_count: usize) {
+        let elapsed = self.last_update.elapsed().as_secs_f32();
+        self.last_update = Instant::now();
+
+        if self.bars.len() != bar_count {
+            self.bars = vec![0.0; bar_count];
+            self.peaks = vec![
+                Peak {
+                    level: 0.0,
+                    set_at: self.last_update,
+                };
+                bar_count
+            ];
+        }
+        if bar_count == 0 {
+            return;
+        }
+
+        tap.snapshot(FFT_SIZE, &mut self.samples);
+        self.spectrum.clear();
+        self.spectrum.extend(
+            self.samples
+                .iter()
+                .zip(&self.window)
+                .map(|(sample, window)| Complex::new(sample * window, 0.0)),
+        );
+        self.spectrum.resize(FFT_SIZE, Complex::new(0.0, 0.0));
+        self.fft.process(&mut self.spectrum);
+
+        let sample_rate = tap.sample_rate().max(1) as f32;
+        let bin_width = sample_rate / FFT_SIZE as f32;
+        let max_frequency = MAX_FREQUENCY.min(sample_rate / 2.0);
+        let ratio = max_frequency / MIN_FREQUENCY;
+
+        for i in 0..bar_count {
+            // Bars cover equal steps on a logarithmic frequency scale
+            let low = MIN_FREQUENCY * ratio.powf(i as f32 / bar_count as f32);
+            let high = MIN_FREQUENCY * ratio.powf((i + 1) as f32 / bar_count as f32);
+            let first = ((low / bin_width) as usize).clamp(1, FFT_SIZE / 2 - 1);
+            let last = ((high / bin_width) as usize).clamp(first, FFT_SIZE / 2 - 1);
+
+            let magnitude = self.spectrum[first..=last]
+                .iter()
+                .map(|c| c.norm())
+                .fold(0.0, f32::max);
+
+            // Normalize against a full-scale sine through the window (gain 0.5)
+            let db = 20.0 * (magnitude / (FFT_SIZE as f32 / 4.0)).max(1e-9).log10();
+            let level = ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0);
+
+            // Rise instantly, fall smoothly
+            let bar = &mut self.bars[i];
+            *bar = level.max(*bar - BAR_FALL_RATE * elapsed);
+
+            let peak = &mut self.peaks[i];
+            if *bar >= peak.level {
+                *peak = Peak {
+                    level: *bar,
+                    set_at: self.last_update,
+                };
+            } else if peak.set_at.elapsed() > PEAK_HOLD {
+                peak.level = (peak.level - PEAK_FALL_RATE * elapsed).max(*bar);
+            }
+        }
+    }
+
+    /// Widget drawing the current bars
+    pub fn spectrum(&self, bar_style: Style, peak_style: Style) -> Spectrum<'_> {
+        Spectrum {
+            bars: &self.bars,
+            peaks: &self.peaks,
+            bar_style,
+            peak_style,
+        }
+    }
+}
+
+/// Vertical bars with peak markers, one bar per terminal column
+pub struct Spectrum<'a> {
+    bars: &'a [f32],
+    peaks: &'a [Peak],
+    bar_style: Style,
+    peak_style: Style,
+}
+
+impl Widget for Spectrum<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let height = usize::from(area.height);
+        if height == 0 {
+            return;
+        }
+
+        for (column, (bar, peak)) in self.bars.iter().zip(self.peaks).enumerate() {
+            let Ok(offset) = u16::try_from(column) else {
+                break;
+            };
+            if offset >= area.width {
+                break;
+            }
+            let x = area.x + offset;
+
+            // Height in eighths of a cell
+            let eighths = (bar * (height * 8) as f32).round() as usize;
+            let peak_row = ((peak.level * height as f32).ceil() as usize).min(height);
+
+            for row in 0..height {
+                let y = area.y + area.height - 1 - row as u16;
+                let filled = eighths.saturating_sub(row * 8).min(8);
+                let cell = &mut buf[(x, y)];
+                if filled > 0 {
+                    cell.set_symbol(BAR_SYMBOLS[filled])
+                        .set_style(self.bar_style);
+                } else if peak_row > 0 && row + 1 == peak_row {
+                    cell.set_symbol("▔").set_style(self.peak_style);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Style;
+use ratatui::widgets::Widget;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Number of min/max pairs kept per track
+const WAVEFORM_BUCKETS: usize = 1024;
+
+/// Frames folded into one intermediate min/max pair while decoding
+const CHUNK_FRAMES: usize = 1024;
+
+/// Magic bytes and version at the start of a cached summary
+const CACHE_MAGIC: &[u8; 4] = b"WFM1";
+
+/// Summaries kept on disk, about 2 KiB each; the least recently used go first past this
+const CACHE_LIMIT: usize = 2000;
+
+/// Compact min/max peak summary of a whole track, normalized to the loudest peak
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    /// Quantized (min, max) sample values per bucket
+    peaks: Vec<(i8, i8)>,
+}
+
+impl Waveform {
+    /// Get the peak amplitude (0.0 to 1.0) over a fraction range of the track
+    fn amplitude(&self, start: f64, end: f64) -> f32 {
+        if self.peaks.is_empty() {
+            return 0.0;
+        }
+        let len = self.peaks.len();
+        let first = ((start * len as f64) as usize).min(len - 1);
+        let last = ((end * len as f64).ceil() as usize).clamp(first + 1, len);
+
+        self.peaks[first..last]
+            .iter()
+            .map(|(min, max)| i16::from(*min).abs().max(i16::from(*max).abs()))
+            .max()
+            .map(|peak| f32::from(peak) / 127.0)
+            .unwrap_or(0.0)
+    }
+
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        let rest = data.strip_prefix(CACHE_MAGIC)?;
+        let (count, rest) = rest.split_first_chunk::<4>()?;
+        let count = u32::from_le_bytes(*count) as usize;
+        if rest.len() != count * 2 {
+            return None;
+        }
+        let peaks = rest
+            .chunks_exact(2)
+            .map(|pair| (pair[0] as i8, pair[1] as i8))
+            .collect();
+        Some(Self { peaks })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(8 + self.peaks.len() * 2);
+        data.extend_from_slice(CACHE_MAGIC);
+        data.extend_from_slice(&(self.peaks.len() as u32).to_le_bytes());
+        for (min, max) in &self.peaks {
+            data.push(*min as u8);
+            data.push(*max as u8);
+        }
+        data
+    }
+}
+
+/// State of a track's summary in the store
+#[derive(Clone)]
+enum Entry {
+    Pending,
+    Ready(Arc<Waveform>),
+    Failed,
+}
+
+/// Computes waveform summaries on a background thread and caches them on disk
+pub struct WaveformStore {
+    entries: Arc<Mutex<HashMap<PathBuf, Entry>>>,
+    requests: Sender<PathBuf>,
+}
+
+impl WaveformStore {
+    pub fn new() -> Self {
+        let entries: Arc<Mutex<HashMap<PathBuf, Entry>>> = Arc::default();
+        let (requests, receiver) = mpsc::channel::<PathBuf>();
+
+        let worker_entries = entries.clone();
+        thread::spawn(move || {
+            let cache_dir = dirs::cache_dir().map(|dir| dir.join("music_player").join("waveforms"));
+            for path in receiver {
+                let entry = match load_or_compute(&path, cache_dir.as_deref()) {
+                    Ok(waveform) => Entry::Ready(Arc::new(waveform)),
+                    Err(e) => {
+                        warn!("Failed to compute waveform for {}: {}", path.display(), e);
+                        Entry::Failed
+                    }
+                };
+                if let Ok(mut entries) = worker_entries.lock() {
+                    entries.insert(path, entry);
+                }
+            }
+        });
+
+        Self { entries, requests }
+    }
+
+    /// Get a track's summary, queueing it for computation if it isn't known yet
+    pub fn get(&self, path: &Path) -> Option<Arc<Waveform>> {
+        let mut entries = self.entries.lock().ok()?;
+        match entries.get(path) {
+            Some(Entry::Ready(waveform)) => Some(waveform.clone()),
+            Some(Entry::Pending | Entry::Failed) => None,
+            None => {
+                entries.insert(path.to_path_buf(), Entry::Pending);
+                let _ = self.requests.send(path.to_path_buf());
+                None
+            }
+        }
+    }
+}
+
+/// Read a summary from the disk cache, or decode the track and cache the result
+fn load_or_compute(path: &Path, cache_dir: Option<&Path>) -> Result<Waveform> {
+    let cache_file =
+        cache_dir.and_then(|dir| cache_key(path).map(|key| dir.join(format!("{}.wf", key))));
+
+    if let Some(file) = &cache_file {
+        match fs::read(file) {
+            Ok(data) => {
+                if let Some(waveform) = Waveform::from_bytes(&data) {
+                    // Marks the summary as recently used, so pruning keeps it
+                    let _ = File::options()
+                        .write(true)
+                        .open(file)
+                        .and_then(|f| f.set_modified(SystemTime::now()));
+                    return Ok(waveform);
+                }
+                warn!("Ignoring corrupt waveform cache: {}", file.display());
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to read waveform cache {}: {}", file.display(), e),
+        }
+    }
+
+    let waveform = compute(path)?;
+    info!("Computed waveform for {}", path.display());
+
+    if let Some(file) = &cache_file {
+        let written = file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(file, waveform.to_bytes()));
+        if let Err(e) = written {
+            warn!("Failed to write waveform cache {}: {}", file.display(), e);
+        }
+    }
+    if let Some(dir) = cache_dir
+        && let Err(e) = prune_cache(dir, CACHE_LIMIT)
+    {
+        warn!("Failed to prune waveform cache {}: {}", dir.display(), e);
+    }
+
+    Ok(waveform)
+}
+
+/// Delete the least recently used summaries until at most `limit` are left
+fn prune_cache(dir: &Path, limit: usize) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "wf") {
+            files.push((fs::metadata(&path)?.modified()?, path));
+        }
+    }
+    if files.len() <= limit {
+        return Ok(());
+    }
+
+    files.sort();
+    let excess = files.len() - limit;
+    for (_, path) in &files[..excess] {
+        match fs::remove_file(path) {
+            // Another player instance may have pruned it already
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    info!("Pruned {} waveforms from the cache", excess);
+    Ok(())
+}
+
+/// Cache file name derived from the path, size and modification time, so edits invalidate it
+pub fn cache_key(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+    // FNV-1a, which unlike `DefaultHasher` is stable across builds
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+    feed(path.to_string_lossy().as_bytes());
+    feed(&metadata.len().to_le_bytes());
+    feed(&modified.as_nanos().to_le_bytes());
+
+    Some(format!("{:016x}", hash))
+}
+
+/// Decode a whole track and reduce it to a min/max summary
+fn compute(path: &Path) -> Result<Waveform> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+    let track = format.default_track().context("No audio track")?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut chunks: Vec<(f32, f32)> = Vec::new();
+    let mut current = (0.0f32, 0.0f32);
+    let mut frames_in_chunk = 0;
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Skip over corrupt packets rather than giving up on the track
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let buf = match &mut sample_buf {
+            Some(buf) if buf.capacity() >= decoded.capacity() * channels => buf,
+            _ => sample_buf.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buf.copy_interleaved_ref(decoded);
+
+        for frame in buf.samples().chunks(channels) {
+            for sample in frame {
+                current.0 = current.0.min(*sample);
+                current.1 = current.1.max(*sample);
+            }
+            frames_in_chunk += 1;
+            if frames_in_chunk == CHUNK_FRAMES {
+                chunks.push(current);
+                current = (0.0, 0.0);
+                frames_in_chunk = 0;
+            }
+        }
+    }
+    if frames_in_chunk > 0 {
+        chunks.push(current);
+    }
+
+    if chunks.is_empty() {
+        bail!("No audio decoded");
+    }
+
+    // Reduce to a fixed number of buckets and scale so the loudest peak fills the bar
+    let buckets = WAVEFORM_BUCKETS.min(chunks.len());
+    let reduced: Vec<(f32, f32)> = (0..buckets)
+        .map(|i| {
+            let start = i * chunks.len() / buckets;
+            let end = ((i + 1) * chunks.len() / buckets).max(start + 1);
+            chunks[start..end]
+                .iter()
+                .fold((0.0f32, 0.0f32), |acc, c| (acc.0.min(c.0), acc.1.max(c.1)))
+        })
+        .collect();
+    let loudest = reduced
+        .iter()
+        .map(|(min, max)| min.abs().max(max.abs()))
+        .fold(f32::EPSILON, f32::max);
+
+    let quantize = |v: f32| (v / loudest * 127.0).round().clamp(-127.0, 127.0) as i8;
+    let peaks = reduced
+        .into_iter()
+        .map(|(min, max)| (quantize(min), quantize(max)))
+        .collect();
+
+    Ok(Waveform { peaks })
+}
+
+/// Braille-dot waveform with the played portion styled differently
+pub struct WaveformBar<'a> {
+    waveform: &'a Waveform,
+    progress: f64,
+    played_style: Style,
+    unplayed_style: Style,
+}
+
+impl<'a> WaveformBar<'a> {
+    pub fn new(
+        waveform: &'a Waveform,
+        progress: f64,
+        played_style: Style,
+        unplayed_style: Style,
+    ) -> Self {
+        Self {
+            waveform,
+            progress,
+            played_style,
+            unplayed_style,
+        }
+    }
+}
+
+/// Braille dot bits for the left and right column, top row first
+const BRAILLE_LEFT: [u8; 4] = [0x01, 0x02, 0x04, 0x40];
+const BRAILLE_RIGHT: [u8; 4] = [0x08, 0x10, 0x20, 0x80];
+
+impl Widget for WaveformBar<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if area.width == 0 || area.height == 0 {
+            return;
+        }
+
+        // Each cell is two dots wide and four dots tall
+        let dot_columns = usize::from(area.width) * 2;
+        let dot_rows = usize::from(area.height) * 4;
+        let center = dot_rows as f32 / 2.0;
+
+        let mut cells = vec![0u8; usize::from(area.width) * usize::from(area.height)];
+        for column in 0..dot_columns {
+            let start = column as f64 / dot_columns as f64;
+            let end = (column + 1) as f64 / dot_columns as f64;
+            let amplitude = self.waveform.amplitude(start, end);
+
+            // Always draw at least the center line so silence is visible
+            let half = (amplitude * center).round().max(1.0);
+            let top = (center - half).max(0.0) as usize;
+            let bottom = ((center + half) as usize).min(dot_rows);
+
+            let bits = if column % 2 == 0 {
+                &BRAILLE_LEFT
+            } else {
+                &BRAILLE_RIGHT
+            };
+            for row in top..bottom {
+                let cell = (row / 4) * usize::from(area.width) + column / 2;
+                cells[cell] |= bits[row % 4];
+            }
+        }
+
+        let played_columns = (self.progress.clamp(0.0, 1.0) * f64::from(area.width)).round() as u16;
+        for y in 0..area.height {
+            for x in 0..area.width {
+                let bits = cells[usize::from(y) * usize::from(area.width) + usize::from(x)];
+                let symbol = char::from_u32(0x2800 + u32::from(bits)).unwrap_or(' ');
+                let style = if x < played_columns {
+                    self.played_style
+                } else {
+                    self.unplayed_style
+                };
+                buf[(area.x + x, area.y + y)]
+                    .set_char(symbol)
+                    .set_style(style);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn pruning_keeps_the_most_recently_used_summaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("a", 40), ("b", 10), ("c", 30), ("d", 20)] {
+            let file = File::create(dir.path().join(format!("{}.wf", name))).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        fs::write(dir.path().join("notes.txt"), "kept").unwrap();
+
+        prune_cache(dir.path(), 4).unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 5);
+
+        prune_cache(dir.path(), 2).unwrap();
+        let mut names: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["b.wf", "d.wf", "notes.txt"]);
+    }
+
+    #[test]
+    fn cache_hits_count_as_uses() {
+        let dir = tempfile::tempdir().unwrap();
+        let track = dir.path().join("track.flac");
+        fs::write(&track, b"not audio").unwrap();
+
+        let waveform = Waveform {
+            peaks: vec![(-64, 127), (-1, 1)],
+        };
+        let cached = dir
+            .path()
+            .join(format!("{}.wf", cache_key(&track).unwrap()));
+        fs::write(&cached, waveform.to_bytes()).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&cached)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        // The track can't be decoded, so the summary can only have come from the cache
+        let loaded = load_or_compute(&track, Some(dir.path())).unwrap();
+        assert_eq!(loaded.peaks, waveform.peaks);
+        assert!(fs::metadata(&cached).unwrap().modified().unwrap() > old);
+    }
+}